@@ -5,9 +5,7 @@ use thiserror::Error;
 pub enum Error {
     #[error("invalid count of memory, must be 1")]
     InvalidMemoryCount,
-    #[error("invalid count of table, must be 1")]
-    InvalidTableCount,
-    #[error("invalid elemtype of table, must be funcref, got {0}")]
+    #[error("invalid elemtype of table, must be funcref or externref, got {0}")]
     InvalidElmType(u8),
     #[error("invalid init expr instruction in expressions, got {0}")]
     InvalidInitExprOpcode(u8),
@@ -17,4 +15,28 @@ pub enum Error {
     InvalidImportKind(u8),
     #[error("invalid opecode: {0:x}")]
     InvalidOpcode(u8),
+    #[error("invalid value type: {0:x}")]
+    InvalidValueType(u8),
+    #[error("malformed mutability: {0:x}")]
+    InvalidMutability(u8),
+    #[error("malformed section id: {0:x}")]
+    InvalidSectionId(u8),
+    #[error("section size mismatch")]
+    SectionSizeMismatch,
+    #[error("too many locals")]
+    TooManyLocals,
+    #[error("zero byte expected")]
+    ZeroByteExpected,
+    #[error("invalid block type: {0}")]
+    InvalidBlockType(i64),
+    #[error("unexpected end of section or function")]
+    UnexpectedEof,
+    #[error("invalid 0xFC prefixed opcode: {0}")]
+    InvalidMiscOpcode(u32),
+    #[error("invalid data segment flag: {0}")]
+    InvalidDataSegmentFlag(u32),
+    #[error("invalid element segment flag: {0}")]
+    InvalidElementSegmentFlag(u32),
+    #[error("invalid elemkind of element segment, must be 0x00, got {0}")]
+    InvalidElmKind(u8),
 }
@@ -1,4 +1,4 @@
-use super::types::Block;
+use super::types::{Block, ValueType};
 use num_derive::FromPrimitive;
 
 #[derive(Debug, PartialEq, Clone)]
@@ -26,6 +26,8 @@ pub enum Opcode {
     LocalTee = 0x22,
     GlobalGet = 0x23,
     GlobalSet = 0x24,
+    TableGet = 0x25,
+    TableSet = 0x26,
     Call = 0x10,
     CallIndirect = 0x11,
     I32Const = 0x41,
@@ -162,8 +164,12 @@ pub enum Opcode {
     I64Store32 = 0x3E,
     MemorySize = 0x3F,
     MemoryGrow = 0x40,
-    MmeoryCopyOrFill = 0xFC,
+    Misc = 0xFC,
     Select = 0x1B,
+    SelectType = 0x1C,
+    RefNull = 0xD0,
+    RefIsNull = 0xD1,
+    RefFunc = 0xD2,
     Drop = 0x1A,
     I32TruncF32S = 0xA8,
     I32TruncF32U = 0xA9,
@@ -191,7 +197,7 @@ pub enum Opcode {
     F64ReinterpretI64 = 0xBF,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Instruction {
     Unreachable,
     Nop,
@@ -208,6 +214,11 @@ pub enum Instruction {
     LocalTee(u32),
     GlobalSet(u32),
     GlobalGet(u32),
+    TableGet(u32),
+    TableSet(u32),
+    TableSize(u32),
+    TableGrow(u32),
+    TableFill(u32),
     Call(u32),
     CallIndirect((u32, u32)),
     I32Const(i32),
@@ -343,10 +354,19 @@ pub enum Instruction {
     I64Store16(MemoryArg),
     I64Store32(MemoryArg),
     Select,
+    SelectType(Vec<ValueType>),
+    RefNull(ValueType),
+    RefIsNull,
+    RefFunc(u32),
     MemoryGrow(u32),
     MemorySize,
     MemoryCopy(u32, u32),
     MemoryFill(u32),
+    MemoryInit(u32),
+    DataDrop(u32),
+    TableInit(u32, u32), // elemidx, tableidx
+    ElemDrop(u32),
+    TableCopy(u32, u32), // dst tableidx, src tableidx
     Drop,
     I32TruncF32S,
     I32TruncF32U,
@@ -358,6 +378,14 @@ pub enum Instruction {
     I64TruncF32U,
     I64TruncF64S,
     I64TruncF64U,
+    I32TruncSatF32S,
+    I32TruncSatF32U,
+    I32TruncSatF64S,
+    I32TruncSatF64U,
+    I64TruncSatF32S,
+    I64TruncSatF32U,
+    I64TruncSatF64S,
+    I64TruncSatF64U,
     F32ConvertI32S,
     F32ConvertI32U,
     F32ConvertI64S,
@@ -3,3 +3,4 @@ pub(crate) mod instruction;
 pub(crate) mod module;
 pub(crate) mod section;
 pub(crate) mod types;
+pub(crate) mod validate;
@@ -1,5 +1,5 @@
-use super::{section::*, types::*};
-use anyhow::{bail, Result};
+use super::{error::Error, section::*, types::*};
+use anyhow::{bail, Context as _, Result};
 use num_traits::FromPrimitive;
 use std::io;
 use std::io::{BufRead, BufReader, Read};
@@ -19,6 +19,7 @@ pub struct Module {
     pub start_section: Option<u32>,
     pub element_section: Option<Vec<Element>>,
     pub data: Option<Vec<Data>>,
+    pub data_count: Option<u32>,
     pub code_section: Option<Vec<FunctionBody>>,
 }
 
@@ -36,6 +37,7 @@ impl Module {
             Section::Code(section) => self.code_section = Some(section),
             Section::Element(section) => self.element_section = Some(section),
             Section::Data(section) => self.data = Some(section),
+            Section::DataCount(count) => self.data_count = Some(count),
             Section::Start(section) => self.start_section = Some(section),
         };
     }
@@ -83,7 +85,9 @@ impl<R: io::Read> Decoder<R> {
     }
 
     pub fn decode_section_header(&mut self) -> Result<(SectionID, usize)> {
-        let id: SectionID = FromPrimitive::from_u8(self.byte()?).unwrap();
+        let byte = self.byte()?;
+        let id: SectionID =
+            FromPrimitive::from_u8(byte).with_context(|| Error::InvalidSectionId(byte))?;
         let size = self.u32()? as usize;
         Ok((id, size))
     }
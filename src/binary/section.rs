@@ -22,6 +22,7 @@ pub enum SectionID {
     Element = 0x09,
     Code = 0x0a,
     Data = 0x0b,
+    DataCount = 0x0c,
 }
 
 impl From<u8> for SectionID {
@@ -39,6 +40,7 @@ impl From<u8> for SectionID {
             0x09 => SectionID::Element,
             0x0b => SectionID::Data,
             0x0a => SectionID::Code,
+            0x0c => SectionID::DataCount,
             _ => panic!("unknown section id: {}", id),
         }
     }
@@ -61,6 +63,17 @@ impl<'a> SectionReader<'a> {
         Ok(buf[0])
     }
 
+    fn peek(&mut self) -> Result<u8> {
+        let buf = self.buf.fill_buf()?;
+        let byte = buf.first().with_context(|| Error::UnexpectedEof)?;
+        Ok(*byte)
+    }
+
+    fn s33(&mut self) -> Result<i64> {
+        let num = leb128::read::signed(&mut self.buf)?;
+        Ok(num)
+    }
+
     fn u32(&mut self) -> Result<u32> {
         let num = leb128::read::unsigned(&mut self.buf)?;
         let num = u32::try_from(num)?;
@@ -123,6 +136,7 @@ pub enum Section {
     Start(u32),
     Element(Vec<Element>),
     Data(Vec<Data>),
+    DataCount(u32),
     Code(Vec<FunctionBody>),
 }
 
@@ -141,7 +155,11 @@ pub fn decode(id: SectionID, data: &[u8]) -> Result<Section> {
         SectionID::Element => decode_element_section(&mut reader)?,
         SectionID::Data => decode_data_section(&mut reader)?,
         SectionID::Code => decode_code_section(&mut reader)?,
+        SectionID::DataCount => Section::DataCount(reader.u32()?),
     };
+    if reader.is_end()? {
+        bail!(Error::SectionSizeMismatch);
+    }
     Ok(section)
 }
 
@@ -152,39 +170,88 @@ fn decode_custom_section(reader: &mut SectionReader) -> Result<Section> {
     Ok(Section::Custom(Custom { name, data }))
 }
 
+// https://webassembly.github.io/spec/core/binary/modules.html#data-section
 fn decode_data_section(reader: &mut SectionReader) -> Result<Section> {
     let mut data = vec![];
     let count = reader.u32()?;
     for _ in 0..count {
-        let memory_index = reader.u32()?;
-        let offset = decode_expr(reader)?;
+        let mode = match reader.u32()? {
+            0 => DataMode::Active {
+                memory_index: 0,
+                offset: decode_expr(reader)?,
+            },
+            1 => DataMode::Passive,
+            2 => DataMode::Active {
+                memory_index: reader.u32()?,
+                offset: decode_expr(reader)?,
+            },
+            flag => bail!(Error::InvalidDataSegmentFlag(flag)),
+        };
         let size = reader.u32()?;
         let init = reader.bytes(size as usize)?;
-        data.push(Data {
-            memory_index,
-            offset,
-            init,
-        });
+        data.push(Data { mode, init });
     }
 
     Ok(Section::Data(data))
 }
 
+// https://webassembly.github.io/spec/core/binary/modules.html#element-section
 fn decode_element_section(reader: &mut SectionReader) -> Result<Section> {
     let mut elements = vec![];
     let count = reader.u32()?;
     for _ in 0..count {
-        let mut init = vec![];
-        let table_index = reader.u32()?;
-        let offset = decode_expr(reader)?;
-        let count = reader.u32()?;
-        for _ in 0..count {
-            let index = reader.u32()?;
-            init.push(index);
+        // bit 0: passive or declarative, bit 1: explicit table index or declarative,
+        // bit 2: elements are expressions instead of function indices
+        let flag = reader.u32()?;
+        if flag > 7 {
+            bail!(Error::InvalidElementSegmentFlag(flag));
         }
+
+        let mode = if flag & 0b001 == 0 {
+            let table_index = if flag & 0b010 == 0 { 0 } else { reader.u32()? };
+            let offset = decode_expr(reader)?;
+            ElementMode::Active {
+                table_index,
+                offset,
+            }
+        } else if flag & 0b010 == 0 {
+            ElementMode::Passive
+        } else {
+            ElementMode::Declarative
+        };
+
+        // the flag 0 and 4 have no elemkind or reftype, it's always funcref
+        let has_type = flag & 0b011 != 0;
+        let mut init = vec![];
+        let elem_type = if flag & 0b100 == 0 {
+            if has_type {
+                let kind = reader.byte()?;
+                if kind != 0x00 {
+                    bail!(Error::InvalidElmKind(kind));
+                }
+            }
+            let count = reader.u32()?;
+            for _ in 0..count {
+                let index = reader.u32()?;
+                init.push(Expr::Value(ExprValue::RefFunc(index)));
+            }
+            ValueType::FuncRef
+        } else {
+            let elem_type = if has_type {
+                decode_ref_type(reader)?
+            } else {
+                ValueType::FuncRef
+            };
+            let count = reader.u32()?;
+            for _ in 0..count {
+                init.push(decode_expr(reader)?);
+            }
+            elem_type
+        };
+
         elements.push(Element {
-            table_index,
-            offset,
+            mode,
+            elem_type,
             init,
         });
     }
@@ -250,8 +317,9 @@ fn decode_global_type(reader: &mut SectionReader) -> Result<GlobalType> {
     let value_type = reader.byte()?;
     let mutability = reader.byte()?;
     let global_type = GlobalType {
-        value_type: value_type.into(),
-        mutability: Mutability::from_u8(mutability).unwrap(),
+        value_type: ValueType::try_from(value_type)?,
+        mutability: Mutability::from_u8(mutability)
+            .with_context(|| Error::InvalidMutability(mutability))?,
     };
     Ok(global_type)
 }
@@ -291,6 +359,8 @@ fn decode_expr_value(reader: &mut SectionReader) -> Result<ExprValue> {
             let value = reader.f64()?;
             ExprValue::F64(value)
         }
+        Opcode::RefNull => ExprValue::RefNull(decode_ref_type(reader)?),
+        Opcode::RefFunc => ExprValue::RefFunc(reader.u32()?),
         _ => bail!(Error::InvalidInitExprOpcode(byte)),
     };
 
@@ -326,6 +396,8 @@ fn decode_expr(reader: &mut SectionReader) -> Result<Expr> {
             let value = reader.u32()?;
             Expr::GlobalIndex(value as usize)
         }
+        Opcode::RefNull => Expr::Value(ExprValue::RefNull(decode_ref_type(reader)?)),
+        Opcode::RefFunc => Expr::Value(ExprValue::RefFunc(reader.u32()?)),
         _ => bail!(Error::InvalidInitExprOpcode(byte)),
     };
 
@@ -337,24 +409,23 @@ fn decode_expr(reader: &mut SectionReader) -> Result<Expr> {
     Ok(value)
 }
 
-fn decode_table(reader: &mut SectionReader) -> Result<Table> {
-    let elem_type = reader.byte()?;
-    if elem_type != 0x70 {
-        bail!(Error::InvalidElmType(elem_type));
+fn decode_ref_type(reader: &mut SectionReader) -> Result<ValueType> {
+    let byte = reader.byte()?;
+    match ValueType::try_from(byte) {
+        Ok(value_type) if value_type.is_ref() => Ok(value_type),
+        _ => bail!(Error::InvalidElmType(byte)),
     }
+}
+
+fn decode_table(reader: &mut SectionReader) -> Result<Table> {
+    let elem_type = decode_ref_type(reader)?;
     let limits = decode_limits(reader)?;
-    let table = Table {
-        elem_type: ElemType::from_u8(elem_type).unwrap(),
-        limits,
-    };
+    let table = Table { elem_type, limits };
     Ok(table)
 }
 
 fn decode_table_secttion(reader: &mut SectionReader) -> Result<Section> {
     let count = reader.u32()?;
-    if count != 1 {
-        bail!(Error::InvalidTableCount);
-    }
     let mut tables = vec![];
     for _ in 0..count {
         let table = decode_table(reader)?;
@@ -409,14 +480,14 @@ fn decode_type_section(reader: &mut SectionReader) -> Result<Section> {
         // read each params
         let size = reader.u32()?;
         for _ in 0..size {
-            let value_type: ValueType = reader.byte()?.into();
+            let value_type = ValueType::try_from(reader.byte()?)?;
             func.params.push(value_type);
         }
 
         // read each results
         let size = reader.u32()?;
         for _ in 0..size {
-            let value_type: ValueType = reader.byte()?.into();
+            let value_type = ValueType::try_from(reader.byte()?)?;
             func.results.push(value_type);
         }
 
@@ -474,9 +545,14 @@ fn decode_function_body(reader: &mut SectionReader) -> Result<FunctionBody> {
 
     // count of local variable declarations
     let count = reader.u32()?;
+    let mut total: u64 = 0;
     for _ in 0..count {
         let type_count = reader.u32()?;
-        let value_type: ValueType = reader.byte()?.into();
+        total += type_count as u64;
+        if total > u32::MAX as u64 {
+            bail!(Error::TooManyLocals);
+        }
+        let value_type = ValueType::try_from(reader.byte()?)?;
         function_body.locals.push(FunctionLocal {
             type_count,
             value_type,
@@ -492,12 +568,18 @@ fn decode_function_body(reader: &mut SectionReader) -> Result<FunctionBody> {
 }
 
 fn decode_block_type(reader: &mut SectionReader) -> Result<BlockType> {
-    let byte = reader.byte()?;
+    let byte = reader.peek()?;
     let block_type = if byte == 0x40 {
+        reader.byte()?;
         BlockType::Empty
-    } else {
-        let value_type = byte.into();
+    } else if let Ok(value_type) = ValueType::try_from(byte) {
+        reader.byte()?;
         BlockType::Value(vec![value_type])
+    } else {
+        // NOTE: type index is encoded as a positive signed integer with 33 bits
+        let idx = reader.s33()?;
+        let idx = u32::try_from(idx).with_context(|| Error::InvalidBlockType(idx))?;
+        BlockType::TypeIdx(idx)
     };
     Ok(block_type)
 }
@@ -547,6 +629,8 @@ fn decode_instruction(reader: &mut SectionReader) -> Result<Instruction> {
         Opcode::LocalTee => Instruction::LocalTee(reader.u32()?),
         Opcode::GlobalSet => Instruction::GlobalSet(reader.u32()?),
         Opcode::GlobalGet => Instruction::GlobalGet(reader.u32()?),
+        Opcode::TableGet => Instruction::TableGet(reader.u32()?),
+        Opcode::TableSet => Instruction::TableSet(reader.u32()?),
         Opcode::I32Sub => Instruction::I32Sub,
         Opcode::I32Add => Instruction::I32Add,
         Opcode::I32Mul => Instruction::I32Mul,
@@ -691,30 +775,66 @@ fn decode_instruction(reader: &mut SectionReader) -> Result<Instruction> {
         Opcode::I64Store8 => Instruction::I64Store8(read_memory_arg(reader)?),
         Opcode::I64Store16 => Instruction::I64Store16(read_memory_arg(reader)?),
         Opcode::I64Store32 => Instruction::I64Store32(read_memory_arg(reader)?),
-        Opcode::MemoryGrow => Instruction::MemoryGrow(reader.u32()?),
+        Opcode::MemoryGrow => Instruction::MemoryGrow(read_zero_byte(reader)?),
         Opcode::MemorySize => {
             // NOTE: memory index is always 0 now
-            let _ = reader.byte();
+            read_zero_byte(reader)?;
             Instruction::MemorySize
         }
-        // TODO: improve instruction decoding because opecode maybe tow bytes in the version 2
-        // this instruction is defined in the version2 of the spec
-        Opcode::MmeoryCopyOrFill => {
-            let kind = reader.byte()?;
-            match kind {
-                0x0A => {
-                    let src_memidx = reader.u32()?;
-                    let dest_memidx = reader.u32()?;
-                    Instruction::MemoryCopy(src_memidx, dest_memidx)
-                }
-                0x0B => {
-                    let memidx = reader.u32()?;
-                    Instruction::MemoryFill(memidx)
-                }
-                _ => unreachable!(),
+        // https://webassembly.github.io/spec/core/binary/instructions.html#memory-instructions
+        // the instructions with 0xFC prefix are followed by u32 sub opcode
+        Opcode::Misc => match reader.u32()? {
+            0x00 => Instruction::I32TruncSatF32S,
+            0x01 => Instruction::I32TruncSatF32U,
+            0x02 => Instruction::I32TruncSatF64S,
+            0x03 => Instruction::I32TruncSatF64U,
+            0x04 => Instruction::I64TruncSatF32S,
+            0x05 => Instruction::I64TruncSatF32U,
+            0x06 => Instruction::I64TruncSatF64S,
+            0x07 => Instruction::I64TruncSatF64U,
+            0x08 => {
+                let dataidx = reader.u32()?;
+                read_zero_byte(reader)?;
+                Instruction::MemoryInit(dataidx)
             }
-        }
+            0x09 => Instruction::DataDrop(reader.u32()?),
+            0x0A => {
+                let src_memidx = reader.u32()?;
+                let dest_memidx = reader.u32()?;
+                Instruction::MemoryCopy(src_memidx, dest_memidx)
+            }
+            0x0B => {
+                let memidx = reader.u32()?;
+                Instruction::MemoryFill(memidx)
+            }
+            0x0C => {
+                let elemidx = reader.u32()?;
+                let tableidx = reader.u32()?;
+                Instruction::TableInit(elemidx, tableidx)
+            }
+            0x0D => Instruction::ElemDrop(reader.u32()?),
+            0x0E => {
+                let dst_tableidx = reader.u32()?;
+                let src_tableidx = reader.u32()?;
+                Instruction::TableCopy(dst_tableidx, src_tableidx)
+            }
+            0x0F => Instruction::TableGrow(reader.u32()?),
+            0x10 => Instruction::TableSize(reader.u32()?),
+            0x11 => Instruction::TableFill(reader.u32()?),
+            op => bail!(Error::InvalidMiscOpcode(op)),
+        },
         Opcode::Select => Instruction::Select,
+        Opcode::SelectType => {
+            let count = reader.u32()?;
+            let mut value_types = vec![];
+            for _ in 0..count {
+                value_types.push(ValueType::try_from(reader.byte()?)?);
+            }
+            Instruction::SelectType(value_types)
+        }
+        Opcode::RefNull => Instruction::RefNull(decode_ref_type(reader)?),
+        Opcode::RefIsNull => Instruction::RefIsNull,
+        Opcode::RefFunc => Instruction::RefFunc(reader.u32()?),
         Opcode::I32TruncF32S => Instruction::I32TruncF32S,
         Opcode::I32TruncF32U => Instruction::I32TruncF32U,
         Opcode::I32TruncF64S => Instruction::I32TruncF64S,
@@ -743,6 +863,15 @@ fn decode_instruction(reader: &mut SectionReader) -> Result<Instruction> {
     Ok(inst)
 }
 
+// reserved byte for the memory index, which must be 0 in the version 1
+fn read_zero_byte(reader: &mut SectionReader) -> Result<u32> {
+    let byte = reader.byte()?;
+    if byte != 0x00 {
+        bail!(Error::ZeroByteExpected);
+    }
+    Ok(byte as u32)
+}
+
 fn read_memory_arg(reader: &mut SectionReader) -> Result<MemoryArg> {
     let arg = MemoryArg {
         align: reader.u32()?,
@@ -1,23 +1,37 @@
+use super::error::Error;
 use super::instruction::Instruction;
 use num_derive::FromPrimitive;
 
 // https://webassembly.github.io/spec/core/binary/types.html#value-types
 #[derive(Debug, Clone, PartialEq)]
 pub enum ValueType {
-    I32, // 0x7F
-    I64, // 0x7E
-    F32, // 0x7D
-    F64, // 0x7C
+    I32,       // 0x7F
+    I64,       // 0x7E
+    F32,       // 0x7D
+    F64,       // 0x7C
+    FuncRef,   // 0x70
+    ExternRef, // 0x6F
+}
+
+impl ValueType {
+    // https://webassembly.github.io/spec/core/syntax/types.html#reference-types
+    pub fn is_ref(&self) -> bool {
+        matches!(self, Self::FuncRef | Self::ExternRef)
+    }
 }
 
-impl From<u8> for ValueType {
-    fn from(value_type: u8) -> Self {
+impl TryFrom<u8> for ValueType {
+    type Error = Error;
+
+    fn try_from(value_type: u8) -> Result<Self, Self::Error> {
         match value_type {
-            0x7F => Self::I32,
-            0x7E => Self::I64,
-            0x7D => Self::F32,
-            0x7C => Self::F64,
-            _ => panic!("Invalid value type: {:X}", value_type),
+            0x7F => Ok(Self::I32),
+            0x7E => Ok(Self::I64),
+            0x7D => Ok(Self::F32),
+            0x7C => Ok(Self::F64),
+            0x70 => Ok(Self::FuncRef),
+            0x6F => Ok(Self::ExternRef),
+            _ => Err(Error::InvalidValueType(value_type)),
         }
     }
 }
@@ -57,14 +71,9 @@ pub struct Export {
     pub desc: ExportDesc,
 }
 
-#[derive(Debug, PartialEq, FromPrimitive)]
-pub enum ElemType {
-    FuncRef = 0x70,
-}
-
 #[derive(Debug, PartialEq)]
 pub struct Table {
-    pub elem_type: ElemType,
+    pub elem_type: ValueType, // funcref or externref
     pub limits: Limits,
 }
 
@@ -97,6 +106,8 @@ pub enum ExprValue {
     I64(i64),
     F32(f32),
     F64(f64),
+    RefNull(ValueType),
+    RefFunc(u32),
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -143,17 +154,34 @@ pub struct Import {
     pub kind: ImportKind,
 }
 
+// https://webassembly.github.io/spec/core/syntax/modules.html#element-segments
+#[derive(Debug, PartialEq)]
+pub enum ElementMode {
+    Passive,
+    Active {
+        table_index: u32,
+        offset: Expr, // offset in table
+    },
+    Declarative,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Element {
-    pub table_index: u32,
-    pub offset: Expr,   // offset in table
-    pub init: Vec<u32>, // index of function
+    pub mode: ElementMode,
+    pub elem_type: ValueType, // funcref or externref
+    pub init: Vec<Expr>,      // function indices are decoded as ref.func
+}
+
+// https://webassembly.github.io/spec/core/syntax/modules.html#data-segments
+#[derive(Debug, PartialEq)]
+pub enum DataMode {
+    Passive,
+    Active { memory_index: u32, offset: Expr },
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Data {
-    pub memory_index: u32,
-    pub offset: Expr,
+    pub mode: DataMode,
     pub init: Vec<u8>,
 }
 
@@ -163,18 +191,24 @@ pub struct Custom {
     pub data: Vec<u8>,
 }
 
-// https://www.w3.org/TR/wasm-core-1/#binary-blocktype
+// https://webassembly.github.io/spec/core/binary/instructions.html#binary-blocktype
 #[derive(Debug, Clone, PartialEq)]
 pub enum BlockType {
     Empty,
     Value(Vec<ValueType>), // only one value type is allowed now
+    TypeIdx(u32),          // index of the function type
 }
 
 impl BlockType {
-    pub fn result_count(&self) -> usize {
+    // returns the params and results of the block,
+    // or None if the type index is not found in the function types.
+    pub fn signature(&self, func_types: &[FuncType]) -> Option<(Vec<ValueType>, Vec<ValueType>)> {
         match self {
-            Self::Empty => 0,
-            Self::Value(value_types) => value_types.len(),
+            Self::Empty => Some((vec![], vec![])),
+            Self::Value(value_types) => Some((vec![], value_types.clone())),
+            Self::TypeIdx(idx) => func_types
+                .get(*idx as usize)
+                .map(|func_type| (func_type.params.clone(), func_type.results.clone())),
         }
     }
 }
@@ -0,0 +1,1286 @@
+// https://webassembly.github.io/spec/core/valid/index.html
+// https://webassembly.github.io/spec/core/appendix/algorithm.html
+use super::instruction::{Instruction, MemoryArg};
+use super::module::Module;
+use super::types::{
+    BlockType, DataMode, ElementMode, ExportDesc, Expr, ExprValue, FuncType, FunctionLocal,
+    ImportKind, Limits, Mutability, ValueType,
+};
+use std::collections::HashSet;
+use std::fmt::Display;
+use thiserror::Error;
+
+// https://webassembly.github.io/spec/core/valid/types.html#memory-types
+const MAX_MEMORY_PAGES: u32 = 65536;
+// https://webassembly.github.io/spec/core/valid/types.html#table-types
+const MAX_TABLE_SIZE: u32 = u32::MAX;
+// same as the limit of wasmparser, the locals are allocated for each call
+const MAX_LOCALS: u64 = 50_000;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ValidationErrorKind {
+    #[error("type mismatch")]
+    TypeMismatch,
+    #[error("unknown local {0}")]
+    UnknownLocal(u32),
+    #[error("unknown global {0}")]
+    UnknownGlobal(u32),
+    #[error("unknown function {0}")]
+    UnknownFunction(u32),
+    #[error("unknown table {0}")]
+    UnknownTable(u32),
+    #[error("unknown memory {0}")]
+    UnknownMemory(u32),
+    #[error("unknown type {0}")]
+    UnknownType(u32),
+    #[error("unknown label {0}")]
+    UnknownLabel(u32),
+    #[error("global is immutable")]
+    ImmutableGlobal,
+    #[error("alignment must not be larger than natural")]
+    InvalidAlignment,
+    #[error("constant expression required")]
+    ConstantExpressionRequired,
+    #[error("duplicate export name {0}")]
+    DuplicateExportName(String),
+    #[error("multiple memories")]
+    MultipleMemories,
+    #[error("memory size must be at most 65536 pages (4GiB)")]
+    MemorySizeTooLarge,
+    #[error("table size must be at most 2^32-1 entries")]
+    TableSizeTooLarge,
+    #[error("too many locals")]
+    TooManyLocals,
+    #[error("size minimum must not be greater than maximum")]
+    SizeMinimumGreaterThanMaximum,
+    #[error("start function")]
+    InvalidStartFunction,
+    #[error("function and code section have inconsistent lengths")]
+    InconsistentFunctionAndCodeLength,
+    #[error("else without matching if")]
+    UnexpectedElse,
+    #[error("unexpected end")]
+    UnexpectedEnd,
+    #[error("END opcode expected")]
+    MissingEnd,
+    #[error("invalid result arity")]
+    InvalidResultArity,
+    #[error("undeclared function reference")]
+    UndeclaredFunctionReference,
+    #[error("unknown elem segment {0}")]
+    UnknownElemSegment(u32),
+    #[error("unknown data segment {0}")]
+    UnknownDataSegment(u32),
+    #[error("data count section required")]
+    DataCountSectionRequired,
+    #[error("data count and data section have inconsistent lengths")]
+    InconsistentDataCountAndDataLength,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    pub kind: ValidationErrorKind,
+    pub func_idx: Option<u32>, // index of the function in the function index space
+    pub offset: Option<usize>, // index of the instruction in the function body
+}
+
+impl ValidationError {
+    fn new(kind: ValidationErrorKind) -> Self {
+        Self {
+            kind,
+            func_idx: None,
+            offset: None,
+        }
+    }
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.func_idx, self.offset) {
+            (Some(func_idx), Some(offset)) => {
+                write!(f, "{} (function {func_idx}, offset {offset})", self.kind)
+            }
+            (Some(func_idx), None) => write!(f, "{} (function {func_idx})", self.kind),
+            _ => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl From<ValidationErrorKind> for ValidationError {
+    fn from(kind: ValidationErrorKind) -> Self {
+        Self::new(kind)
+    }
+}
+
+type Result<T> = std::result::Result<T, ValidationError>;
+
+// https://webassembly.github.io/spec/core/valid/conventions.html#contexts
+#[derive(Default)]
+struct Context {
+    types: Vec<FuncType>,
+    funcs: Vec<u32>,        // type index of each function
+    tables: Vec<ValueType>, // element type of each table
+    memories: usize,
+    globals: Vec<(ValueType, bool)>, // value type and mutability
+    imported_globals: usize,
+    refs: HashSet<u32>,    // functions which can be referenced by ref.func
+    elems: Vec<ValueType>, // element type of each element segment
+    datas: Option<u32>,    // count of data segments declared in the data count section
+}
+
+impl Context {
+    fn func_type(&self, idx: u32) -> Result<&FuncType> {
+        self.types
+            .get(idx as usize)
+            .ok_or_else(|| ValidationErrorKind::UnknownType(idx).into())
+    }
+
+    fn func_type_of(&self, func_idx: u32) -> Result<&FuncType> {
+        let type_idx = self
+            .funcs
+            .get(func_idx as usize)
+            .ok_or(ValidationErrorKind::UnknownFunction(func_idx))?;
+        self.func_type(*type_idx)
+    }
+
+    fn table(&self, idx: u32) -> Result<ValueType> {
+        self.tables
+            .get(idx as usize)
+            .cloned()
+            .ok_or_else(|| ValidationErrorKind::UnknownTable(idx).into())
+    }
+
+    fn check_memory(&self, idx: u32) -> Result<()> {
+        if idx as usize >= self.memories {
+            return Err(ValidationErrorKind::UnknownMemory(idx).into());
+        }
+        Ok(())
+    }
+
+    fn elem(&self, idx: u32) -> Result<ValueType> {
+        self.elems
+            .get(idx as usize)
+            .cloned()
+            .ok_or_else(|| ValidationErrorKind::UnknownElemSegment(idx).into())
+    }
+
+    // https://webassembly.github.io/spec/core/binary/modules.html#data-count-section
+    fn check_data(&self, idx: u32) -> Result<()> {
+        let count = self
+            .datas
+            .ok_or(ValidationErrorKind::DataCountSectionRequired)?;
+        if idx >= count {
+            return Err(ValidationErrorKind::UnknownDataSegment(idx).into());
+        }
+        Ok(())
+    }
+}
+
+// https://webassembly.github.io/spec/core/valid/modules.html#valid-module
+pub fn validate(module: &Module) -> Result<()> {
+    let mut ctx = Context {
+        types: module.type_section.clone().unwrap_or_default(),
+        datas: module.data_count,
+        ..Context::default()
+    };
+
+    if let Some(ref imports) = module.import_section {
+        for import in imports {
+            match import.kind {
+                ImportKind::Func(type_idx) => {
+                    ctx.func_type(type_idx)?;
+                    ctx.funcs.push(type_idx);
+                }
+                ImportKind::Table(ref table) => {
+                    validate_table_limits(&table.limits)?;
+                    ctx.tables.push(table.elem_type.clone());
+                }
+                ImportKind::Memory(ref memory) => {
+                    validate_memory_limits(&memory.limits)?;
+                    ctx.memories += 1;
+                }
+                ImportKind::Global(ref global_type) => {
+                    ctx.globals.push((
+                        global_type.value_type.clone(),
+                        global_type.mutability == Mutability::Var,
+                    ));
+                    ctx.imported_globals += 1;
+                }
+            }
+        }
+    }
+
+    let functions = module.function_section.clone().unwrap_or_default();
+    for type_idx in functions.iter() {
+        ctx.func_type(*type_idx)?;
+        ctx.funcs.push(*type_idx);
+    }
+
+    if let Some(ref tables) = module.table_section {
+        for table in tables {
+            validate_table_limits(&table.limits)?;
+            ctx.tables.push(table.elem_type.clone());
+        }
+    }
+
+    if let Some(ref memories) = module.memory_section {
+        for memory in memories {
+            validate_memory_limits(&memory.limits)?;
+            ctx.memories += 1;
+        }
+    }
+    if ctx.memories > 1 {
+        return Err(ValidationErrorKind::MultipleMemories.into());
+    }
+
+    // NOTE: a global initializer can only refer to imported globals,
+    // so globals defined in this module are added after validating it.
+    if let Some(ref globals) = module.global_section {
+        for global in globals {
+            let value_type = &global.global_type.value_type;
+            if expr_value_type(&global.init_expr) != *value_type {
+                return Err(ValidationErrorKind::TypeMismatch.into());
+            }
+            if let ExprValue::RefFunc(idx) = global.init_expr {
+                ctx.func_type_of(idx)?;
+                ctx.refs.insert(idx);
+            }
+        }
+        for global in globals {
+            ctx.globals.push((
+                global.global_type.value_type.clone(),
+                global.global_type.mutability == Mutability::Var,
+            ));
+        }
+    }
+
+    if let Some(ref exports) = module.export_section {
+        let mut names = HashSet::new();
+        for export in exports {
+            if !names.insert(export.name.as_str()) {
+                return Err(ValidationErrorKind::DuplicateExportName(export.name.clone()).into());
+            }
+            match export.desc {
+                ExportDesc::Func(idx) => {
+                    ctx.func_type_of(idx)?;
+                    ctx.refs.insert(idx);
+                }
+                ExportDesc::Table(idx) => {
+                    ctx.table(idx)?;
+                }
+                ExportDesc::Memory(idx) => ctx.check_memory(idx)?,
+                ExportDesc::Global(idx) => {
+                    if idx as usize >= ctx.globals.len() {
+                        return Err(ValidationErrorKind::UnknownGlobal(idx).into());
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(idx) = module.start_section {
+        let func_type = ctx.func_type_of(idx)?;
+        if !func_type.params.is_empty() || !func_type.results.is_empty() {
+            return Err(ValidationErrorKind::InvalidStartFunction.into());
+        }
+    }
+
+    if let Some(ref elements) = module.element_section {
+        for elem in elements {
+            if let ElementMode::Active {
+                table_index,
+                ref offset,
+            } = elem.mode
+            {
+                if ctx.table(table_index)? != elem.elem_type {
+                    return Err(ValidationErrorKind::TypeMismatch.into());
+                }
+                validate_const_expr(&ctx, offset, &ValueType::I32)?;
+            }
+            for expr in elem.init.iter() {
+                validate_const_expr(&ctx, expr, &elem.elem_type)?;
+                if let Expr::Value(ExprValue::RefFunc(idx)) = expr {
+                    ctx.func_type_of(*idx)?;
+                    ctx.refs.insert(*idx);
+                }
+            }
+            ctx.elems.push(elem.elem_type.clone());
+        }
+    }
+
+    let data = module.data.as_deref().unwrap_or_default();
+    if module
+        .data_count
+        .is_some_and(|count| count as usize != data.len())
+    {
+        return Err(ValidationErrorKind::InconsistentDataCountAndDataLength.into());
+    }
+    for data in data {
+        if let DataMode::Active {
+            memory_index,
+            ref offset,
+        } = data.mode
+        {
+            ctx.check_memory(memory_index)?;
+            validate_const_expr(&ctx, offset, &ValueType::I32)?;
+        }
+    }
+
+    let bodies = module.code_section.as_deref().unwrap_or_default();
+    if bodies.len() != functions.len() {
+        return Err(ValidationErrorKind::InconsistentFunctionAndCodeLength.into());
+    }
+
+    let imported_funcs = ctx.funcs.len() - functions.len();
+    for (i, body) in bodies.iter().enumerate() {
+        let func_idx = (imported_funcs + i) as u32;
+        let func_type = ctx.func_type_of(func_idx)?;
+
+        let locals =
+            Locals::new(&func_type.params, &body.locals).map_err(|kind| ValidationError {
+                kind,
+                func_idx: Some(func_idx),
+                offset: None,
+            })?;
+
+        let mut validator = FuncValidator::new(&ctx, locals, func_type.results.clone());
+        validator
+            .validate(&body.code)
+            .map_err(|(offset, kind)| ValidationError {
+                kind,
+                func_idx: Some(func_idx),
+                offset,
+            })?;
+    }
+
+    Ok(())
+}
+
+fn validate_limits(limits: &Limits, range: u32, too_large: ValidationErrorKind) -> Result<()> {
+    if limits.min > range || limits.max.is_some_and(|max| max > range) {
+        return Err(too_large.into());
+    }
+    if limits.max.is_some_and(|max| limits.min > max) {
+        return Err(ValidationErrorKind::SizeMinimumGreaterThanMaximum.into());
+    }
+    Ok(())
+}
+
+fn validate_memory_limits(limits: &Limits) -> Result<()> {
+    validate_limits(
+        limits,
+        MAX_MEMORY_PAGES,
+        ValidationErrorKind::MemorySizeTooLarge,
+    )
+}
+
+fn validate_table_limits(limits: &Limits) -> Result<()> {
+    validate_limits(
+        limits,
+        MAX_TABLE_SIZE,
+        ValidationErrorKind::TableSizeTooLarge,
+    )
+}
+
+fn expr_value_type(value: &ExprValue) -> ValueType {
+    match value {
+        ExprValue::I32(_) => ValueType::I32,
+        ExprValue::I64(_) => ValueType::I64,
+        ExprValue::F32(_) => ValueType::F32,
+        ExprValue::F64(_) => ValueType::F64,
+        ExprValue::RefNull(value_type) => value_type.clone(),
+        ExprValue::RefFunc(_) => ValueType::FuncRef,
+    }
+}
+
+// https://webassembly.github.io/spec/core/valid/instructions.html#constant-expressions
+fn validate_const_expr(ctx: &Context, expr: &Expr, expected: &ValueType) -> Result<()> {
+    let value_type = match expr {
+        Expr::Value(value) => expr_value_type(value),
+        Expr::GlobalIndex(idx) => {
+            if *idx >= ctx.imported_globals {
+                return Err(ValidationErrorKind::UnknownGlobal(*idx as u32).into());
+            }
+            let (value_type, mutable) = &ctx.globals[*idx];
+            if *mutable {
+                return Err(ValidationErrorKind::ConstantExpressionRequired.into());
+            }
+            value_type.clone()
+        }
+    };
+    if value_type != *expected {
+        return Err(ValidationErrorKind::TypeMismatch.into());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum CtrlKind {
+    Block,
+    Loop,
+    If,
+    Else,
+}
+
+// https://webassembly.github.io/spec/core/appendix/algorithm.html#data-structures
+#[derive(Debug)]
+struct CtrlFrame {
+    kind: CtrlKind,
+    start_types: Vec<ValueType>,
+    end_types: Vec<ValueType>,
+    height: usize,
+    unreachable: bool,
+}
+
+impl CtrlFrame {
+    fn label_types(&self) -> &[ValueType] {
+        if self.kind == CtrlKind::Loop {
+            &self.start_types
+        } else {
+            &self.end_types
+        }
+    }
+}
+
+type FuncResult<T> = std::result::Result<T, ValidationErrorKind>;
+
+// the locals are kept as the runs of the same type,
+// so the declaration like `(local i32)` * u32::MAX is not expanded
+struct Locals(Vec<(u64, ValueType)>); // end index of the run and its type
+
+impl Locals {
+    fn new(params: &[ValueType], locals: &[FunctionLocal]) -> FuncResult<Self> {
+        let mut runs = Vec::with_capacity(params.len() + locals.len());
+        let mut end = 0;
+        let params = params.iter().map(|value_type| (1, value_type));
+        let locals = locals
+            .iter()
+            .map(|local| (local.type_count, &local.value_type));
+        for (count, value_type) in params.chain(locals) {
+            end += count as u64;
+            if end > MAX_LOCALS {
+                return Err(ValidationErrorKind::TooManyLocals);
+            }
+            runs.push((end, value_type.clone()));
+        }
+        Ok(Self(runs))
+    }
+
+    fn get(&self, idx: u32) -> Option<ValueType> {
+        let i = self.0.partition_point(|(end, _)| *end <= idx as u64);
+        self.0.get(i).map(|(_, value_type)| value_type.clone())
+    }
+}
+
+struct FuncValidator<'a> {
+    ctx: &'a Context,
+    locals: Locals,
+    vals: Vec<Option<ValueType>>, // None means the type is unknown
+    ctrls: Vec<CtrlFrame>,
+}
+
+impl<'a> FuncValidator<'a> {
+    fn new(ctx: &'a Context, locals: Locals, results: Vec<ValueType>) -> Self {
+        let frame = CtrlFrame {
+            kind: CtrlKind::Block,
+            start_types: vec![],
+            end_types: results,
+            height: 0,
+            unreachable: false,
+        };
+        Self {
+            ctx,
+            locals,
+            vals: vec![],
+            ctrls: vec![frame],
+        }
+    }
+
+    // returns the offset of the instruction where the validation failed
+    fn validate(
+        &mut self,
+        code: &[Instruction],
+    ) -> std::result::Result<(), (Option<usize>, ValidationErrorKind)> {
+        for (offset, inst) in code.iter().enumerate() {
+            if self.ctrls.is_empty() {
+                return Err((Some(offset), ValidationErrorKind::UnexpectedEnd));
+            }
+            self.instruction(inst)
+                .map_err(|kind| (Some(offset), kind))?;
+        }
+        if !self.ctrls.is_empty() {
+            return Err((None, ValidationErrorKind::MissingEnd));
+        }
+        Ok(())
+    }
+
+    fn push_val(&mut self, value_type: ValueType) {
+        self.vals.push(Some(value_type));
+    }
+
+    fn pop_val(&mut self) -> FuncResult<Option<ValueType>> {
+        let frame = self
+            .ctrls
+            .last()
+            .ok_or(ValidationErrorKind::UnexpectedEnd)?;
+        if self.vals.len() == frame.height {
+            if frame.unreachable {
+                return Ok(None);
+            }
+            return Err(ValidationErrorKind::TypeMismatch);
+        }
+        Ok(self.vals.pop().flatten())
+    }
+
+    fn pop_expect(&mut self, expect: &ValueType) -> FuncResult<()> {
+        match self.pop_val()? {
+            Some(actual) if actual != *expect => Err(ValidationErrorKind::TypeMismatch),
+            _ => Ok(()),
+        }
+    }
+
+    fn push_vals(&mut self, types: &[ValueType]) {
+        for value_type in types {
+            self.push_val(value_type.clone());
+        }
+    }
+
+    fn pop_vals(&mut self, types: &[ValueType]) -> FuncResult<Vec<Option<ValueType>>> {
+        let mut popped = Vec::with_capacity(types.len());
+        for value_type in types.iter().rev() {
+            self.pop_expect(value_type)?;
+            popped.push(Some(value_type.clone()));
+        }
+        popped.reverse();
+        Ok(popped)
+    }
+
+    fn push_ctrl(
+        &mut self,
+        kind: CtrlKind,
+        start_types: Vec<ValueType>,
+        end_types: Vec<ValueType>,
+    ) {
+        let frame = CtrlFrame {
+            kind,
+            height: self.vals.len(),
+            end_types,
+            unreachable: false,
+            start_types: start_types.clone(),
+        };
+        self.ctrls.push(frame);
+        self.push_vals(&start_types);
+    }
+
+    fn pop_ctrl(&mut self) -> FuncResult<CtrlFrame> {
+        let end_types = self
+            .ctrls
+            .last()
+            .ok_or(ValidationErrorKind::UnexpectedEnd)?
+            .end_types
+            .clone();
+        self.pop_vals(&end_types)?;
+        let frame = self.ctrls.pop().ok_or(ValidationErrorKind::UnexpectedEnd)?;
+        if self.vals.len() != frame.height {
+            return Err(ValidationErrorKind::TypeMismatch);
+        }
+        Ok(frame)
+    }
+
+    fn unreachable(&mut self) -> FuncResult<()> {
+        let frame = self
+            .ctrls
+            .last_mut()
+            .ok_or(ValidationErrorKind::UnexpectedEnd)?;
+        self.vals.truncate(frame.height);
+        frame.unreachable = true;
+        Ok(())
+    }
+
+    fn label_types(&self, depth: u32) -> FuncResult<Vec<ValueType>> {
+        let len = self.ctrls.len();
+        if depth as usize >= len {
+            return Err(ValidationErrorKind::UnknownLabel(depth));
+        }
+        Ok(self.ctrls[len - 1 - depth as usize].label_types().to_vec())
+    }
+
+    fn local(&self, idx: u32) -> FuncResult<ValueType> {
+        self.locals
+            .get(idx)
+            .ok_or(ValidationErrorKind::UnknownLocal(idx))
+    }
+
+    fn global(&self, idx: u32) -> FuncResult<(ValueType, bool)> {
+        self.ctx
+            .globals
+            .get(idx as usize)
+            .cloned()
+            .ok_or(ValidationErrorKind::UnknownGlobal(idx))
+    }
+
+    fn block_type(&self, block_type: &BlockType) -> FuncResult<(Vec<ValueType>, Vec<ValueType>)> {
+        match block_type {
+            BlockType::Empty => Ok((vec![], vec![])),
+            BlockType::Value(value_types) => Ok((vec![], value_types.clone())),
+            BlockType::TypeIdx(idx) => {
+                let func_type = self.ctx.func_type(*idx).map_err(|e| e.kind)?;
+                Ok((func_type.params.clone(), func_type.results.clone()))
+            }
+        }
+    }
+
+    fn check_memory(&self, idx: u32) -> FuncResult<()> {
+        self.ctx.check_memory(idx).map_err(|e| e.kind)
+    }
+
+    fn table(&self, idx: u32) -> FuncResult<ValueType> {
+        self.ctx.table(idx).map_err(|e| e.kind)
+    }
+
+    fn elem(&self, idx: u32) -> FuncResult<ValueType> {
+        self.ctx.elem(idx).map_err(|e| e.kind)
+    }
+
+    fn check_data(&self, idx: u32) -> FuncResult<()> {
+        self.ctx.check_data(idx).map_err(|e| e.kind)
+    }
+
+    fn select(&mut self, value_type: Option<ValueType>) -> FuncResult<()> {
+        self.pop_expect(&ValueType::I32)?;
+        if let Some(value_type) = value_type {
+            self.pop_expect(&value_type)?;
+            self.pop_expect(&value_type)?;
+            self.push_val(value_type);
+            return Ok(());
+        }
+
+        // NOTE: select without type annotation only accepts numeric types
+        let t1 = self.pop_val()?;
+        let t2 = self.pop_val()?;
+        let value_type = match (t1, t2) {
+            (Some(t1), Some(t2)) if t1 != t2 => return Err(ValidationErrorKind::TypeMismatch),
+            (Some(t), _) | (_, Some(t)) => Some(t),
+            (None, None) => None,
+        };
+        if value_type.as_ref().is_some_and(ValueType::is_ref) {
+            return Err(ValidationErrorKind::TypeMismatch);
+        }
+        self.vals.push(value_type);
+        Ok(())
+    }
+
+    fn unop(&mut self, value_type: ValueType) -> FuncResult<()> {
+        self.pop_expect(&value_type)?;
+        self.push_val(value_type);
+        Ok(())
+    }
+
+    fn binop(&mut self, value_type: ValueType) -> FuncResult<()> {
+        self.pop_expect(&value_type)?;
+        self.pop_expect(&value_type)?;
+        self.push_val(value_type);
+        Ok(())
+    }
+
+    fn testop(&mut self, value_type: ValueType) -> FuncResult<()> {
+        self.pop_expect(&value_type)?;
+        self.push_val(ValueType::I32);
+        Ok(())
+    }
+
+    fn relop(&mut self, value_type: ValueType) -> FuncResult<()> {
+        self.pop_expect(&value_type)?;
+        self.pop_expect(&value_type)?;
+        self.push_val(ValueType::I32);
+        Ok(())
+    }
+
+    fn cvtop(&mut self, from: ValueType, to: ValueType) -> FuncResult<()> {
+        self.pop_expect(&from)?;
+        self.push_val(to);
+        Ok(())
+    }
+
+    // natural is the log2 of the access width in bytes
+    fn load(&mut self, value_type: ValueType, arg: &MemoryArg, natural: u32) -> FuncResult<()> {
+        self.check_memory(0)?;
+        if arg.align > natural {
+            return Err(ValidationErrorKind::InvalidAlignment);
+        }
+        self.pop_expect(&ValueType::I32)?;
+        self.push_val(value_type);
+        Ok(())
+    }
+
+    fn store(&mut self, value_type: ValueType, arg: &MemoryArg, natural: u32) -> FuncResult<()> {
+        self.check_memory(0)?;
+        if arg.align > natural {
+            return Err(ValidationErrorKind::InvalidAlignment);
+        }
+        self.pop_expect(&value_type)?;
+        self.pop_expect(&ValueType::I32)?;
+        Ok(())
+    }
+
+    fn call(&mut self, func_type: FuncType) -> FuncResult<()> {
+        self.pop_vals(&func_type.params)?;
+        self.push_vals(&func_type.results);
+        Ok(())
+    }
+
+    // https://webassembly.github.io/spec/core/appendix/algorithm.html#validation-of-opcode-sequences
+    fn instruction(&mut self, inst: &Instruction) -> FuncResult<()> {
+        use ValueType::*;
+        match inst {
+            Instruction::Unreachable => self.unreachable()?,
+            Instruction::Nop => {}
+            Instruction::Block(block) => {
+                let (params, results) = self.block_type(&block.block_type)?;
+                self.pop_vals(&params)?;
+                self.push_ctrl(CtrlKind::Block, params, results);
+            }
+            Instruction::Loop(block) => {
+                let (params, results) = self.block_type(&block.block_type)?;
+                self.pop_vals(&params)?;
+                self.push_ctrl(CtrlKind::Loop, params, results);
+            }
+            Instruction::If(block) => {
+                let (params, results) = self.block_type(&block.block_type)?;
+                self.pop_expect(&I32)?;
+                self.pop_vals(&params)?;
+                self.push_ctrl(CtrlKind::If, params, results);
+            }
+            Instruction::Else => {
+                let frame = self.pop_ctrl()?;
+                if frame.kind != CtrlKind::If {
+                    return Err(ValidationErrorKind::UnexpectedElse);
+                }
+                self.push_ctrl(CtrlKind::Else, frame.start_types, frame.end_types);
+            }
+            Instruction::End => {
+                let frame = self.pop_ctrl()?;
+                // if without else must not change the types of the operand stack
+                if frame.kind == CtrlKind::If && frame.start_types != frame.end_types {
+                    return Err(ValidationErrorKind::TypeMismatch);
+                }
+                self.push_vals(&frame.end_types);
+            }
+            Instruction::Br(depth) => {
+                let types = self.label_types(*depth)?;
+                self.pop_vals(&types)?;
+                self.unreachable()?;
+            }
+            Instruction::BrIf(depth) => {
+                self.pop_expect(&I32)?;
+                let types = self.label_types(*depth)?;
+                self.pop_vals(&types)?;
+                self.push_vals(&types);
+            }
+            Instruction::BrTable(depths, default) => {
+                self.pop_expect(&I32)?;
+                let arity = self.label_types(*default)?.len();
+                for depth in depths {
+                    let types = self.label_types(*depth)?;
+                    if types.len() != arity {
+                        return Err(ValidationErrorKind::TypeMismatch);
+                    }
+                    let vals = self.pop_vals(&types)?;
+                    self.vals.extend(vals);
+                }
+                let types = self.label_types(*default)?;
+                self.pop_vals(&types)?;
+                self.unreachable()?;
+            }
+            Instruction::Return => {
+                let types = self.ctrls[0].end_types.clone();
+                self.pop_vals(&types)?;
+                self.unreachable()?;
+            }
+            Instruction::Call(idx) => {
+                let func_type = self.ctx.func_type_of(*idx).map_err(|e| e.kind)?.clone();
+                self.call(func_type)?;
+            }
+            Instruction::CallIndirect((type_idx, table_idx)) => {
+                if self.table(*table_idx)? != FuncRef {
+                    return Err(ValidationErrorKind::TypeMismatch);
+                }
+                let func_type = self.ctx.func_type(*type_idx).map_err(|e| e.kind)?.clone();
+                self.pop_expect(&I32)?;
+                self.call(func_type)?;
+            }
+            Instruction::Drop => {
+                self.pop_val()?;
+            }
+            Instruction::Select => self.select(None)?,
+            Instruction::SelectType(value_types) => {
+                let [value_type] = value_types.as_slice() else {
+                    return Err(ValidationErrorKind::InvalidResultArity);
+                };
+                self.select(Some(value_type.clone()))?
+            }
+            Instruction::RefNull(value_type) => self.push_val(value_type.clone()),
+            Instruction::RefIsNull => {
+                if self.pop_val()?.is_some_and(|t| !t.is_ref()) {
+                    return Err(ValidationErrorKind::TypeMismatch);
+                }
+                self.push_val(I32);
+            }
+            Instruction::RefFunc(idx) => {
+                self.ctx.func_type_of(*idx).map_err(|e| e.kind)?;
+                if !self.ctx.refs.contains(idx) {
+                    return Err(ValidationErrorKind::UndeclaredFunctionReference);
+                }
+                self.push_val(FuncRef);
+            }
+            Instruction::TableGet(idx) => {
+                let elem_type = self.table(*idx)?;
+                self.pop_expect(&I32)?;
+                self.push_val(elem_type);
+            }
+            Instruction::TableSet(idx) => {
+                let elem_type = self.table(*idx)?;
+                self.pop_expect(&elem_type)?;
+                self.pop_expect(&I32)?;
+            }
+            Instruction::TableSize(idx) => {
+                self.table(*idx)?;
+                self.push_val(I32);
+            }
+            Instruction::TableGrow(idx) => {
+                let elem_type = self.table(*idx)?;
+                self.pop_expect(&I32)?;
+                self.pop_expect(&elem_type)?;
+                self.push_val(I32);
+            }
+            Instruction::TableFill(idx) => {
+                let elem_type = self.table(*idx)?;
+                self.pop_vals(&[I32, elem_type, I32])?;
+            }
+            Instruction::TableInit(elem_idx, table_idx) => {
+                if self.table(*table_idx)? != self.elem(*elem_idx)? {
+                    return Err(ValidationErrorKind::TypeMismatch);
+                }
+                self.pop_vals(&[I32, I32, I32])?;
+            }
+            Instruction::ElemDrop(idx) => {
+                self.elem(*idx)?;
+            }
+            Instruction::TableCopy(dst, src) => {
+                if self.table(*dst)? != self.table(*src)? {
+                    return Err(ValidationErrorKind::TypeMismatch);
+                }
+                self.pop_vals(&[I32, I32, I32])?;
+            }
+            Instruction::LocalGet(idx) => {
+                let value_type = self.local(*idx)?;
+                self.push_val(value_type);
+            }
+            Instruction::LocalSet(idx) => {
+                let value_type = self.local(*idx)?;
+                self.pop_expect(&value_type)?;
+            }
+            Instruction::LocalTee(idx) => {
+                let value_type = self.local(*idx)?;
+                self.unop(value_type)?;
+            }
+            Instruction::GlobalGet(idx) => {
+                let (value_type, _) = self.global(*idx)?;
+                self.push_val(value_type);
+            }
+            Instruction::GlobalSet(idx) => {
+                let (value_type, mutable) = self.global(*idx)?;
+                if !mutable {
+                    return Err(ValidationErrorKind::ImmutableGlobal);
+                }
+                self.pop_expect(&value_type)?;
+            }
+            Instruction::I32Load(arg) => self.load(I32, arg, 2)?,
+            Instruction::I64Load(arg) => self.load(I64, arg, 3)?,
+            Instruction::F32Load(arg) => self.load(F32, arg, 2)?,
+            Instruction::F64Load(arg) => self.load(F64, arg, 3)?,
+            Instruction::I32Load8S(arg) | Instruction::I32Load8U(arg) => self.load(I32, arg, 0)?,
+            Instruction::I32Load16S(arg) | Instruction::I32Load16U(arg) => {
+                self.load(I32, arg, 1)?
+            }
+            Instruction::I64Load8S(arg) | Instruction::I64Load8U(arg) => self.load(I64, arg, 0)?,
+            Instruction::I64Load16S(arg) | Instruction::I64Load16U(arg) => {
+                self.load(I64, arg, 1)?
+            }
+            Instruction::I64Load32S(arg) | Instruction::I64Load32U(arg) => {
+                self.load(I64, arg, 2)?
+            }
+            Instruction::I32Store(arg) => self.store(I32, arg, 2)?,
+            Instruction::I64Store(arg) => self.store(I64, arg, 3)?,
+            Instruction::F32Store(arg) => self.store(F32, arg, 2)?,
+            Instruction::F64Store(arg) => self.store(F64, arg, 3)?,
+            Instruction::I32Store8(arg) => self.store(I32, arg, 0)?,
+            Instruction::I32Store16(arg) => self.store(I32, arg, 1)?,
+            Instruction::I64Store8(arg) => self.store(I64, arg, 0)?,
+            Instruction::I64Store16(arg) => self.store(I64, arg, 1)?,
+            Instruction::I64Store32(arg) => self.store(I64, arg, 2)?,
+            Instruction::MemorySize => {
+                self.check_memory(0)?;
+                self.push_val(I32);
+            }
+            Instruction::MemoryGrow(idx) => {
+                self.check_memory(*idx)?;
+                self.unop(I32)?;
+            }
+            Instruction::MemoryCopy(src, dst) => {
+                self.check_memory(*src)?;
+                self.check_memory(*dst)?;
+                self.pop_vals(&[I32, I32, I32])?;
+            }
+            Instruction::MemoryFill(idx) => {
+                self.check_memory(*idx)?;
+                self.pop_vals(&[I32, I32, I32])?;
+            }
+            Instruction::MemoryInit(idx) => {
+                self.check_memory(0)?;
+                self.check_data(*idx)?;
+                self.pop_vals(&[I32, I32, I32])?;
+            }
+            Instruction::DataDrop(idx) => self.check_data(*idx)?,
+            Instruction::I32Const(_) => self.push_val(I32),
+            Instruction::I64Const(_) => self.push_val(I64),
+            Instruction::F32Const(_) => self.push_val(F32),
+            Instruction::F64Const(_) => self.push_val(F64),
+            Instruction::I32Eqz => self.testop(I32)?,
+            Instruction::I64Eqz => self.testop(I64)?,
+            Instruction::I32Eq
+            | Instruction::I32Ne
+            | Instruction::I32LtS
+            | Instruction::I32LtU
+            | Instruction::I32GtS
+            | Instruction::I32GtU
+            | Instruction::I32LeS
+            | Instruction::I32LeU
+            | Instruction::I32GeS
+            | Instruction::I32GeU => self.relop(I32)?,
+            Instruction::I64Eq
+            | Instruction::I64Ne
+            | Instruction::I64LtS
+            | Instruction::I64LtU
+            | Instruction::I64GtS
+            | Instruction::I64GtU
+            | Instruction::I64LeS
+            | Instruction::I64LeU
+            | Instruction::I64GeS
+            | Instruction::I64GeU => self.relop(I64)?,
+            Instruction::F32Eq
+            | Instruction::F32Ne
+            | Instruction::F32Lt
+            | Instruction::F32Gt
+            | Instruction::F32Le
+            | Instruction::F32Ge => self.relop(F32)?,
+            Instruction::F64Eq
+            | Instruction::F64Ne
+            | Instruction::F64Lt
+            | Instruction::F64Gt
+            | Instruction::F64Le
+            | Instruction::F64Ge => self.relop(F64)?,
+            Instruction::I32Clz
+            | Instruction::I32Ctz
+            | Instruction::I32Popcnt
+            | Instruction::I32Extend8S
+            | Instruction::I32Extend16S => self.unop(I32)?,
+            Instruction::I64Clz
+            | Instruction::I64Ctz
+            | Instruction::I64Popcnt
+            | Instruction::I64Extend8S
+            | Instruction::I64Extend16S
+            | Instruction::I64Extend32S => self.unop(I64)?,
+            Instruction::F32Abs
+            | Instruction::F32Neg
+            | Instruction::F32Ceil
+            | Instruction::F32Floor
+            | Instruction::F32Trunc
+            | Instruction::F32Nearest
+            | Instruction::F32Sqrt => self.unop(F32)?,
+            Instruction::F64Abs
+            | Instruction::F64Neg
+            | Instruction::F64Ceil
+            | Instruction::F64Floor
+            | Instruction::F64Trunc
+            | Instruction::F64Nearest
+            | Instruction::F64Sqrt => self.unop(F64)?,
+            Instruction::I32Add
+            | Instruction::I32Sub
+            | Instruction::I32Mul
+            | Instruction::I32DivS
+            | Instruction::I32DivU
+            | Instruction::I32RemS
+            | Instruction::I32RemU
+            | Instruction::I32And
+            | Instruction::I32Or
+            | Instruction::I32Xor
+            | Instruction::I32ShL
+            | Instruction::I32ShrS
+            | Instruction::I32ShrU
+            | Instruction::I32RtoL
+            | Instruction::I32RtoR => self.binop(I32)?,
+            Instruction::I64Add
+            | Instruction::I64Sub
+            | Instruction::I64Mul
+            | Instruction::I64DivS
+            | Instruction::I64DivU
+            | Instruction::I64RemS
+            | Instruction::I64RemU
+            | Instruction::I64And
+            | Instruction::I64Or
+            | Instruction::I64Xor
+            | Instruction::I64ShL
+            | Instruction::I64ShrS
+            | Instruction::I64ShrU
+            | Instruction::I64RtoL
+            | Instruction::I64RtoR => self.binop(I64)?,
+            Instruction::F32Add
+            | Instruction::F32Sub
+            | Instruction::F32Mul
+            | Instruction::F32Div
+            | Instruction::F32Min
+            | Instruction::F32Max
+            | Instruction::F32Copysign => self.binop(F32)?,
+            Instruction::F64Add
+            | Instruction::F64Sub
+            | Instruction::F64Mul
+            | Instruction::F64Div
+            | Instruction::F64Min
+            | Instruction::F64Max
+            | Instruction::F64Copysign => self.binop(F64)?,
+            Instruction::I32WrapI64 => self.cvtop(I64, I32)?,
+            Instruction::I32TruncF32S | Instruction::I32TruncF32U => self.cvtop(F32, I32)?,
+            Instruction::I32TruncF64S | Instruction::I32TruncF64U => self.cvtop(F64, I32)?,
+            Instruction::I64ExtendI32S | Instruction::I64ExtendI32U => self.cvtop(I32, I64)?,
+            Instruction::I64TruncF32S | Instruction::I64TruncF32U => self.cvtop(F32, I64)?,
+            Instruction::I64TruncF64S | Instruction::I64TruncF64U => self.cvtop(F64, I64)?,
+            Instruction::I32TruncSatF32S | Instruction::I32TruncSatF32U => self.cvtop(F32, I32)?,
+            Instruction::I32TruncSatF64S | Instruction::I32TruncSatF64U => self.cvtop(F64, I32)?,
+            Instruction::I64TruncSatF32S | Instruction::I64TruncSatF32U => self.cvtop(F32, I64)?,
+            Instruction::I64TruncSatF64S | Instruction::I64TruncSatF64U => self.cvtop(F64, I64)?,
+            Instruction::F32ConvertI32S | Instruction::F32ConvertI32U => self.cvtop(I32, F32)?,
+            Instruction::F32ConvertI64S | Instruction::F32ConvertI64U => self.cvtop(I64, F32)?,
+            Instruction::F32DemoteF64 => self.cvtop(F64, F32)?,
+            Instruction::F64ConvertI32S | Instruction::F64ConvertI32U => self.cvtop(I32, F64)?,
+            Instruction::F64ConvertI64S | Instruction::F64ConvertI64U => self.cvtop(I64, F64)?,
+            Instruction::F64PromoteF32 => self.cvtop(F32, F64)?,
+            Instruction::I32ReinterpretF32 => self.cvtop(F32, I32)?,
+            Instruction::I64ReinterpretF64 => self.cvtop(F64, I64)?,
+            Instruction::F32ReinterpretI32 => self.cvtop(I32, F32)?,
+            Instruction::F64ReinterpretI64 => self.cvtop(I64, F64)?,
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{validate, ValidationError, ValidationErrorKind};
+    use crate::binary::module::Decoder;
+    use anyhow::Result;
+
+    fn validate_wat(source: &str) -> Result<std::result::Result<(), ValidationError>> {
+        let wasm = wat::parse_str(source)?;
+        let mut decoder = Decoder::new(std::io::Cursor::new(wasm));
+        let module = decoder.decode()?;
+        Ok(validate(&module))
+    }
+
+    #[test]
+    fn valid_module() -> Result<()> {
+        let source = r#"
+(module
+  (import "env" "g" (global $g i32))
+  (memory 1)
+  (table 1 funcref)
+  (global $x (mut i32) (i32.const 0))
+  (data (global.get $g) "abc")
+  (elem (i32.const 0) $fib)
+  (func $fib (export "fib") (param i32) (result i32)
+    (if (result i32) (i32.lt_u (local.get 0) (i32.const 2))
+      (then (local.get 0))
+      (else
+        (i32.add
+          (call $fib (i32.sub (local.get 0) (i32.const 1)))
+          (call $fib (i32.sub (local.get 0) (i32.const 2)))
+        )
+      )
+    )
+  )
+  (func (export "unreachable") (result i32)
+    (block (result i32) (unreachable) (br 0))
+  )
+  (func (export "store") (param i32)
+    (global.set $x (local.get 0))
+    (i32.store8 (i32.const 0) (global.get $x))
+  )
+)
+"#;
+        assert_eq!(validate_wat(source)?, Ok(()));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_instructions() -> Result<()> {
+        let tests = [
+            (
+                "(module (func (result i32) (i64.const 1)))",
+                ValidationErrorKind::TypeMismatch,
+                Some(1),
+            ),
+            (
+                "(module (func (i32.add (i32.const 1) (f32.const 1)) (drop)))",
+                ValidationErrorKind::TypeMismatch,
+                Some(2),
+            ),
+            (
+                "(module (func (local.get 0) (drop)))",
+                ValidationErrorKind::UnknownLocal(0),
+                Some(0),
+            ),
+            (
+                "(module (func (br 1)))",
+                ValidationErrorKind::UnknownLabel(1),
+                Some(0),
+            ),
+            (
+                "(module (global i32 (i32.const 0)) (func (global.set 0 (i32.const 1))))",
+                ValidationErrorKind::ImmutableGlobal,
+                Some(1),
+            ),
+            (
+                "(module (memory 1) (func (drop (i32.load align=8 (i32.const 0)))))",
+                ValidationErrorKind::InvalidAlignment,
+                Some(1),
+            ),
+            (
+                "(module (func (drop (i32.load (i32.const 0)))))",
+                ValidationErrorKind::UnknownMemory(0),
+                Some(1),
+            ),
+            (
+                "(module (func $f (drop (ref.func $f))))",
+                ValidationErrorKind::UndeclaredFunctionReference,
+                Some(0),
+            ),
+            (
+                "(module (table 1 externref) (func (call_indirect (i32.const 0))))",
+                ValidationErrorKind::TypeMismatch,
+                Some(1),
+            ),
+            (
+                "(module (func (param externref) (drop (select (local.get 0) (local.get 0) (i32.const 1)))))",
+                ValidationErrorKind::TypeMismatch,
+                Some(3),
+            ),
+            (
+                "(module (func (elem.drop 0)))",
+                ValidationErrorKind::UnknownElemSegment(0),
+                Some(0),
+            ),
+            (
+                "(module (memory 1) (func (data.drop 0)))",
+                ValidationErrorKind::UnknownDataSegment(0),
+                Some(0),
+            ),
+            (
+                "(module (table 1 externref) (elem func 0) (func (table.init 0 0 (i32.const 0) (i32.const 0) (i32.const 0))))",
+                ValidationErrorKind::TypeMismatch,
+                Some(3),
+            ),
+        ];
+
+        for (source, kind, offset) in tests {
+            let err = validate_wat(source)?.expect_err(source);
+            assert_eq!(err.kind, kind, "{source}");
+            assert_eq!(err.func_idx, Some(0), "{source}");
+            assert_eq!(err.offset, offset, "{source}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_module() -> Result<()> {
+        let tests = [
+            (
+                r#"(module (func (export "a")) (func (export "a")))"#,
+                ValidationErrorKind::DuplicateExportName("a".into()),
+            ),
+            (
+                "(module (memory 2 1))",
+                ValidationErrorKind::SizeMinimumGreaterThanMaximum,
+            ),
+            (
+                "(module (memory 65537))",
+                ValidationErrorKind::MemorySizeTooLarge,
+            ),
+            (
+                "(module (func $main (param i32)) (start $main))",
+                ValidationErrorKind::InvalidStartFunction,
+            ),
+            (
+                "(module (global (mut i32) (i32.const 0)) (memory 1) (data (global.get 0) \"a\"))",
+                ValidationErrorKind::UnknownGlobal(0),
+            ),
+            (
+                "(module (global i64 (i32.const 0)))",
+                ValidationErrorKind::TypeMismatch,
+            ),
+            (
+                "(module (table 1 externref) (elem (table 0) (i32.const 0) func))",
+                ValidationErrorKind::TypeMismatch,
+            ),
+            (
+                "(module (elem externref (ref.func 0)) (func))",
+                ValidationErrorKind::TypeMismatch,
+            ),
+            (
+                "(module (table 2 1 funcref))",
+                ValidationErrorKind::SizeMinimumGreaterThanMaximum,
+            ),
+        ];
+
+        for (source, kind) in tests {
+            let err = validate_wat(source)?.expect_err(source);
+            assert_eq!(err.kind, kind, "{source}");
+            assert_eq!(err.func_idx, None, "{source}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn locals() -> Result<()> {
+        let source = r#"
+(module
+  (func (param i32) (local i64 i64) (local f32)
+    (drop (i32.add (local.get 0) (i32.const 1)))
+    (drop (i64.add (local.get 2) (i64.const 1)))
+    (drop (f32.neg (local.get 3)))
+  )
+)
+"#;
+        assert_eq!(validate_wat(source)?, Ok(()));
+
+        let source = "(module (func (param i32) (local i64) (drop (local.get 2))))";
+        let err = validate_wat(source)?.unwrap_err();
+        assert_eq!(err.kind, ValidationErrorKind::UnknownLocal(2));
+
+        // (func (local i32) * u32::MAX) isn't expanded
+        let wasm = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section
+            0x03, 0x02, 0x01, 0x00, // function section
+            0x0a, 0x0a, 0x01, 0x08, 0x01, 0xff, 0xff, 0xff, 0xff, 0x0f, 0x7f,
+            0x0b, // code section
+        ];
+        let mut decoder = Decoder::new(std::io::Cursor::new(wasm));
+        let module = decoder.decode()?;
+        let err = validate(&module).unwrap_err();
+        assert_eq!(err.kind, ValidationErrorKind::TooManyLocals);
+        assert_eq!(err.func_idx, Some(0));
+        Ok(())
+    }
+}
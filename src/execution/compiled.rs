@@ -0,0 +1,141 @@
+use super::side_table::SideTable;
+use crate::binary::{
+    instruction::Instruction,
+    module::{Decoder, Module as BinaryModule},
+    types::ValueType,
+    validate::validate,
+};
+use anyhow::{bail, Context as _, Result};
+use std::{
+    fs,
+    io::{Cursor, Read},
+    sync::Arc,
+};
+
+// function body which is ready to execute
+#[derive(Debug, Clone)]
+pub(crate) struct CompiledFunc {
+    pub type_idx: u32,
+    pub locals: Vec<ValueType>,
+    pub body: Arc<Vec<Instruction>>,
+    pub side_table: Arc<SideTable>,
+}
+
+// module which is decoded and validated once, and can be instantiated many times.
+// it's immutable and the function bodies are shared by the instances,
+// so it can be wrapped in `Arc` and shared across threads.
+//
+//   let module = Arc::new(Module::from_file("app.wasm")?);
+//   let mut runtime = Runtime::from_module(&module, None)?;
+#[derive(Debug)]
+pub struct Module {
+    pub(crate) module: BinaryModule,
+    pub(crate) funcs: Vec<CompiledFunc>,
+}
+
+impl Module {
+    pub fn from_file(file: &str) -> Result<Self> {
+        let file = fs::File::open(file)?;
+        Self::decode(file)
+    }
+
+    pub fn from_reader(reader: &mut impl Read) -> Result<Self> {
+        Self::decode(reader)
+    }
+
+    pub fn from_bytes<T: AsRef<[u8]>>(b: T) -> Result<Self> {
+        Self::decode(Cursor::new(b))
+    }
+
+    fn decode(reader: impl Read) -> Result<Self> {
+        let mut decoder = Decoder::new(reader);
+        let module = decoder.decode()?;
+        Self::compile(module)
+    }
+
+    // https://www.w3.org/TR/wasm-core-1/#valid-module
+    fn compile(mut module: BinaryModule) -> Result<Self> {
+        validate(&module)?;
+
+        let func_type_idxs = module.function_section.as_deref().unwrap_or_default();
+        let func_types = module.type_section.as_deref().unwrap_or_default();
+        let code_section = module.code_section.take().unwrap_or_default();
+        if code_section.len() != func_type_idxs.len() {
+            bail!("code section length must be equal to function section length");
+        }
+
+        let mut funcs = Vec::with_capacity(code_section.len());
+        for (func_body, typeidx) in code_section.into_iter().zip(func_type_idxs.iter()) {
+            func_types
+                .get(*typeidx as usize)
+                .with_context(|| "cannot get func type from type section")?;
+
+            // the count of the locals is limited by the validation
+            let mut locals = Vec::with_capacity(func_body.locals.len());
+            for local in func_body.locals.iter() {
+                for _ in 0..local.type_count {
+                    locals.push(local.value_type.clone());
+                }
+            }
+
+            let side_table = SideTable::new(&func_body.code, func_types)?;
+            funcs.push(CompiledFunc {
+                type_idx: *typeidx,
+                locals,
+                body: Arc::new(func_body.code),
+                side_table: Arc::new(side_table),
+            });
+        }
+
+        Ok(Self { module, funcs })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Module;
+    use crate::{module::FuncInst, Runtime, Value};
+    use anyhow::Result;
+    use std::sync::Arc;
+
+    #[test]
+    fn instantiate_many() -> Result<()> {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Module>();
+
+        let wasm = wat::parse_str(
+            r#"(module
+              (global $count (mut i32) (i32.const 0))
+              (func (export "incr") (result i32)
+                (global.set $count (i32.add (global.get $count) (i32.const 1)))
+                (global.get $count))
+            )"#,
+        )?;
+        let module = Arc::new(Module::from_bytes(wasm)?);
+
+        let mut runtimes = (0..2)
+            .map(|_| Runtime::from_module(&module, None))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            runtimes[0].call("incr".into(), vec![])?,
+            Some(Value::I32(1))
+        );
+        assert_eq!(
+            runtimes[0].call("incr".into(), vec![])?,
+            Some(Value::I32(2))
+        );
+        // the state is not shared between the instances
+        assert_eq!(
+            runtimes[1].call("incr".into(), vec![])?,
+            Some(Value::I32(1))
+        );
+
+        let body = |runtime: &Runtime| match &runtime.store.lock().unwrap().funcs[0] {
+            FuncInst::Internal(func) => Arc::clone(&func.code.body),
+            FuncInst::External(_) => unreachable!(),
+        };
+        assert!(Arc::ptr_eq(&body(&runtimes[0]), &body(&runtimes[1])));
+        assert!(Arc::ptr_eq(&body(&runtimes[0]), &module.funcs[0].body));
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use crate::binary::instruction::Instruction;
+
+// limits of the runtime to stop the runaway guest before it exhausts the host memory
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub(crate) max_call_depth: usize,
+    pub(crate) max_value_stack: usize,
+    pub(crate) max_table_elements: u32,
+    pub(crate) consume_fuel: bool,
+    pub(crate) fuel_cost: fn(&Instruction) -> u64,
+}
+
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+pub const DEFAULT_MAX_VALUE_STACK: usize = 1024 * 1024;
+// the table without the declared maximum could grow to u32::MAX elements otherwise
+pub const DEFAULT_MAX_TABLE_ELEMENTS: u32 = 10_000_000;
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            max_value_stack: DEFAULT_MAX_VALUE_STACK,
+            max_table_elements: DEFAULT_MAX_TABLE_ELEMENTS,
+            consume_fuel: false,
+            fuel_cost: default_fuel_cost,
+        }
+    }
+}
+
+// the structural instructions are free, the others cost one unit each
+pub fn default_fuel_cost(inst: &Instruction) -> u64 {
+    match inst {
+        Instruction::Nop
+        | Instruction::Block(_)
+        | Instruction::Loop(_)
+        | Instruction::Else
+        | Instruction::End => 0,
+        _ => 1,
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // maximum number of the function frames in the call stack
+    pub fn max_call_depth(mut self, depth: usize) -> Self {
+        self.max_call_depth = depth;
+        self
+    }
+
+    // maximum number of the values in the value stack
+    pub fn max_value_stack(mut self, size: usize) -> Self {
+        self.max_value_stack = size;
+        self
+    }
+
+    // maximum number of the elements which table.grow can grow the table to,
+    // it's also applied to the table without the declared maximum
+    pub fn max_table_elements(mut self, size: u32) -> Self {
+        self.max_table_elements = size;
+        self
+    }
+
+    // charge fuel for every executed instruction, the runtime starts with no fuel
+    // and traps with `TrapCode::OutOfFuel` when the fuel is exhausted
+    pub fn consume_fuel(mut self, enable: bool) -> Self {
+        self.consume_fuel = enable;
+        self
+    }
+
+    // cost table that returns the fuel consumed by the instruction
+    pub fn fuel_cost(mut self, cost: fn(&Instruction) -> u64) -> Self {
+        self.fuel_cost = cost;
+        self
+    }
+}
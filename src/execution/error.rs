@@ -1,22 +1,15 @@
-use super::value::Value;
+use crate::binary::types::BlockType;
+use std::fmt::Display;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("integer divide by zero")]
-    IntegerDivideByZero,
-    #[error("integer overflow")]
-    IntegerOverflow,
-    #[error("integer overflow")]
-    DivisionOverflow,
     #[error("cannot pop value from stack")]
     StackPopError,
-    #[error("memory size is not page aligned, page size is {0}")]
-    MemorySizeNotPageAligned(u32),
     #[error("memory page is overflow. max is {0}, grow size is {1}")]
     MemoryPageOverflow(u32, u32),
-    #[error("unexpected stack value type: {0:?}")]
-    UnexpectedStackValueType(Value),
+    #[error("unexpected stack value type: {0}")]
+    UnexpectedStackValueType(String),
     #[error("not found local variable with index: {0}")]
     NotFoundLocalVariable(usize),
     #[error("not found global variable with index: {0}")]
@@ -31,6 +24,10 @@ pub enum Error {
     NotFoundLabel(usize),
     #[error("cannot get start pc in the label")]
     NotFoundStartPc,
+    #[error("not found jump target of the block with pc: {0}")]
+    NotFoundControlTarget(usize),
+    #[error("invalid block type: {0:?}")]
+    InvalidBlockType(BlockType),
     #[error("not found exported instance by name: {0}")]
     NotFoundExportInstance(String),
     #[error("not found exported function by index: {0}")]
@@ -53,14 +50,128 @@ pub enum Error {
     NotFoundFunction(usize),
     #[error("not found table by index: {0}")]
     NotFoundTable(usize),
+    #[error("not found function type by index: {0}")]
+    NotFoundFuncType(usize),
+    #[error("not found type section")]
+    NotFoundTypeSection,
+    #[error("not found element segment by index: {0}")]
+    NotFoundElemSegment(usize),
+    #[error("not found data segment by index: {0}")]
+    NotFoundDataSegment(usize),
+    #[error("not found import function: {0}::{1}")]
+    NotFoundImportFunc(String, String),
+    #[error("unknown import: {0}::{1}")]
+    UnknownImport(String, String),
+    #[error("not found module instance by address: {0}")]
+    NotFoundInstance(usize),
+    #[error("instance '{0}' belongs to another store")]
+    ForeignInstance(String),
+    #[error("incompatible import type: {0}::{1}")]
+    IncompatibleImportType(String, String),
+    #[error("results of the host function {0}::{1} don't match the signature: {2}")]
+    HostResultMismatch(String, String, String),
+    #[error("invalid arguments: expected {0}, actual {1}")]
+    InvalidArguments(String, String),
+    #[error("function type mismatch: expected {0}, actual {1}")]
+    TypedFuncMismatch(String, String),
+    #[error("typed function belongs to another store")]
+    ForeignTypedFunc,
+    #[error("fuel consumption is not enabled in the config")]
+    FuelNotEnabled,
+    #[error("no suspended execution to resume")]
+    NothingToResume,
+    #[error("all fuel consumed in the callback from the host function {0}::{1}")]
+    CallbackOutOfFuel(String, String),
+    #[error("execution is paused by the step budget")]
+    Paused,
+    #[error("resumable call belongs to another store")]
+    ForeignResumableCall,
+    #[error("invalid snapshot: {0}")]
+    InvalidSnapshot(String),
+    #[error("snapshot doesn't match the store: {0}")]
+    SnapshotMismatch(String),
+}
+
+// https://webassembly.github.io/spec/core/intro/overview.html#trap
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum TrapCode {
+    #[error("unreachable")]
+    Unreachable,
+    #[error("integer divide by zero")]
+    IntegerDivideByZero,
+    #[error("integer overflow")]
+    IntegerOverflow,
+    #[error("invalid conversion to integer")]
+    InvalidConversionToInteger,
+    #[error("out of bounds memory access")]
+    MemoryOutOfBounds,
+    #[error("out of bounds table access")]
+    TableOutOfBounds,
     #[error("undefined element")]
     UndefinedElement,
     #[error("uninitialized element {0}")]
     UninitializedElement(usize),
-    #[error("not found function type by index: {0}")]
-    NotFoundFuncType(usize),
     #[error("indirect call type mismatch")]
-    TypeMismatchIndirectCall,
-    #[error("not found type section")]
-    NotFoundTypeSection,
+    IndirectCallTypeMismatch,
+    #[error("call stack exhausted")]
+    StackOverflow,
+    #[error("all fuel consumed")]
+    OutOfFuel,
+    #[error("interrupted")]
+    Interrupted,
+}
+
+// a function frame which was active when the trap occurred
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameInfo {
+    pub func_idx: u32,
+    pub func_name: Option<String>, // exported name of the function
+    pub pc: usize,                 // index of the instruction in the function body
+}
+
+impl Display for FrameInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "func[{}]", self.func_idx)?;
+        if let Some(ref name) = self.func_name {
+            write!(f, " <{name}>")?;
+        }
+        write!(f, " @ pc {}", self.pc)
+    }
+}
+
+// the trap with the wasm backtrace, the innermost frame comes first.
+// `{:#}` prints the backtrace after the message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trap {
+    code: TrapCode,
+    backtrace: Vec<FrameInfo>,
+}
+
+impl Trap {
+    pub fn new(code: TrapCode, backtrace: Vec<FrameInfo>) -> Self {
+        Self { code, backtrace }
+    }
+
+    pub fn code(&self) -> &TrapCode {
+        &self.code
+    }
+
+    pub fn backtrace(&self) -> &[FrameInfo] {
+        &self.backtrace
+    }
+}
+
+impl Display for Trap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.code)?;
+        if f.alternate() && !self.backtrace.is_empty() {
+            write!(f, "\nwasm backtrace:")?;
+            for (i, frame) in self.backtrace.iter().enumerate() {
+                write!(f, "\n  {i}: {frame}")?;
+            }
+        }
+        Ok(())
+    }
 }
+
+impl std::error::Error for Trap {}
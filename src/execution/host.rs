@@ -0,0 +1,670 @@
+use super::{
+    error::{Error, TrapCode},
+    importer::Importer,
+    module::{ExternalFuncInst, FuncInst, GlobalInst, MemoryInst},
+    runtime::Runtime,
+    store::{Exports, Store},
+    value::Value,
+};
+use crate::binary::types::{FuncType, ValueType};
+use anyhow::{bail, Context as _, Result};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    mem::size_of,
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+/// types which can be copied from and to the guest memory as raw bytes.
+/// the linear memory is little-endian, so `to_le` converts every primitive field
+/// between the host byte order and little-endian, it's a no-op on little-endian hosts.
+///
+/// # Safety
+///
+/// the type must be `#[repr(C)]` or primitive without padding bytes,
+/// and every bit pattern must be a valid value of the type.
+pub unsafe trait Pod: Copy + 'static {
+    // swapping the bytes is its own inverse, so it also converts from little-endian
+    fn to_le(self) -> Self;
+}
+
+macro_rules! impl_pod {
+    ($($ty: ty),*) => {
+        $(unsafe impl Pod for $ty {
+            fn to_le(self) -> Self {
+                <$ty>::to_le(self)
+            }
+        })*
+    };
+}
+
+impl_pod!(u8, i8, u16, i16, u32, i32, u64, i64);
+
+unsafe impl Pod for f32 {
+    fn to_le(self) -> Self {
+        f32::from_bits(self.to_bits().to_le())
+    }
+}
+
+unsafe impl Pod for f64 {
+    fn to_le(self) -> Self {
+        f64::from_bits(self.to_bits().to_le())
+    }
+}
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {
+    fn to_le(self) -> Self {
+        self.map(T::to_le)
+    }
+}
+
+// context of the host function call, gives access to the exports and the memory
+// of the calling instance. the memory helpers trap with out of bounds memory access
+// like the load and store instructions.
+pub struct Caller {
+    store: Arc<Mutex<Store>>,
+    runtime: Rc<RefCell<Runtime>>,
+}
+
+impl Caller {
+    pub(crate) fn new(store: Arc<Mutex<Store>>) -> Self {
+        let runtime = Runtime::with_store(Arc::clone(&store));
+        Self {
+            store,
+            runtime: Rc::new(RefCell::new(runtime)),
+        }
+    }
+
+    pub(crate) fn with_runtime(runtime: Rc<RefCell<Runtime>>) -> Self {
+        let store = Arc::clone(&runtime.borrow().store);
+        Self { store, runtime }
+    }
+
+    // call the exported function of the calling instance from the host function.
+    // the store must not be locked by the host function while calling.
+    pub fn call(&self, name: &str, args: Vec<Value>) -> Result<Vec<Value>> {
+        self.runtime.borrow_mut().call_multi(name.into(), args)
+    }
+
+    pub fn store(&self) -> Arc<Mutex<Store>> {
+        Arc::clone(&self.store)
+    }
+
+    pub fn get_export(&self, name: &str) -> Result<Exports> {
+        let instance = self.runtime.borrow().instance();
+        self.store
+            .lock()
+            .expect("cannot lock store")
+            .exports(instance, name)
+    }
+
+    pub fn get_func(&self, name: &str) -> Result<FuncInst> {
+        match self.get_export(name)? {
+            Exports::Func(func) => Ok(func),
+            _ => bail!(Error::NotFoundExportInstance(name.into())),
+        }
+    }
+
+    pub fn get_global(&self, name: &str) -> Result<GlobalInst> {
+        match self.get_export(name)? {
+            Exports::Global(global) => Ok(global),
+            _ => bail!(Error::NotFoundExportInstance(name.into())),
+        }
+    }
+
+    // the memory exported as "memory", or the first memory of the instance
+    pub fn memory(&self) -> Result<MemoryInst> {
+        if let Ok(Exports::Memory(memory)) = self.get_export("memory") {
+            return Ok(memory);
+        }
+        let instance = self.runtime.borrow().instance();
+        let store = self.store.lock().expect("cannot lock store");
+        let memory = store.get_memory(instance, 0)?;
+        Ok(Arc::clone(memory))
+    }
+
+    pub fn read_bytes(&self, ptr: u32, len: u32) -> Result<Vec<u8>> {
+        let memory = self.memory()?;
+        let memory = memory.lock().expect("cannot lock memory");
+        let bytes = memory.read_bytes(ptr as usize, len as usize)?;
+        Ok(bytes.to_vec())
+    }
+
+    pub fn write_bytes(&self, ptr: u32, bytes: &[u8]) -> Result<()> {
+        self.memory()?
+            .lock()
+            .expect("cannot lock memory")
+            .write_bytes(ptr as usize, bytes)
+    }
+
+    pub fn read_string(&self, ptr: u32, len: u32) -> Result<String> {
+        let bytes = self.read_bytes(ptr, len)?;
+        Ok(String::from_utf8(bytes)?)
+    }
+
+    pub fn read<T: Pod>(&self, ptr: u32) -> Result<T> {
+        let bytes = self.read_bytes(ptr, size_of::<T>() as u32)?;
+        // SAFETY: any bit pattern is a valid `T` and the length is checked
+        let value: T = unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const T) };
+        Ok(value.to_le())
+    }
+
+    pub fn write<T: Pod>(&self, ptr: u32, value: &T) -> Result<()> {
+        let value = value.to_le();
+        // SAFETY: `T` has no padding bytes, so all bytes are initialized
+        let bytes =
+            unsafe { std::slice::from_raw_parts(&value as *const T as *const u8, size_of::<T>()) };
+        self.write_bytes(ptr, bytes)
+    }
+
+    pub fn read_slice<T: Pod>(&self, ptr: u32, len: u32) -> Result<Vec<T>> {
+        let size = size_of::<T>() as u32;
+        (0..len)
+            .map(|i| {
+                let offset = i.checked_mul(size).and_then(|n| ptr.checked_add(n));
+                self.read(offset.with_context(|| TrapCode::MemoryOutOfBounds)?)
+            })
+            .collect()
+    }
+
+    pub fn write_slice<T: Pod>(&self, ptr: u32, values: &[T]) -> Result<()> {
+        let values: Vec<T> = values.iter().map(|value| value.to_le()).collect();
+        // SAFETY: same as `write`, the elements of the slice are contiguous
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                values.as_ptr() as *const u8,
+                std::mem::size_of_val(&*values),
+            )
+        };
+        self.write_bytes(ptr, bytes)
+    }
+}
+
+// rust types which can be passed to or returned from the host function
+pub trait WasmTy: Sized {
+    fn value_type() -> ValueType;
+    fn from_value(value: Value) -> Result<Self>;
+    fn into_value(self) -> Value;
+}
+
+macro_rules! impl_wasm_ty {
+    ($($ty: ty => $variant: ident),*) => {
+        $(
+            impl WasmTy for $ty {
+                fn value_type() -> ValueType {
+                    ValueType::$variant
+                }
+
+                fn from_value(value: Value) -> Result<Self> {
+                    match value {
+                        Value::$variant(v) => Ok(v),
+                        _ => bail!(Error::UnexpectedStackValueType(format!("{value:?}"))),
+                    }
+                }
+
+                fn into_value(self) -> Value {
+                    Value::$variant(self)
+                }
+            }
+        )*
+    };
+}
+
+impl_wasm_ty!(i32 => I32, i64 => I64, f32 => F32, f64 => F64);
+
+// return type of the host function or the typed function,
+// `Result` is used to trap the guest from the host function
+pub trait WasmResults: Sized {
+    fn value_types() -> Vec<ValueType>;
+    fn into_values(self) -> Result<Vec<Value>>;
+    fn from_values(values: Vec<Value>) -> Result<Self>;
+}
+
+impl<R: WasmResults> WasmResults for Result<R> {
+    fn value_types() -> Vec<ValueType> {
+        R::value_types()
+    }
+
+    fn into_values(self) -> Result<Vec<Value>> {
+        self?.into_values()
+    }
+
+    fn from_values(values: Vec<Value>) -> Result<Self> {
+        Ok(R::from_values(values))
+    }
+}
+
+macro_rules! impl_wasm_results {
+    ($($ty: ident),*) => {
+        impl<$($ty: WasmTy),*> WasmResults for ($($ty,)*) {
+            fn value_types() -> Vec<ValueType> {
+                vec![$($ty::value_type()),*]
+            }
+
+            #[allow(non_snake_case)]
+            fn into_values(self) -> Result<Vec<Value>> {
+                let ($($ty,)*) = self;
+                Ok(vec![$($ty.into_value()),*])
+            }
+
+            #[allow(unused_mut, unused_variables)]
+            fn from_values(values: Vec<Value>) -> Result<Self> {
+                let mut values = values.into_iter();
+                Ok(($($ty::from_value(values.next().with_context(|| Error::StackPopError)?)?,)*))
+            }
+        }
+    };
+}
+
+impl_wasm_results!();
+impl_wasm_results!(A1);
+impl_wasm_results!(A1, A2);
+impl_wasm_results!(A1, A2, A3);
+impl_wasm_results!(A1, A2, A3, A4);
+
+macro_rules! impl_wasm_results_single {
+    ($($ty: ty),*) => {
+        $(
+            impl WasmResults for $ty {
+                fn value_types() -> Vec<ValueType> {
+                    vec![<$ty>::value_type()]
+                }
+
+                fn into_values(self) -> Result<Vec<Value>> {
+                    Ok(vec![self.into_value()])
+                }
+
+                fn from_values(values: Vec<Value>) -> Result<Self> {
+                    let value = values.into_iter().next().with_context(|| Error::StackPopError)?;
+                    <$ty>::from_value(value)
+                }
+            }
+        )*
+    };
+}
+
+impl_wasm_results_single!(i32, i64, f32, f64);
+
+type HostCallback = Box<dyn Fn(Caller, Vec<Value>) -> Result<Vec<Value>> + Send + Sync>;
+
+pub struct HostFunc {
+    func_type: FuncType,
+    callback: HostCallback,
+}
+
+// closure which can be registered as the host function,
+// the signature is inferred from the types of the arguments and the return value
+pub trait IntoHostFunc<Params, Results> {
+    fn into_host_func(self) -> HostFunc;
+}
+
+macro_rules! impl_into_host_func {
+    ($($param: ident),*) => {
+        impl<F, R, $($param),*> IntoHostFunc<($($param,)*), R> for F
+        where
+            F: Fn(Caller, $($param),*) -> R + Send + Sync + 'static,
+            R: WasmResults,
+            $($param: WasmTy,)*
+        {
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn into_host_func(self) -> HostFunc {
+                let func_type = FuncType {
+                    params: vec![$($param::value_type()),*],
+                    results: R::value_types(),
+                };
+                let callback = move |caller: Caller, args: Vec<Value>| {
+                    let mut args = args.into_iter();
+                    $(
+                        let $param = $param::from_value(args.next().with_context(|| Error::StackPopError)?)?;
+                    )*
+                    self(caller, $($param),*).into_values()
+                };
+                HostFunc {
+                    func_type,
+                    callback: Box::new(callback),
+                }
+            }
+        }
+    };
+}
+
+impl_into_host_func!();
+impl_into_host_func!(A1);
+impl_into_host_func!(A1, A2);
+impl_into_host_func!(A1, A2, A3);
+impl_into_host_func!(A1, A2, A3, A4);
+impl_into_host_func!(A1, A2, A3, A4, A5);
+impl_into_host_func!(A1, A2, A3, A4, A5, A6);
+impl_into_host_func!(A1, A2, A3, A4, A5, A6, A7);
+impl_into_host_func!(A1, A2, A3, A4, A5, A6, A7, A8);
+
+// importer of the functions defined by closures
+//
+//   let env = HostModule::new("env").func("add", |_: Caller, a: i32, b: i32| a + b);
+//   let runtime = Runtime::from_file("add.wasm", Some(vec![Box::new(env)]))?;
+pub struct HostModule {
+    name: String,
+    funcs: HashMap<String, HostFunc>,
+}
+
+impl HostModule {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            funcs: HashMap::new(),
+        }
+    }
+
+    pub fn func<Params, Results>(
+        mut self,
+        name: impl Into<String>,
+        func: impl IntoHostFunc<Params, Results>,
+    ) -> Self {
+        self.funcs.insert(name.into(), func.into_host_func());
+        self
+    }
+}
+
+impl Importer for HostModule {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn invoke(
+        &self,
+        store: Arc<Mutex<Store>>,
+        func: ExternalFuncInst,
+        args: Vec<Value>,
+    ) -> Result<Vec<Value>> {
+        self.invoke_with_caller(Caller::new(store), func, args)
+    }
+
+    fn invoke_with_caller(
+        &self,
+        caller: Caller,
+        func: ExternalFuncInst,
+        args: Vec<Value>,
+    ) -> Result<Vec<Value>> {
+        let host_func = self
+            .funcs
+            .get(&func.field)
+            .with_context(|| Error::NotFoundImportFunc(self.name.clone(), func.field.clone()))?;
+        (host_func.callback)(caller, args)
+    }
+
+    fn resolve_func(&self, module: &str, field: &str) -> Result<Option<FuncType>> {
+        let host_func = self
+            .funcs
+            .get(field)
+            .with_context(|| Error::NotFoundImportFunc(module.into(), field.into()))?;
+        Ok(Some(host_func.func_type.clone()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Caller, HostModule, Pod};
+    use crate::execution::{error::Error, module::ExternalFuncInst};
+    use crate::{Config, Importer, Runtime, Store, Value};
+    use anyhow::Result;
+    use std::sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    };
+
+    #[test]
+    fn host_module() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"(module
+              (import "env" "add" (func $add (param i32 i32) (result i32)))
+              (import "env" "log" (func $log (param i64)))
+              (import "env" "fail" (func $fail))
+              (func (export "run") (param i32) (result i32)
+                (call $log (i64.extend_i32_s (local.get 0)))
+                (call $add (local.get 0) (i32.const 2)))
+              (func (export "fail") (call $fail))
+            )"#,
+        )?;
+
+        let logged = Arc::new(AtomicI64::new(0));
+        let log = Arc::clone(&logged);
+        let env = HostModule::new("env")
+            .func("add", |_: Caller, a: i32, b: i32| a + b)
+            .func("log", move |_: Caller, v: i64| {
+                log.store(v, Ordering::Relaxed)
+            })
+            .func("fail", |_: Caller| -> Result<()> {
+                anyhow::bail!("failed in host")
+            });
+
+        let mut runtime = Runtime::from_bytes(&wasm, Some(vec![Box::new(env)]))?;
+        assert_eq!(
+            runtime.call("run".into(), vec![Value::I32(40)])?,
+            Some(Value::I32(42))
+        );
+        assert_eq!(logged.load(Ordering::Relaxed), 40);
+
+        let err = runtime.call("fail".into(), vec![]).unwrap_err();
+        assert_eq!(err.to_string(), "failed in host");
+        Ok(())
+    }
+
+    #[test]
+    fn host_module_signature_check() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"(module
+              (import "env" "add" (func (param i32 i32) (result i32))))"#,
+        )?;
+
+        let env = HostModule::new("env").func("add", |_: Caller, a: i64, b: i64| a + b);
+        let err = Runtime::from_bytes(&wasm, Some(vec![Box::new(env)])).err();
+        assert_eq!(
+            err.map(|e| e.to_string()),
+            Some("incompatible import type: env::add".into())
+        );
+
+        let env = HostModule::new("env").func("sub", |_: Caller, a: i32, b: i32| a - b);
+        let err = Runtime::from_bytes(&wasm, Some(vec![Box::new(env)])).err();
+        assert_eq!(
+            err.map(|e| e.to_string()),
+            Some("not found import function: env::add".into())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn host_results_check() -> Result<()> {
+        // importer which doesn't declare the signatures of the functions
+        struct Unchecked(HostModule);
+
+        impl Importer for Unchecked {
+            fn name(&self) -> &str {
+                self.0.name()
+            }
+
+            fn invoke(
+                &self,
+                store: Arc<Mutex<Store>>,
+                func: ExternalFuncInst,
+                args: Vec<Value>,
+            ) -> Result<Vec<Value>> {
+                self.0.invoke(store, func, args)
+            }
+        }
+
+        let wasm = wat::parse_str(
+            r#"(module
+              (import "env" "get" (func $get (result i32)))
+              (func (export "run") (result i32)
+                (i32.add (i32.const 1) (call $get))))"#,
+        )?;
+
+        let env = HostModule::new("env").func("get", |_: Caller| -> i64 { 1 });
+        let mut runtime = Runtime::from_bytes(&wasm, Some(vec![Box::new(Unchecked(env))]))?;
+        let err = runtime.call("run".into(), vec![]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "results of the host function env::get don't match the signature: \
+             expected [I32], actual [I64]"
+        );
+        assert!(runtime.stack.is_empty());
+
+        let env = HostModule::new("env").func("get", |_: Caller| {});
+        let mut runtime = Runtime::from_bytes(&wasm, Some(vec![Box::new(Unchecked(env))]))?;
+        let err = runtime.call("run".into(), vec![]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::HostResultMismatch(module, field, _)) if module == "env" && field == "get"
+        ));
+
+        let env = HostModule::new("env").func("get", |_: Caller| -> i32 { 1 });
+        let mut runtime = Runtime::from_bytes(&wasm, Some(vec![Box::new(Unchecked(env))]))?;
+        assert_eq!(runtime.call("run".into(), vec![])?, Some(2.into()));
+        Ok(())
+    }
+
+    #[test]
+    fn caller_memory_access() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"(module
+              (import "env" "hello" (func $hello (param i32 i32) (result i32)))
+              (import "env" "swap" (func $swap (param i32)))
+              (memory (export "memory") 1)
+              (global (export "answer") i32 (i32.const 42))
+              (data (i32.const 0) "hello\01\00\00\00\02\00\00\00")
+              (func (export "hello") (param i32 i32) (result i32)
+                (call $hello (local.get 0) (local.get 1)))
+              (func (export "swap") (result i32)
+                (call $swap (i32.const 5))
+                (i32.load (i32.const 5)))
+            )"#,
+        )?;
+
+        #[repr(C)]
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Pair {
+            a: u32,
+            b: u32,
+        }
+        unsafe impl Pod for Pair {
+            fn to_le(self) -> Self {
+                Self {
+                    a: self.a.to_le(),
+                    b: self.b.to_le(),
+                }
+            }
+        }
+
+        let env = HostModule::new("env")
+            .func(
+                "hello",
+                |caller: Caller, ptr: i32, len: i32| -> Result<i32> {
+                    let s = caller.read_string(ptr as u32, len as u32)?;
+                    let answer = caller.get_global("answer")?.lock().unwrap().value.clone();
+                    caller.write_bytes(16, format!("{s}, world").as_bytes())?;
+                    Ok(i32::from(answer) + s.len() as i32)
+                },
+            )
+            .func("swap", |caller: Caller, ptr: i32| -> Result<()> {
+                let pair: Pair = caller.read(ptr as u32)?;
+                caller.write(
+                    ptr as u32,
+                    &Pair {
+                        a: pair.b,
+                        b: pair.a,
+                    },
+                )
+            });
+
+        let mut runtime = Runtime::from_bytes(&wasm, Some(vec![Box::new(env)]))?;
+        let result = runtime.call("hello".into(), vec![0.into(), 5.into()])?;
+        assert_eq!(result, Some(47.into()));
+        assert_eq!(runtime.call("swap".into(), vec![])?, Some(2.into()));
+
+        let store = runtime.store.clone();
+        let caller = Caller::new(store);
+        assert_eq!(caller.read_string(16, 12)?, "hello, world");
+        assert_eq!(caller.read_slice::<u32>(5, 2)?, vec![2, 1]);
+        caller.write_slice(100, &[7u16, 8])?;
+        assert_eq!(caller.read::<[u8; 4]>(100)?, [7, 0, 8, 0]);
+        caller.write(100, &1.5f32)?;
+        assert_eq!(caller.read_bytes(100, 4)?, 1.5f32.to_le_bytes());
+        caller.write(100, &0x0102_0304u32)?;
+        assert_eq!(caller.read_bytes(100, 4)?, [4, 3, 2, 1]);
+
+        let err = runtime
+            .call("hello".into(), vec![65534.into(), 5.into()])
+            .unwrap_err();
+        assert_eq!(err.to_string(), "out of bounds memory access");
+        assert!(caller.read::<u64>(65530).is_err());
+        assert!(caller.read_slice::<u32>(u32::MAX, 2).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn reentrant_call() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"(module
+              (import "env" "greeting" (func $greeting (result i32)))
+              (import "env" "fact" (func $host_fact (param i32) (result i32)))
+              (memory (export "memory") 1)
+              (global $heap (mut i32) (i32.const 1024))
+              (func (export "malloc") (param i32) (result i32)
+                (global.get $heap)
+                (global.set $heap (i32.add (global.get $heap) (local.get 0))))
+              (func (export "greeting") (result i32)
+                (i32.load8_u (call $greeting)))
+              (func (export "fact") (param i32) (result i32)
+                (if (result i32) (i32.eqz (local.get 0))
+                  (then (i32.const 1))
+                  (else (i32.mul (local.get 0)
+                    (call $host_fact (i32.sub (local.get 0) (i32.const 1)))))))
+            )"#,
+        )?;
+        let env = || {
+            HostModule::new("env")
+                .func("greeting", |caller: Caller| -> Result<i32> {
+                    let ptr = caller.call("malloc", vec![5.into()])?[0].clone();
+                    caller.write_bytes(i32::from(ptr.clone()) as u32, b"hello")?;
+                    Ok(ptr.into())
+                })
+                .func("fact", |caller: Caller, n: i32| -> Result<i32> {
+                    Ok(caller.call("fact", vec![n.into()])?[0].clone().into())
+                })
+        };
+
+        let mut runtime = Runtime::from_bytes(&wasm, Some(vec![Box::new(env())]))?;
+        assert_eq!(runtime.call("greeting".into(), vec![])?, Some(104.into()));
+        assert_eq!(
+            runtime.call("malloc".into(), vec![0.into()])?,
+            Some(1029.into())
+        );
+        assert_eq!(
+            runtime.call("fact".into(), vec![5.into()])?,
+            Some(120.into())
+        );
+
+        // the limits and the fuel are shared with the nested calls
+        let store = Store::from_bytes(&wasm, Some(vec![Box::new(env())]))?;
+        let config = Config::new().max_call_depth(10).consume_fuel(true);
+        let mut runtime = Runtime::instantiate_with_config(Arc::new(Mutex::new(store)), config)?;
+        runtime.add_fuel(u64::MAX)?;
+        let mut consumed = vec![];
+        for n in 0..3 {
+            let fuel = runtime.fuel().unwrap_or_default();
+            runtime.call("fact".into(), vec![n.into()])?;
+            consumed.push(fuel - runtime.fuel().unwrap_or_default());
+        }
+        assert_eq!(consumed[2] - consumed[1], consumed[1] - consumed[0]);
+        assert!(consumed[1] > consumed[0]);
+
+        assert_eq!(
+            runtime.call("fact".into(), vec![9.into()])?,
+            Some(362880.into())
+        );
+        let err = runtime.call("fact".into(), vec![10.into()]).unwrap_err();
+        assert_eq!(err.to_string(), "call stack exhausted");
+        Ok(())
+    }
+}
@@ -1,29 +1,42 @@
 use crate::{
-    module::{ExternalFuncInst, FuncInst, GlobalInst, InternalMemoryInst, InternalTableInst},
-    Store, Value,
+    binary::types::FuncType,
+    module::{ExternalFuncInst, GlobalInst, InternalMemoryInst, InternalTableInst},
+    Caller, Store, Value,
 };
 use anyhow::Result;
-use std::{cell::RefCell, rc::Rc};
+use std::sync::{Arc, Mutex};
 
-pub trait Importer {
+// NOTE: the importer is shared by the instances which may run on other threads
+pub trait Importer: Send + Sync {
     fn name(&self) -> &str;
 
-    fn get(&self, _name: &str) -> Result<Option<Rc<RefCell<Store>>>> {
+    fn get(&self, _name: &str) -> Result<Option<Arc<Mutex<Store>>>> {
         Ok(None)
     }
 
     fn invoke(
         &self,
-        store: Rc<RefCell<Store>>,
+        store: Arc<Mutex<Store>>,
         func: ExternalFuncInst,
         args: Vec<Value>,
-    ) -> Result<Option<Value>>;
+    ) -> Result<Vec<Value>>;
+
+    // same as `invoke`, but the caller can call back into the guest.
+    // the store of the caller is passed to `invoke` by default.
+    fn invoke_with_caller(
+        &self,
+        caller: Caller,
+        func: ExternalFuncInst,
+        args: Vec<Value>,
+    ) -> Result<Vec<Value>> {
+        self.invoke(caller.store(), func, args)
+    }
 
     fn resolve_table(
         &self,
         _module: &str,
         _field: &str,
-    ) -> Result<Option<Rc<RefCell<InternalTableInst>>>> {
+    ) -> Result<Option<Arc<Mutex<InternalTableInst>>>> {
         Ok(None)
     }
 
@@ -31,7 +44,9 @@ pub trait Importer {
         Ok(None)
     }
 
-    fn resolve_func(&self, _module: &str, _field: &str) -> Result<Option<FuncInst>> {
+    // signature of the function, the import is rejected if it doesn't match.
+    // `None` means the importer doesn't check the signature
+    fn resolve_func(&self, _module: &str, _field: &str) -> Result<Option<FuncType>> {
         Ok(None)
     }
 
@@ -39,7 +54,7 @@ pub trait Importer {
         &self,
         _name: &str,
         _field: &str,
-    ) -> Result<Option<Rc<RefCell<InternalMemoryInst>>>> {
+    ) -> Result<Option<Arc<Mutex<InternalMemoryInst>>>> {
         Ok(None)
     }
 }
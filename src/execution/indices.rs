@@ -3,3 +3,12 @@ pub type FuncIdx = u32;
 pub type TableIdx = u32;
 pub type MemoryIdx = u32;
 pub type GlobalIdx = u32;
+
+// https://webassembly.github.io/spec/core/exec/runtime.html#addresses
+pub type FuncAddr = usize;
+pub type TableAddr = usize;
+pub type MemAddr = usize;
+pub type GlobalAddr = usize;
+pub type ElemAddr = usize;
+pub type DataAddr = usize;
+pub type InstanceAddr = usize;
@@ -1,4 +1,4 @@
-use super::error::Error;
+use super::error::TrapCode;
 use crate::{ibinop, irelop, itestop, iunop};
 use anyhow::{bail, Result};
 
@@ -0,0 +1,67 @@
+use super::error::TrapCode;
+use anyhow::{bail, Result};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+// handle to interrupt the guest from another thread, e.g. a watchdog of the timeout.
+// the guest checks the epoch at the next loop back-edge or function entry.
+#[derive(Debug, Clone)]
+pub struct InterruptHandle {
+    epoch: Arc<AtomicU64>,
+}
+
+impl InterruptHandle {
+    pub fn increment_epoch(&self) {
+        self.epoch.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::Relaxed)
+    }
+}
+
+// called when the deadline is reached, returns the epochs to extend the deadline
+// to continue the execution, or an error to stop it
+pub type EpochDeadlineCallback = Arc<Mutex<dyn FnMut() -> Result<u64> + Send>>;
+
+#[derive(Default, Clone)]
+pub(crate) struct Epoch {
+    counter: Arc<AtomicU64>,
+    deadline: Option<u64>,
+    callback: Option<EpochDeadlineCallback>,
+}
+
+impl Epoch {
+    pub(crate) fn handle(&self) -> InterruptHandle {
+        InterruptHandle {
+            epoch: Arc::clone(&self.counter),
+        }
+    }
+
+    pub(crate) fn set_deadline(&mut self, delta: u64) {
+        let current = self.counter.load(Ordering::Relaxed);
+        self.deadline = Some(current.saturating_add(delta));
+    }
+
+    pub(crate) fn set_callback(&mut self, callback: EpochDeadlineCallback) {
+        self.callback = Some(callback);
+    }
+
+    pub(crate) fn check(&mut self) -> Result<()> {
+        let Some(deadline) = self.deadline else {
+            return Ok(());
+        };
+        let current = self.counter.load(Ordering::Relaxed);
+        if current < deadline {
+            return Ok(());
+        }
+        let Some(callback) = &self.callback else {
+            bail!(TrapCode::Interrupted);
+        };
+        let delta = (callback.lock().expect("cannot lock callback"))()?;
+        self.deadline = Some(current.saturating_add(delta));
+        Ok(())
+    }
+}
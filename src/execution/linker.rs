@@ -0,0 +1,253 @@
+use super::{
+    compiled::Module, config::Config, error::Error, indices::InstanceAddr, runtime::Runtime,
+    store::Store,
+};
+use crate::Importer;
+use anyhow::{bail, Result};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+// resolves the imports of the module against the named instances and the host modules.
+// the instances must live in the store which the module is instantiated in,
+// so they share the functions, tables, memories and globals.
+//
+//   let store = Arc::new(Mutex::new(Store::default()));
+//   let lib = Linker::new().instantiate(&store, &lib_module)?;
+//   let mut linker = Linker::new();
+//   linker.instance("lib", &lib);
+//   let mut app = linker.instantiate(&store, &app_module)?;
+#[derive(Default, Clone)]
+pub struct Linker {
+    instances: HashMap<String, (Arc<Mutex<Store>>, InstanceAddr)>,
+    hosts: Vec<Arc<dyn Importer>>,
+}
+
+impl Linker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // host module like WASI, the name of the importer is used as the module name
+    pub fn host(&mut self, importer: impl Importer + 'static) -> &mut Self {
+        self.hosts.push(Arc::new(importer));
+        self
+    }
+
+    // register the instance of the runtime by the module name
+    pub fn instance(&mut self, name: impl Into<String>, runtime: &Runtime) -> &mut Self {
+        let entry = (Arc::clone(&runtime.store), runtime.instance());
+        self.instances.insert(name.into(), entry);
+        self
+    }
+
+    pub fn instantiate(&self, store: &Arc<Mutex<Store>>, module: &Module) -> Result<Runtime> {
+        self.instantiate_with_config(store, module, Config::default())
+    }
+
+    // https://webassembly.github.io/spec/core/exec/modules.html#exec-instantiation
+    pub fn instantiate_with_config(
+        &self,
+        store: &Arc<Mutex<Store>>,
+        module: &Module,
+        config: Config,
+    ) -> Result<Runtime> {
+        let mut instances = HashMap::with_capacity(self.instances.len());
+        for (name, (instance_store, addr)) in self.instances.iter() {
+            if !Arc::ptr_eq(instance_store, store) {
+                bail!(Error::ForeignInstance(name.clone()));
+            }
+            instances.insert(name.clone(), *addr);
+        }
+
+        let hosts = self
+            .hosts
+            .iter()
+            .map(|host| (host.name().to_string(), Arc::clone(host)))
+            .collect();
+
+        let instance = {
+            let mut store = store.lock().expect("cannot lock store");
+            let externs = store.resolve_imports(module, &instances, &hosts)?;
+            store.instantiate(module, externs)?
+        };
+        Runtime::bind(Arc::clone(store), instance, config)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Linker;
+    use crate::{Exports, HostModule, Module, Store, Value};
+    use anyhow::Result;
+    use std::sync::{Arc, Mutex};
+
+    const LIB: &str = r#"(module
+      (memory (export "memory") 1)
+      (global (export "counter") (mut i32) (i32.const 0))
+      (table (export "table") 2 funcref)
+      (func $double (export "double") (param i32) (result i32)
+        (i32.mul (local.get 0) (i32.const 2)))
+      (func (export "incr") (result i32)
+        (global.set 0 (i32.add (global.get 0) (i32.const 1)))
+        (global.get 0))
+      (elem (i32.const 0) $double)
+    )"#;
+
+    const APP: &str = r#"(module
+      (import "lib" "memory" (memory 1))
+      (import "lib" "counter" (global (mut i32)))
+      (import "lib" "table" (table 2 funcref))
+      (import "lib" "double" (func $double (param i32) (result i32)))
+      (import "env" "add" (func $add (param i32 i32) (result i32)))
+      (type $unary (func (param i32) (result i32)))
+      (func (export "store") (param i32 i32)
+        (i32.store (local.get 0) (local.get 1)))
+      (func (export "load") (param i32) (result i32)
+        (i32.load (local.get 0)))
+      (func (export "counter") (result i32) (global.get 0))
+      (func (export "call_double") (param i32) (result i32)
+        (call $double (local.get 0)))
+      (func (export "call_table") (param i32 i32) (result i32)
+        (call_indirect (type $unary) (local.get 1) (local.get 0)))
+      (func (export "add") (param i32 i32) (result i32)
+        (call $add (local.get 0) (local.get 1)))
+    )"#;
+
+    fn env() -> HostModule {
+        HostModule::new("env").func("add", |_, a: i32, b: i32| a + b)
+    }
+
+    #[test]
+    fn link_instances() -> Result<()> {
+        let lib = Module::from_bytes(wat::parse_str(LIB)?)?;
+        let app = Module::from_bytes(wat::parse_str(APP)?)?;
+
+        let store = Arc::new(Mutex::new(Store::default()));
+        let mut lib = Linker::new().instantiate(&store, &lib)?;
+
+        let mut linker = Linker::new();
+        linker.instance("lib", &lib).host(env());
+        let mut app1 = linker.instantiate(&store, &app)?;
+        let mut app2 = linker.instantiate(&store, &app)?;
+        assert_eq!(store.lock().unwrap().instances.len(), 3);
+
+        // the memory and the global are shared by all the instances
+        app1.call("store".into(), vec![8.into(), 42.into()])?;
+        assert_eq!(app2.call("load".into(), vec![8.into()])?, Some(42.into()));
+        let Exports::Memory(memory) = lib.exports("memory".into())? else {
+            panic!("memory is not exported");
+        };
+        assert_eq!(memory.lock().unwrap().data[8], 42);
+
+        lib.call("incr".into(), vec![])?;
+        lib.call("incr".into(), vec![])?;
+        assert_eq!(app1.call("counter".into(), vec![])?, Some(2.into()));
+        assert_eq!(app2.call("counter".into(), vec![])?, Some(2.into()));
+
+        // the functions are called in the instance which defines them
+        let result = app1.call("call_double".into(), vec![21.into()])?;
+        assert_eq!(result, Some(42.into()));
+        let result = app2.call("call_table".into(), vec![0.into(), 5.into()])?;
+        assert_eq!(result, Some(10.into()));
+        let err = app2
+            .call("call_table".into(), vec![1.into(), 5.into()])
+            .unwrap_err();
+        assert_eq!(err.to_string(), "uninitialized element 1");
+
+        assert_eq!(
+            app1.call("add".into(), vec![1.into(), 2.into()])?,
+            Some(Value::I32(3))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn host_binding() -> Result<()> {
+        let lib = Module::from_bytes(wat::parse_str(LIB)?)?;
+        let app = Module::from_bytes(wat::parse_str(APP)?)?;
+
+        let store = Arc::new(Mutex::new(Store::default()));
+        let lib = Linker::new().instantiate(&store, &lib)?;
+        let mut linker = Linker::new();
+        linker.instance("lib", &lib).host(env());
+        let mut app1 = linker.instantiate(&store, &app)?;
+
+        // another "env" doesn't rebind the imports of the existing instance
+        let mut linker = Linker::new();
+        let sub = HostModule::new("env").func("add", |_, a: i32, b: i32| a - b);
+        linker.instance("lib", &lib).host(sub);
+        let mut app2 = linker.instantiate(&store, &app)?;
+
+        let args = vec![5.into(), 3.into()];
+        assert_eq!(app1.call("add".into(), args.clone())?, Some(8.into()));
+        assert_eq!(app2.call("add".into(), args)?, Some(2.into()));
+        Ok(())
+    }
+
+    #[test]
+    fn unlinkable() -> Result<()> {
+        let lib = Module::from_bytes(wat::parse_str(LIB)?)?;
+        let app = Module::from_bytes(wat::parse_str(
+            r#"(module (import "lib" "double" (func (param i64) (result i64))))"#,
+        )?)?;
+
+        let store = Arc::new(Mutex::new(Store::default()));
+        let lib = Linker::new().instantiate(&store, &lib)?;
+        let mut linker = Linker::new();
+        linker.instance("lib", &lib);
+
+        let err = linker
+            .instantiate(&store, &app)
+            .err()
+            .map(|e| e.to_string());
+        assert_eq!(err, Some("incompatible import type: lib::double".into()));
+
+        let other = Arc::new(Mutex::new(Store::default()));
+        let err = linker
+            .instantiate(&other, &app)
+            .err()
+            .map(|e| e.to_string());
+        assert_eq!(err, Some("instance 'lib' belongs to another store".into()));
+
+        let app = Module::from_bytes(wat::parse_str(
+            r#"(module (import "lib" "unknown" (func)))"#,
+        )?)?;
+        let err = linker
+            .instantiate(&store, &app)
+            .err()
+            .map(|e| e.to_string());
+        assert_eq!(err, Some("unknown import: lib::unknown".into()));
+
+        // the tables, memories and globals must be the subtype of the imports
+        let imports = [
+            (r#"(global (mut f64))"#, "counter"),
+            (r#"(global i32)"#, "counter"),
+            (r#"(table 3 funcref)"#, "table"),
+            (r#"(table 1 externref)"#, "table"),
+            (r#"(table 1 10 funcref)"#, "table"),
+            (r#"(memory 2)"#, "memory"),
+            (r#"(memory 1 2)"#, "memory"),
+        ];
+        for (import, field) in imports {
+            let source = format!(r#"(module (import "lib" "{field}" {import}))"#);
+            let app = Module::from_bytes(wat::parse_str(&source)?)?;
+            let err = linker
+                .instantiate(&store, &app)
+                .err()
+                .map(|e| e.to_string());
+            let expected = format!("incompatible import type: lib::{field}");
+            assert_eq!(err, Some(expected), "{source}");
+        }
+
+        let app = Module::from_bytes(wat::parse_str(
+            r#"(module
+              (import "lib" "counter" (global (mut i32)))
+              (import "lib" "table" (table 1 funcref))
+              (import "lib" "memory" (memory 0)))"#,
+        )?)?;
+        linker.instantiate(&store, &app)?;
+        Ok(())
+    }
+}
@@ -1,24 +1,18 @@
 #[macro_export]
 macro_rules! load {
-    ($stack: expr, $store: expr, $ty: ty, $arg: expr) => {{
-        let store = $store.borrow();
-        let memory = store
-            .memory
-            .get(0)
-            .with_context(|| Error::NotFoundMemory(0))?;
-        let memory = memory.borrow();
-        let addr = $stack.pop1::<i32>()? as usize;
+    ($stack: expr, $store: expr, $inst: expr, $ty: ty, $arg: expr) => {{
+        let store = $store.lock().expect("cannot lock store");
+        let memory = store.get_memory($inst, 0)?;
+        let memory = memory.lock().expect("cannot lock memory");
+        let addr = $stack.pop1::<i32>()? as u32 as usize;
         let value = memory.load::<$ty>(addr, $arg)?;
         $stack.push(value.into());
     }};
-    ($stack: expr, $store: expr, $ty: ty, $arg: expr, $tz: ty) => {{
-        let addr = $stack.pop1::<i32>()? as usize;
-        let store = $store.borrow();
-        let memory = store
-            .memory
-            .get(0)
-            .with_context(|| Error::NotFoundMemory(0))?;
-        let memory = memory.borrow();
+    ($stack: expr, $store: expr, $inst: expr, $ty: ty, $arg: expr, $tz: ty) => {{
+        let addr = $stack.pop1::<i32>()? as u32 as usize;
+        let store = $store.lock().expect("cannot lock store");
+        let memory = store.get_memory($inst, 0)?;
+        let memory = memory.lock().expect("cannot lock memory");
         let value = memory.load::<$ty>(addr, $arg)? as $tz;
         $stack.push(value.into());
     }};
@@ -26,26 +20,20 @@ macro_rules! load {
 
 #[macro_export]
 macro_rules! store {
-    ($stack: expr, $store: expr, $ty: ty, $arg: expr) => {{
-        let store = $store.borrow();
-        let memory = store
-            .memory
-            .get(0)
-            .with_context(|| Error::NotFoundMemory(0))?;
-        let mut memory = memory.borrow_mut();
+    ($stack: expr, $store: expr, $inst: expr, $ty: ty, $arg: expr) => {{
+        let store = $store.lock().expect("cannot lock store");
+        let memory = store.get_memory($inst, 0)?;
+        let mut memory = memory.lock().expect("cannot lock memory");
         let value = $stack.pop1::<$ty>()?;
-        let addr = $stack.pop1::<i32>()? as usize;
+        let addr = $stack.pop1::<i32>()? as u32 as usize;
         memory.write(addr, $arg, value)?;
     }};
-    ($stack: expr, $store: expr, $ty: ty, $arg: expr, $tz: ty) => {{
-        let store = $store.borrow();
-        let memory = store
-            .memory
-            .get(0)
-            .with_context(|| Error::NotFoundMemory(0))?;
-        let mut memory = memory.borrow_mut();
+    ($stack: expr, $store: expr, $inst: expr, $ty: ty, $arg: expr, $tz: ty) => {{
+        let store = $store.lock().expect("cannot lock store");
+        let memory = store.get_memory($inst, 0)?;
+        let mut memory = memory.lock().expect("cannot lock memory");
         let value = $stack.pop1::<$ty>()? as $tz;
-        let addr = $stack.pop1::<i32>()? as usize;
+        let addr = $stack.pop1::<i32>()? as u32 as usize;
         memory.write(addr, $arg, value)?;
     }};
 }
@@ -243,16 +231,16 @@ macro_rules! ibinop {
         }
         fn div_s(&self, rhs: Self) -> Result<Self> {
             if rhs == 0 {
-                bail!(Error::IntegerDivideByZero);
+                bail!(TrapCode::IntegerDivideByZero);
             }
             match self.checked_div(rhs) {
                 Some(v) => Ok(v),
-                None => bail!(Error::DivisionOverflow),
+                None => bail!(TrapCode::IntegerOverflow),
             }
         }
         fn rem_s(&self, rhs: Self) -> Result<Self> {
             if rhs == 0 {
-                bail!(Error::IntegerDivideByZero);
+                bail!(TrapCode::IntegerDivideByZero);
             }
             Ok(self.wrapping_rem(rhs) as Self)
         }
@@ -283,13 +271,13 @@ macro_rules! ibinop {
             ibinop!();
             fn div_u(&self, rhs: Self) -> Result<Self> {
                 if rhs == 0 {
-                    bail!(Error::IntegerDivideByZero);
+                    bail!(TrapCode::IntegerDivideByZero);
                 }
                 Ok(u32::wrapping_div(*self as u32, rhs as u32) as Self)
             }
             fn rem_u(&self, rhs: Self) -> Result<Self> {
                 if rhs == 0 {
-                    bail!(Error::IntegerDivideByZero);
+                    bail!(TrapCode::IntegerDivideByZero);
                 }
                 Ok((*self as u32).wrapping_rem(rhs as u32) as Self)
             }
@@ -303,13 +291,13 @@ macro_rules! ibinop {
             ibinop!();
             fn div_u(&self, rhs: Self) -> Result<Self> {
                 if rhs == 0 {
-                    bail!(Error::IntegerDivideByZero);
+                    bail!(TrapCode::IntegerDivideByZero);
                 }
                 Ok(u64::wrapping_div(*self as u64, rhs as u64) as Self)
             }
             fn rem_u(&self, rhs: Self) -> Result<Self> {
                 if rhs == 0 {
-                    bail!(Error::IntegerDivideByZero);
+                    bail!(TrapCode::IntegerDivideByZero);
                 }
                 Ok((*self as u64).wrapping_rem(rhs as u64) as Self)
             }
@@ -1,16 +1,34 @@
+pub mod compiled;
+pub mod config;
 pub mod error;
 pub(crate) mod float;
+pub mod host;
 pub mod importer;
 pub(crate) mod indices;
 pub(crate) mod integer;
+pub mod interrupt;
+pub mod linker;
 mod macros;
 pub mod module;
 pub(crate) mod op;
+pub mod resumable;
 pub mod runtime;
+pub(crate) mod side_table;
+pub mod snapshot;
 pub mod store;
+pub mod typed;
 pub mod value;
 
+pub use compiled::Module;
+pub use config::Config;
+pub use error::{FrameInfo, Trap, TrapCode};
+pub use host::{Caller, HostModule, Pod};
 pub use importer::*;
+pub use interrupt::{EpochDeadlineCallback, InterruptHandle};
+pub use linker::Linker;
+pub use resumable::{CallResult, ResumableCall};
 pub use runtime::*;
+pub use snapshot::{Snapshot, SnapshotValue};
 pub use store::*;
+pub use typed::TypedFunc;
 pub use value::*;
@@ -1,56 +1,118 @@
-use super::indices::TypeIdx;
+use super::indices::*;
+use super::side_table::SideTable;
 use super::value::{ExternalVal, Numeric, Value};
 use crate::binary::instruction::{Instruction, MemoryArg};
 use crate::binary::module::Module;
 use crate::binary::types::{FuncType, ValueType};
-use crate::execution::error::Error;
-use anyhow::{bail, Result};
-use std::cell::RefCell;
+use crate::execution::error::{Error, TrapCode};
+use crate::Importer;
+use anyhow::{bail, Context as _, Result};
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 
 // https://www.w3.org/TR/wasm-core-1/#memory-instances%E2%91%A0
 pub const PAGE_SIZE: u32 = 65536; // 64Ki
+pub const MAX_PAGES: u32 = 65536; // 4GiB
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Func {
+    pub instance: InstanceAddr, // module instance which the function belongs to
+    pub func_idx: FuncIdx,      // index in the function index space of the module
     pub type_idx: TypeIdx,
     pub locals: Vec<ValueType>,
-    pub body: Vec<Instruction>,
+    pub body: Arc<Vec<Instruction>>,
+    pub side_table: Arc<SideTable>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct InternalFuncInst {
     pub func_type: FuncType,
     pub code: Func,
 }
 
-#[derive(Debug, Clone)]
+// the importer is bound when the module is instantiated,
+// so registering another importer by the same name doesn't change the function
+#[derive(Clone)]
 pub struct ExternalFuncInst {
     pub module: String,
     pub field: String, // function name
     pub func_type: FuncType,
+    pub importer: Arc<dyn Importer>,
 }
 
-#[derive(Debug, Clone)]
+impl std::fmt::Debug for ExternalFuncInst {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExternalFuncInst")
+            .field("module", &self.module)
+            .field("field", &self.field)
+            .field("func_type", &self.func_type)
+            .finish()
+    }
+}
+
+impl PartialEq for ExternalFuncInst {
+    fn eq(&self, other: &Self) -> bool {
+        self.module == other.module
+            && self.field == other.field
+            && self.func_type == other.func_type
+            && Arc::ptr_eq(&self.importer, &other.importer)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum FuncInst {
     Internal(InternalFuncInst),
     External(ExternalFuncInst),
 }
 
-#[derive(Debug, Clone, Default)]
+impl FuncInst {
+    pub fn func_type(&self) -> &FuncType {
+        match self {
+            Self::Internal(func) => &func.func_type,
+            Self::External(func) => &func.func_type,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct InternalTableInst {
-    pub funcs: Vec<Option<FuncInst>>,
+    pub elem_type: ValueType,
+    pub elements: Vec<Value>, // funcref or externref values
     pub max: Option<u32>,
 }
-pub type TableInst = Rc<RefCell<InternalTableInst>>;
+pub type TableInst = Arc<Mutex<InternalTableInst>>;
+
+impl InternalTableInst {
+    // copy the n elements from src[s..] to the table[d..]
+    // https://webassembly.github.io/spec/core/exec/instructions.html#exec-table-init
+    pub fn init(&mut self, d: usize, src: &[Value], s: usize, n: usize) -> Result<()> {
+        let in_bounds = |start: usize, len| start.checked_add(n).is_some_and(|end| end <= len);
+        if !(in_bounds(s, src.len()) && in_bounds(d, self.elements.len())) {
+            bail!(TrapCode::TableOutOfBounds);
+        }
+        self.elements[d..d + n].clone_from_slice(&src[s..s + n]);
+        Ok(())
+    }
+}
+
+// https://webassembly.github.io/spec/core/exec/runtime.html#element-instances
+#[derive(Debug, Clone, Default)]
+pub struct ElemInst {
+    pub elements: Vec<Value>, // become empty when the segment is dropped
+}
+
+// https://webassembly.github.io/spec/core/exec/runtime.html#data-instances
+#[derive(Debug, Clone, Default)]
+pub struct DataInst {
+    pub data: Vec<u8>, // become empty when the segment is dropped
+}
 
 #[derive(Default, Debug, Clone)]
 pub struct InternalMemoryInst {
     pub data: Vec<u8>,
     pub max: Option<u32>,
 }
-pub type MemoryInst = Rc<RefCell<InternalMemoryInst>>;
+pub type MemoryInst = Arc<Mutex<InternalMemoryInst>>;
 
 impl InternalMemoryInst {
     pub fn size(&self) -> usize {
@@ -60,39 +122,79 @@ impl InternalMemoryInst {
     // https://www.w3.org/TR/wasm-core-1/#grow-mem
     pub fn grow(&mut self, grow_size: u32) -> Result<()> {
         let size = self.size() as u32;
-        if size % PAGE_SIZE != 0 {
-            bail!(Error::MemorySizeNotPageAligned(PAGE_SIZE));
-        }
-        let len = size + grow_size;
-        if let Some(max) = self.max {
-            if max < len {
-                bail!(Error::MemoryPageOverflow(max, len));
-            }
+        // the memory can't be larger than 4GiB even if the max is not specified
+        let max = self.max.unwrap_or(MAX_PAGES).min(MAX_PAGES);
+        let len = size.saturating_add(grow_size);
+        if max < len {
+            bail!(Error::MemoryPageOverflow(max, len));
         }
-        self.data.resize((len * PAGE_SIZE) as usize, 0);
+        self.data.resize(len as usize * PAGE_SIZE as usize, 0);
         Ok(())
     }
 
+    // the effective address is the sum of the address operand and the static offset,
+    // both are u32, so it's computed without wrapping around.
+    // NOTE: the alignment in the memarg is only a hint, an unaligned access must not trap.
+    // https://webassembly.github.io/spec/core/exec/instructions.html#exec-load
+    fn effective_address(addr: usize, arg: &MemoryArg) -> Result<usize> {
+        addr.checked_add(arg.offset as usize)
+            .with_context(|| TrapCode::MemoryOutOfBounds)
+    }
+
     pub fn load<T: Numeric>(&self, addr: usize, arg: &MemoryArg) -> Result<T> {
-        // TODO: check align and memory size
-        let at = addr + arg.offset as usize;
+        let at = Self::effective_address(addr, arg)?;
         Numeric::read(&self.data, at)
     }
 
     pub fn write<T: Numeric>(&mut self, addr: usize, arg: &MemoryArg, value: T) -> Result<()> {
-        // TODO: check align and memory size
-        let at = addr + arg.offset as usize;
+        let at = Self::effective_address(addr, arg)?;
         Numeric::write(&mut self.data, at, value)
     }
 
+    pub fn read_bytes(&self, addr: usize, len: usize) -> Result<&[u8]> {
+        addr.checked_add(len)
+            .and_then(|end| self.data.get(addr..end))
+            .with_context(|| TrapCode::MemoryOutOfBounds)
+    }
+
+    pub fn bytes_mut(&mut self, addr: usize, len: usize) -> Result<&mut [u8]> {
+        addr.checked_add(len)
+            .and_then(|end| self.data.get_mut(addr..end))
+            .with_context(|| TrapCode::MemoryOutOfBounds)
+    }
+
+    // https://webassembly.github.io/spec/core/exec/instructions.html#exec-memory-copy
+    pub fn copy(&mut self, dst: usize, src: usize, n: usize) -> Result<()> {
+        self.read_bytes(src, n)?;
+        self.bytes_mut(dst, n)?;
+        self.data.copy_within(src..src + n, dst);
+        Ok(())
+    }
+
+    // https://webassembly.github.io/spec/core/exec/instructions.html#exec-memory-fill
+    pub fn fill(&mut self, dst: usize, value: u8, n: usize) -> Result<()> {
+        self.bytes_mut(dst, n)?.fill(value);
+        Ok(())
+    }
+
+    // copy the n bytes from src[s..] to the memory[d..]
+    // https://webassembly.github.io/spec/core/exec/instructions.html#exec-memory-init
+    pub fn init(&mut self, d: usize, src: &[u8], s: usize, n: usize) -> Result<()> {
+        let in_bounds = |start: usize, len| start.checked_add(n).is_some_and(|end| end <= len);
+        if !(in_bounds(s, src.len()) && in_bounds(d, self.data.len())) {
+            bail!(TrapCode::MemoryOutOfBounds);
+        }
+        self.data[d..d + n].copy_from_slice(&src[s..s + n]);
+        Ok(())
+    }
+
     pub fn write_bytes(&mut self, addr: usize, bytes: &[u8]) -> Result<()> {
-        let slice = &mut self.data[addr..addr + bytes.len()];
-        slice.copy_from_slice(bytes);
+        self.bytes_mut(addr, bytes.len())?.copy_from_slice(bytes);
         Ok(())
     }
 }
 
-pub type GlobalInst = Rc<RefCell<InternalGlobalInst>>;
+pub type GlobalInst = Arc<Mutex<InternalGlobalInst>>;
 
 #[derive(Debug, Clone)]
 pub struct InternalGlobalInst {
@@ -106,10 +208,19 @@ pub struct ExportInst {
     pub desc: ExternalVal,
 }
 
+// the indices of the module are mapped to the addresses in the store
+// https://webassembly.github.io/spec/core/exec/runtime.html#module-instances
 #[derive(Debug, Default, Clone)]
 pub struct ModuleInst {
     pub func_types: Vec<FuncType>,
+    pub func_addrs: Vec<FuncAddr>,
+    pub table_addrs: Vec<TableAddr>,
+    pub mem_addrs: Vec<MemAddr>,
+    pub global_addrs: Vec<GlobalAddr>,
+    pub elem_addrs: Vec<ElemAddr>,
+    pub data_addrs: Vec<DataAddr>,
     pub exports: HashMap<String, ExportInst>,
+    pub start: Option<u32>,
 }
 
 impl ModuleInst {
@@ -149,6 +260,8 @@ impl ModuleInst {
         ModuleInst {
             func_types,
             exports,
+            start: module.start_section,
+            ..Default::default()
         }
     }
 }
@@ -1,16 +1,17 @@
 use super::{
-    module::{ExternalFuncInst, InternalFuncInst},
+    config::Config,
+    indices::InstanceAddr,
+    module::{InternalFuncInst, TableInst},
     store::Store,
     value::{Frame, Label, LabelKind, StackAccess, Value},
 };
 use crate::{
-    binary::{instruction::Instruction, types::ValueType},
-    execution::error::Error,
+    execution::error::{Error, TrapCode},
     impl_binary_operation, impl_cvtop_operation, impl_unary_operation,
 };
 use anyhow::{bail, Context as _, Result};
 use log::trace;
-use std::{cell::RefCell, rc::Rc};
+use std::sync::Arc;
 
 pub fn local_get(locals: &[Value], stack: &mut impl StackAccess, idx: usize) -> Result<()> {
     let value = locals
@@ -34,23 +35,206 @@ pub fn local_tee(locals: &mut [Value], stack: &mut impl StackAccess, idx: usize)
     Ok(())
 }
 
-pub fn global_set(store: &mut Store, stack: &mut impl StackAccess, idx: usize) -> Result<()> {
+pub fn global_set(
+    store: &Store,
+    inst: InstanceAddr,
+    stack: &mut impl StackAccess,
+    idx: usize,
+) -> Result<()> {
     let value: Value = stack.pop1().with_context(|| Error::StackPopError)?;
     let mut global = store
-        .globals
-        .get(idx)
-        .with_context(|| Error::NotFoundGlobalVariable(idx))?
-        .borrow_mut();
+        .get_global(inst, idx)?
+        .lock()
+        .expect("cannot lock global");
     global.value = value;
     Ok(())
 }
 
-pub fn global_get(store: &mut Store, stack: &mut impl StackAccess, idx: usize) -> Result<()> {
-    let global = store
-        .globals
-        .get(idx)
-        .with_context(|| Error::NotFoundGlobalVariable(idx))?;
-    stack.push(global.borrow().value.clone());
+pub fn global_get(
+    store: &Store,
+    inst: InstanceAddr,
+    stack: &mut impl StackAccess,
+    idx: usize,
+) -> Result<()> {
+    let global = store.get_global(inst, idx)?;
+    stack.push(global.lock().expect("cannot lock global").value.clone());
+    Ok(())
+}
+
+fn get_table(store: &Store, inst: InstanceAddr, idx: usize) -> Result<TableInst> {
+    let table = store.get_table(inst, idx)?;
+    Ok(Arc::clone(table))
+}
+
+// https://webassembly.github.io/spec/core/exec/instructions.html#table-instructions
+pub fn table_get(
+    store: &Store,
+    inst: InstanceAddr,
+    stack: &mut impl StackAccess,
+    idx: usize,
+) -> Result<()> {
+    let table = get_table(store, inst, idx)?;
+    let i = stack.pop1::<i32>()? as u32 as usize;
+    let value = table
+        .lock()
+        .expect("cannot lock table")
+        .elements
+        .get(i)
+        .cloned()
+        .with_context(|| TrapCode::TableOutOfBounds)?;
+    stack.push(value);
+    Ok(())
+}
+
+pub fn table_set(
+    store: &Store,
+    inst: InstanceAddr,
+    stack: &mut impl StackAccess,
+    idx: usize,
+) -> Result<()> {
+    let table = get_table(store, inst, idx)?;
+    let value: Value = stack.pop1()?;
+    let i = stack.pop1::<i32>()? as u32 as usize;
+    let mut table = table.lock().expect("cannot lock table");
+    let elem = table
+        .elements
+        .get_mut(i)
+        .with_context(|| TrapCode::TableOutOfBounds)?;
+    *elem = value;
+    Ok(())
+}
+
+pub fn table_size(
+    store: &Store,
+    inst: InstanceAddr,
+    stack: &mut impl StackAccess,
+    idx: usize,
+) -> Result<()> {
+    let table = get_table(store, inst, idx)?;
+    let size = table.lock().expect("cannot lock table").elements.len();
+    stack.push(size as i32);
+    Ok(())
+}
+
+pub fn table_grow(
+    store: &Store,
+    inst: InstanceAddr,
+    stack: &mut impl StackAccess,
+    idx: usize,
+    max_elements: u32,
+) -> Result<()> {
+    let table = get_table(store, inst, idx)?;
+    let n = stack.pop1::<i32>()? as u32;
+    let value: Value = stack.pop1()?;
+
+    let mut table = table.lock().expect("cannot lock table");
+    let size = table.elements.len() as u32;
+    let max = table.max.unwrap_or(u32::MAX).min(max_elements);
+    match size.checked_add(n) {
+        Some(len) if len <= max => {
+            table.elements.resize(len as usize, value);
+            stack.push(size as i32);
+        }
+        // NOTE: failing to grow is not a trap, it returns -1
+        _ => stack.push(-1),
+    }
+    Ok(())
+}
+
+pub fn table_fill(
+    store: &Store,
+    inst: InstanceAddr,
+    stack: &mut impl StackAccess,
+    idx: usize,
+) -> Result<()> {
+    let table = get_table(store, inst, idx)?;
+    let n = stack.pop1::<i32>()? as u32 as usize;
+    let value: Value = stack.pop1()?;
+    let i = stack.pop1::<i32>()? as u32 as usize;
+
+    let mut table = table.lock().expect("cannot lock table");
+    let elements = i
+        .checked_add(n)
+        .and_then(|end| table.elements.get_mut(i..end))
+        .with_context(|| TrapCode::TableOutOfBounds)?;
+    elements.fill(value);
+    Ok(())
+}
+
+pub fn table_init(
+    store: &Store,
+    inst: InstanceAddr,
+    stack: &mut impl StackAccess,
+    elem_idx: usize,
+    table_idx: usize,
+) -> Result<()> {
+    let table = get_table(store, inst, table_idx)?;
+    let elem = store.get_elem(inst, elem_idx)?;
+    let n = stack.pop1::<i32>()? as u32 as usize;
+    let s = stack.pop1::<i32>()? as u32 as usize;
+    let d = stack.pop1::<i32>()? as u32 as usize;
+    table
+        .lock()
+        .expect("cannot lock table")
+        .init(d, &elem.elements, s, n)?;
+    Ok(())
+}
+
+pub fn elem_drop(store: &mut Store, inst: InstanceAddr, idx: usize) -> Result<()> {
+    let elem = store.get_elem_mut(inst, idx)?;
+    elem.elements.clear();
+    Ok(())
+}
+
+pub fn table_copy(
+    store: &Store,
+    inst: InstanceAddr,
+    stack: &mut impl StackAccess,
+    dst_idx: usize,
+    src_idx: usize,
+) -> Result<()> {
+    let dst = get_table(store, inst, dst_idx)?;
+    let src = get_table(store, inst, src_idx)?;
+    let n = stack.pop1::<i32>()? as u32 as usize;
+    let s = stack.pop1::<i32>()? as u32 as usize;
+    let d = stack.pop1::<i32>()? as u32 as usize;
+
+    // NOTE: the source and destination may be the same table, so copy the elements first
+    let elements = s
+        .checked_add(n)
+        .and_then(|end| {
+            let src = src.lock().expect("cannot lock table");
+            src.elements.get(s..end).map(<[Value]>::to_vec)
+        })
+        .with_context(|| TrapCode::TableOutOfBounds)?;
+    dst.lock()
+        .expect("cannot lock table")
+        .init(d, &elements, 0, n)?;
+    Ok(())
+}
+
+// https://webassembly.github.io/spec/core/exec/instructions.html#memory-instructions
+pub fn memory_init(
+    store: &Store,
+    inst: InstanceAddr,
+    stack: &mut impl StackAccess,
+    data_idx: usize,
+) -> Result<()> {
+    let memory = store.get_memory(inst, 0)?;
+    let data = store.get_data(inst, data_idx)?;
+    let n = stack.pop1::<i32>()? as u32 as usize;
+    let s = stack.pop1::<i32>()? as u32 as usize;
+    let d = stack.pop1::<i32>()? as u32 as usize;
+    memory
+        .lock()
+        .expect("cannot lock memory")
+        .init(d, &data.data, s, n)?;
+    Ok(())
+}
+
+pub fn data_drop(store: &mut Store, inst: InstanceAddr, idx: usize) -> Result<()> {
+    let data = store.get_data_mut(inst, idx)?;
+    data.data.clear();
     Ok(())
 }
 
@@ -64,7 +248,7 @@ pub fn popcnt(stack: &mut impl StackAccess) -> Result<()> {
         Value::I64(v) => {
             stack.push(v.count_ones() as i64);
         }
-        _ => bail!(Error::UnexpectedStackValueType(value)),
+        _ => bail!(Error::UnexpectedStackValueType(format!("{value:?}"))),
     }
     Ok(())
 }
@@ -77,98 +261,61 @@ pub fn i64extend_32s(stack: &mut impl StackAccess) -> Result<()> {
             let value: Value = result.into();
             stack.push(value);
         }
-        _ => bail!(Error::UnexpectedStackValueType(value)),
+        _ => bail!(Error::UnexpectedStackValueType(format!("{value:?}"))),
     }
     Ok(())
 }
 
-pub fn get_end_address(insts: &[Instruction], pc: isize) -> Result<usize> {
-    let mut pc = pc as usize;
-    let mut depth = 0;
-    loop {
-        pc += 1;
-        let inst = insts
-            .get(pc)
-            .with_context(|| Error::NotFoundInstruction(pc))?;
-        match inst {
-            Instruction::If(_) | Instruction::Block(_) | Instruction::Loop(_) => depth += 1,
-            Instruction::End => {
-                if depth == 0 {
-                    return Ok(pc);
-                } else {
-                    depth -= 1;
-                }
-            }
-            _ => {
-                // do nothing
-            }
-        }
-    }
-}
+pub fn push_frame(
+    stack: &mut Vec<Value>,
+    call_stack: &mut Vec<Frame>,
+    func: &InternalFuncInst,
+    config: &Config,
+) -> Result<()> {
+    let arity = func.func_type.results.len();
+    let bottom = stack
+        .len()
+        .checked_sub(func.func_type.params.len())
+        .with_context(|| Error::StackPopError)?;
 
-pub fn get_else_or_end_address(insts: &[Instruction], pc: isize) -> Result<usize> {
-    let mut pc = pc as usize;
-    let mut depth = 0;
-    loop {
-        pc += 1;
-        let inst = insts
-            .get(pc)
-            .with_context(|| Error::NotFoundInstruction(pc))?;
-        match inst {
-            Instruction::If(_) => {
-                depth += 1;
-            }
-            Instruction::Else => {
-                if depth == 0 {
-                    return Ok(pc);
-                }
-            }
-            Instruction::End => {
-                if depth == 0 {
-                    return Ok(pc);
-                } else {
-                    depth -= 1;
-                }
-            }
-            _ => {
-                // do nothing
-            }
-        }
+    // NOTE: the arguments are moved from the value stack to the locals of the new frame,
+    // so the locals are counted as the values on the stack
+    let values = stack.len() + func.code.locals.len();
+    if call_stack.len() >= config.max_call_depth || values > config.max_value_stack {
+        bail!(TrapCode::StackOverflow);
     }
-}
-
-pub fn push_frame(stack: &mut Vec<Value>, call_stack: &mut Vec<Frame>, func: &InternalFuncInst) {
-    let arity = func.func_type.results.len();
-    let bottom = stack.len() - func.func_type.params.len();
     let mut locals = stack.split_off(bottom);
 
     for local in func.code.locals.iter() {
-        match local {
-            ValueType::I32 => locals.push(Value::I32(0)),
-            ValueType::I64 => locals.push(Value::I64(0)),
-            ValueType::F32 => locals.push(Value::F32(0.0)),
-            ValueType::F64 => locals.push(Value::F64(0.0)),
-        }
+        locals.push(Value::default_of(local));
     }
 
     let sp = stack.len();
     let frame = Frame {
+        instance: func.code.instance,
+        func_idx: func.code.func_idx,
         pc: -1,
         sp,
         insts: func.code.body.clone(),
+        side_table: func.code.side_table.clone(),
         arity,
         locals,
         labels: vec![],
     };
     trace!("call internal function: {:?}", &frame);
     call_stack.push(frame);
+    Ok(())
 }
 
 pub fn stack_unwind(stack: &mut Vec<Value>, sp: usize, arity: usize) -> Result<()> {
     if arity > 0 {
-        let value = stack.pop().with_context(|| Error::StackPopError)?;
+        let bottom = stack
+            .len()
+            .checked_sub(arity)
+            .with_context(|| Error::StackPopError)?;
+        let values = stack.split_off(bottom);
         stack.drain(sp..);
-        stack.push(value);
+        stack.extend(values);
     } else {
         stack.drain(sp..);
     }
@@ -182,6 +329,7 @@ pub fn br(labels: &mut Vec<Label>, stack: &mut Vec<Value>, level: &u32) -> Resul
         start,
         sp,
         arity,
+        branch_arity,
         kind,
     } = labels
         .get(label_index)
@@ -192,8 +340,8 @@ pub fn br(labels: &mut Vec<Label>, stack: &mut Vec<Value>, level: &u32) -> Resul
         // NOTE: we still need loop label to jump to the beginning of the loop.
         labels.drain(label_index + 1..);
         // NOTE: since it jumps to the beginning of the loop,
-        // the stack is unwound without considering the return value.
-        stack_unwind(stack, sp, 0)?;
+        // the stack is unwound with the branch arity instead of the result arity.
+        stack_unwind(stack, sp, branch_arity)?;
         start.with_context(|| Error::NotFoundStartPc)?
     } else {
         labels.drain(label_index..);
@@ -203,55 +351,6 @@ pub fn br(labels: &mut Vec<Label>, stack: &mut Vec<Value>, level: &u32) -> Resul
     Ok(pc)
 }
 
-fn is_wasi_modules(module: &str) -> bool {
-    module == "wasi_snapshot_preview1" || module == "wasi_ephemeral_nn"
-}
-
-pub fn invoke_external(
-    store: Rc<RefCell<Store>>,
-    stack: &mut impl StackAccess,
-    func: ExternalFuncInst,
-) -> Result<Option<Value>> {
-    trace!("invoke external function: {:?}", &func);
-    let mut args = Vec::with_capacity(func.func_type.params.len());
-    for _ in 0..func.func_type.params.len() {
-        args.push(stack.pop1()?);
-    }
-    args.reverse();
-
-    // if module is a WASI module, it dosn't have store, so we suould use current store.
-    let module_store = if is_wasi_modules(&func.module) {
-        store.clone()
-    } else {
-        let brrowed = store.borrow();
-        let Some(imports) = brrowed.imports.as_ref() else {
-            bail!(Error::NoImports);
-        };
-
-        let import = imports
-            .get(&func.module)
-            .with_context(|| Error::NotFoundImportModule(func.module.clone()))?;
-
-        let Some(module_store) = import.get(&func.module)? else {
-            bail!(Error::NotFoundImportModule(func.module.clone()));
-        };
-
-        module_store.clone()
-    };
-
-    let store_for_invoke = module_store.clone();
-
-    let module_store = store.borrow();
-    let importer = module_store
-        .imports
-        .as_ref()
-        .with_context(|| Error::NoImports)?
-        .get(&func.module)
-        .with_context(|| Error::NotFoundImportModule(func.module.clone()))?;
-
-    importer.invoke(store_for_invoke, func, args)
-}
-
 impl_unary_operation!(
     eqz, // itestop
     clz, ctz, extend8_s, extend16_s, // iunop
@@ -277,6 +376,14 @@ impl_cvtop_operation!(
     i64_trunc_f32_u,
     i64_trunc_f64_s,
     i64_trunc_f64_u,
+    i32_trunc_sat_f32_s,
+    i32_trunc_sat_f32_u,
+    i32_trunc_sat_f64_s,
+    i32_trunc_sat_f64_u,
+    i64_trunc_sat_f32_s,
+    i64_trunc_sat_f32_u,
+    i64_trunc_sat_f64_s,
+    i64_trunc_sat_f64_u,
     i64_extend_i32_s,
     i64_extend_i32_u,
     f32_convert_i32_s,
@@ -0,0 +1,43 @@
+use super::{
+    runtime::Runtime,
+    store::Store,
+    value::{Frame, Value},
+};
+use anyhow::Result;
+use std::sync::{Arc, Mutex};
+
+// result of the call with the step budget
+#[derive(Debug)]
+pub enum CallResult {
+    Finished(Vec<Value>),
+    Paused(ResumableCall),
+}
+
+// execution paused by running out of the step budget.
+// it owns the stacks, so the runtime can call other functions until it's resumed.
+pub struct ResumableCall {
+    pub(crate) store: Arc<Mutex<Store>>,
+    pub(crate) stack: Vec<Value>,
+    pub(crate) call_stack: Vec<Frame>,
+}
+
+impl ResumableCall {
+    // continue the execution with the new budget on the runtime which started the call
+    pub fn resume(self, runtime: &mut Runtime, steps: u64) -> Result<CallResult> {
+        runtime.resume_call(self, steps)
+    }
+
+    // depth of the paused call stack
+    pub fn depth(&self) -> usize {
+        self.call_stack.len()
+    }
+}
+
+impl std::fmt::Debug for ResumableCall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResumableCall")
+            .field("stack", &self.stack)
+            .field("depth", &self.call_stack.len())
+            .finish()
+    }
+}
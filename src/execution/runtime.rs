@@ -1,9 +1,18 @@
-use super::module::{FuncInst, InternalFuncInst};
+use super::compiled::Module;
+use super::config::Config;
+use super::host::Caller;
+use super::host::WasmResults;
+use super::indices::{FuncAddr, InstanceAddr};
+use super::interrupt::{Epoch, EpochDeadlineCallback, InterruptHandle};
+use super::module::{ExternalFuncInst, FuncInst, InternalFuncInst};
 use super::op::*;
+use super::resumable::{CallResult, ResumableCall};
 use super::store::{Exports, Store};
+use super::typed::{TypedFunc, WasmParams};
 use super::value::{ExternalVal, Frame, Label, StackAccess, Value};
 use crate::binary::instruction::*;
-use crate::execution::error::Error;
+use crate::binary::types::FuncType;
+use crate::execution::error::{Error, FrameInfo, Trap, TrapCode};
 use crate::execution::value::LabelKind;
 use crate::{load, store, Importer};
 use anyhow::{bail, Context as _, Result};
@@ -11,18 +20,25 @@ use log::{error, trace};
 use std::cell::RefCell;
 use std::io::Read;
 use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 
 #[derive(Default, Clone)]
 pub struct Runtime {
-    pub store: Rc<RefCell<Store>>,
+    pub store: Arc<Mutex<Store>>,
     pub stack: Vec<Value>,
     pub call_stack: Vec<Frame>,
+    pub config: Config,
+    instance: InstanceAddr, // module instance whose exports are called
+    fuel: u64,
+    epoch: Epoch,
+    steps: Option<u64>, // instructions left until the resumable call is paused
+    callback: bool,     // called back from the host function, which can't be paused
 }
 
 impl Runtime {
     pub fn from_file(file: &str, imports: Option<Vec<Box<dyn Importer>>>) -> Result<Self> {
         let store = Store::from_file(file, imports)?;
-        Self::instantiate(Rc::new(RefCell::new(store)))
+        Self::instantiate(Arc::new(Mutex::new(store)))
     }
 
     pub fn from_reader(
@@ -30,7 +46,7 @@ impl Runtime {
         imports: Option<Vec<Box<dyn Importer>>>,
     ) -> Result<Self> {
         let store = Store::from_reader(reader, imports)?;
-        Self::instantiate(Rc::new(RefCell::new(store)))
+        Self::instantiate(Arc::new(Mutex::new(store)))
     }
 
     pub fn from_bytes<T: AsRef<[u8]>>(
@@ -38,155 +54,425 @@ impl Runtime {
         imports: Option<Vec<Box<dyn Importer>>>,
     ) -> Result<Self> {
         let store = Store::from_bytes(b, imports)?;
-        Self::instantiate(Rc::new(RefCell::new(store)))
+        Self::instantiate(Arc::new(Mutex::new(store)))
+    }
+
+    // instantiate the compiled module in a new store,
+    // the function bodies are shared with the other instances of the module
+    pub fn from_module(module: &Module, imports: Option<Vec<Box<dyn Importer>>>) -> Result<Self> {
+        let store = Store::new(module, imports)?;
+        Self::instantiate(Arc::new(Mutex::new(store)))
     }
 
     // https://www.w3.org/TR/wasm-core-1/#instantiation%E2%91%A1
-    pub fn instantiate(store: Rc<RefCell<Store>>) -> Result<Self> {
-        let start = store.borrow().start;
+    pub fn instantiate(store: Arc<Mutex<Store>>) -> Result<Self> {
+        Self::instantiate_with_config(store, Config::default())
+    }
+
+    // the limits in the config are also applied to the start function
+    // NOTE: the runtime is bound to the first instance in the store
+    pub fn instantiate_with_config(store: Arc<Mutex<Store>>, config: Config) -> Result<Self> {
+        Self::bind(store, 0, config)
+    }
+
+    // runtime to call the exports of the instance, the start function is run here
+    pub(crate) fn bind(
+        store: Arc<Mutex<Store>>,
+        instance: InstanceAddr,
+        config: Config,
+    ) -> Result<Self> {
+        let start = store
+            .lock()
+            .expect("cannot lock store")
+            .instance(instance)?
+            .start;
         let mut runtime = Self {
             store,
+            config,
+            instance,
             ..Default::default()
         };
 
         // https://www.w3.org/TR/wasm-core-1/#start-function%E2%91%A1
         if let Some(idx) = start {
-            let result = runtime.call_start(idx as usize, vec![])?;
-            if let Some(out) = result {
-                runtime.stack.push(out);
-            }
+            let results = runtime.call_start(idx as usize, vec![])?;
+            runtime.stack.extend(results);
         }
 
         Ok(runtime)
     }
 
     // execute function by name
+    // NOTE: returns only the first result, use `call_multi` for the function
+    // that returns multiple values
     pub fn call(&mut self, name: String, args: Vec<Value>) -> Result<Option<Value>> {
+        let results = self.call_multi(name, args)?;
+        Ok(results.into_iter().next())
+    }
+
+    // execute function by name and returns all results
+    pub fn call_multi(&mut self, name: String, args: Vec<Value>) -> Result<Vec<Value>> {
         trace!("call function: {}", name);
-        for arg in args {
-            self.stack.push(arg);
+        let addr = self
+            .store
+            .lock()
+            .expect("cannot lock store")
+            .export_func_addr(self.instance, &name)?;
+        self.call_by_addr(addr, args)
+    }
+
+    // execute function when module has start section
+    pub fn call_start(&mut self, idx: usize, args: Vec<Value>) -> Result<Vec<Value>> {
+        let addr = *self
+            .store
+            .lock()
+            .expect("cannot lock store")
+            .instance(self.instance)?
+            .func_addrs
+            .get(idx)
+            .with_context(|| Error::NotFoundFunction(idx))?;
+        self.call_by_addr(addr, args)
+    }
+
+    // address of the module instance which the runtime is bound to
+    pub fn instance(&self) -> InstanceAddr {
+        self.instance
+    }
+
+    // get the exported function with the signature checked against the rust types,
+    // the returned handle calls the function without looking up the export
+    pub fn get_typed_func<Params, Results>(&self, name: &str) -> Result<TypedFunc<Params, Results>>
+    where
+        Params: WasmParams,
+        Results: WasmResults,
+    {
+        let addr = self
+            .store
+            .lock()
+            .expect("cannot lock store")
+            .export_func_addr(self.instance, name)?;
+        let func = self.get_func_by_addr(addr)?;
+        TypedFunc::new(Arc::clone(&self.store), addr, func.func_type())
+    }
+
+    // execute function by name with the budget of the instructions to execute,
+    // the call is paused when the budget is used up instead of trapping
+    pub fn call_resumable(
+        &mut self,
+        name: String,
+        args: Vec<Value>,
+        steps: u64,
+    ) -> Result<CallResult> {
+        let addr = self
+            .store
+            .lock()
+            .expect("cannot lock store")
+            .export_func_addr(self.instance, &name)?;
+        let func = self.get_func_by_addr(addr)?;
+        Self::check_args(func.func_type(), &args)?;
+        self.discard_suspended();
+        self.stack.extend(args);
+        self.run_steps(steps, |runtime| runtime.invoke(func))
+    }
+
+    pub(crate) fn resume_call(&mut self, call: ResumableCall, steps: u64) -> Result<CallResult> {
+        if !Arc::ptr_eq(&call.store, &self.store) {
+            bail!(Error::ForeignResumableCall);
         }
+        self.discard_suspended();
+        self.stack = call.stack;
+        self.call_stack = call.call_stack;
+        self.run_steps(steps, Self::continue_execution)
+    }
 
-        let idx = {
-            let store = self.store.borrow();
-            let export_inst = store
-                .module
-                .exports
-                .get(&name)
-                .with_context(|| Error::NotFoundExportInstance(name))?;
-            let external_val = &export_inst.desc;
-
-            let ExternalVal::Func(idx) = external_val else {
-                bail!("invalid export desc: {:?}", external_val);
-            };
+    fn run_steps(
+        &mut self,
+        steps: u64,
+        run: impl FnOnce(&mut Self) -> Result<Vec<Value>>,
+    ) -> Result<CallResult> {
+        self.steps = Some(steps);
+        let result = run(self);
+        self.steps = None;
+        match result {
+            Ok(values) => Ok(CallResult::Finished(values)),
+            Err(e) if matches!(e.downcast_ref::<Error>(), Some(Error::Paused)) => {
+                Ok(CallResult::Paused(ResumableCall {
+                    store: Arc::clone(&self.store),
+                    stack: std::mem::take(&mut self.stack),
+                    call_stack: std::mem::take(&mut self.call_stack),
+                }))
+            }
+            Err(e) => Err(e),
+        }
+    }
 
-            *idx as usize
-        };
-        self.invoke(idx)
+    pub(crate) fn call_by_addr(&mut self, addr: FuncAddr, args: Vec<Value>) -> Result<Vec<Value>> {
+        let func = self.get_func_by_addr(addr)?;
+        Self::check_args(func.func_type(), &args)?;
+        self.discard_suspended();
+        self.stack.extend(args);
+        self.invoke(func)
     }
 
-    // execute function when module has start section
-    pub fn call_start(&mut self, idx: usize, args: Vec<Value>) -> Result<Option<Value>> {
-        for arg in args {
-            self.stack.push(arg);
+    // the arguments from the embedder are not validated like the guest code,
+    // so they are checked before pushed to the stack
+    fn check_args(func_type: &FuncType, args: &[Value]) -> Result<()> {
+        let types: Vec<_> = args.iter().map(Value::value_type).collect();
+        if types != func_type.params {
+            bail!(Error::InvalidArguments(
+                format!("{:?}", func_type.params),
+                format!("{types:?}")
+            ));
+        }
+        Ok(())
+    }
+
+    // add fuel to the runtime, fails if the fuel consumption is not enabled
+    pub fn add_fuel(&mut self, fuel: u64) -> Result<()> {
+        if !self.config.consume_fuel {
+            bail!(Error::FuelNotEnabled);
+        }
+        self.fuel = self.fuel.saturating_add(fuel);
+        Ok(())
+    }
+
+    // remaining fuel, or None if the fuel consumption is not enabled
+    pub fn fuel(&self) -> Option<u64> {
+        self.config.consume_fuel.then_some(self.fuel)
+    }
+
+    // handle to interrupt the execution from another thread
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.epoch.handle()
+    }
+
+    // trap the guest after the epoch is incremented `delta` times from now
+    pub fn set_epoch_deadline(&mut self, delta: u64) {
+        self.epoch.set_deadline(delta);
+    }
+
+    // call the callback instead of trapping when the epoch deadline is reached
+    pub fn epoch_deadline_callback(
+        &mut self,
+        callback: impl FnMut() -> Result<u64> + Send + 'static,
+    ) {
+        let callback: EpochDeadlineCallback = Arc::new(Mutex::new(callback));
+        self.epoch.set_callback(callback);
+    }
+
+    // continue the execution which was suspended by running out of fuel,
+    // returns the results of the function called at first.
+    // NOTE: the host function can't be suspended, so running out of fuel in the guest function
+    // called back from it fails with `Error::CallbackOutOfFuel` and leaves nothing to resume
+    pub fn resume(&mut self) -> Result<Vec<Value>> {
+        if self.call_stack.is_empty() {
+            bail!(Error::NothingToResume);
+        }
+        self.continue_execution()
+    }
+
+    // run the frames left on the call stack,
+    // returns the results of the function at the bottom of the stack
+    fn continue_execution(&mut self) -> Result<Vec<Value>> {
+        let arity = self
+            .call_stack
+            .first()
+            .with_context(|| Error::NothingToResume)?
+            .arity;
+        let result = self.execute().and_then(|_| {
+            let bottom = self
+                .stack
+                .len()
+                .checked_sub(arity)
+                .with_context(|| Error::StackPopError)?;
+            Ok(self.stack.split_off(bottom))
+        });
+        result.map_err(|e| self.trap(e))
+    }
+
+    // the suspended execution is dropped when another function is called
+    fn discard_suspended(&mut self) {
+        if !self.call_stack.is_empty() {
+            self.stack.clear();
+            self.call_stack.clear();
         }
-        self.invoke(idx)
     }
 
     // get exported instances by name, like table, memory, global
     pub fn exports(&mut self, name: String) -> Result<Exports> {
-        let store = self.store.borrow();
-        let export_inst = store
-            .module
-            .exports
-            .get(&name)
-            .with_context(|| Error::NotFoundExportInstance(name))?;
-
-        let exports = match export_inst.desc {
-            ExternalVal::Table(idx) => {
-                let table = store
-                    .tables
-                    .get(idx as usize)
-                    .with_context(|| Error::NotFoundExportedTable(idx))?;
-                Exports::Table(Rc::clone(table))
-            }
-            ExternalVal::Memory(idx) => {
-                let memory = store
-                    .memory
-                    .get(idx as usize)
-                    .with_context(|| Error::NotFoundExportedMemory(idx))?;
-                Exports::Memory(Rc::clone(memory))
-            }
-            ExternalVal::Global(idx) => {
-                let global = store
-                    .globals
-                    .get(idx as usize)
-                    .with_context(|| Error::NotFoundExportedGlobal(idx))?;
-                Exports::Global(Rc::clone(global))
-            }
-            ExternalVal::Func(idx) => {
-                let func = store
-                    .funcs
-                    .get(idx as usize)
-                    .with_context(|| Error::NotFoundExportedFunction(idx))?;
-                Exports::Func(func.clone())
-            }
-        };
-
-        Ok(exports)
+        self.store
+            .lock()
+            .expect("cannot lock store")
+            .exports(self.instance, &name)
     }
 
-    fn invoke_internal(&mut self, func: InternalFuncInst) -> Result<Option<Value>> {
+    fn invoke_internal(&mut self, func: InternalFuncInst) -> Result<Vec<Value>> {
         let arity = func.func_type.results.len();
 
-        push_frame(&mut self.stack, &mut self.call_stack, &func);
+        push_frame(&mut self.stack, &mut self.call_stack, &func, &self.config)?;
 
         self.execute()?;
 
-        let result = if arity > 0 {
-            // NOTE: only returns one value now
-            let value: Value = self.stack.pop1()?;
-            Some(value)
-        } else {
-            None
-        };
+        let bottom = self
+            .stack
+            .len()
+            .checked_sub(arity)
+            .with_context(|| Error::StackPopError)?;
+        let results = self.stack.split_off(bottom);
 
-        Ok(result)
+        Ok(results)
     }
 
     // https://www.w3.org/TR/wasm-core-1/#exec-invoke
-    fn invoke(&mut self, idx: usize) -> Result<Option<Value>> {
-        let func = self.get_func_by_idx(idx)?;
+    fn invoke(&mut self, func: FuncInst) -> Result<Vec<Value>> {
         let result = match func {
             FuncInst::Internal(func) => self.invoke_internal(func),
-            FuncInst::External(func) => {
-                let stack = &mut self.stack;
-                invoke_external(Rc::clone(&self.store), stack, func)
+            FuncInst::External(func) => self.invoke_external(func),
+        };
+        result.map_err(|e| self.trap(e))
+    }
+
+    fn invoke_external(&mut self, func: ExternalFuncInst) -> Result<Vec<Value>> {
+        trace!("invoke external function: {:?}", &func);
+        let bottom = self
+            .stack
+            .len()
+            .checked_sub(func.func_type.params.len())
+            .with_context(|| Error::StackPopError)?;
+        let args = self.stack.split_off(bottom);
+
+        // the store must not be locked while the host function is running
+        // because it can call back into the guest
+        let importer = Arc::clone(&func.importer);
+
+        // if module is a host module like WASI, it dosn't have store, so we suould use current store.
+        let module_store = importer
+            .get(&func.module)?
+            .unwrap_or_else(|| Arc::clone(&self.store));
+
+        // the host function called by the guest sees the instance of the caller
+        let instance = if Arc::ptr_eq(&module_store, &self.store) {
+            self.call_stack
+                .last()
+                .map_or(self.instance, |frame| frame.instance)
+        } else {
+            0
+        };
+        let nested = Rc::new(RefCell::new(self.nested(module_store, instance)));
+        let caller = Caller::with_runtime(Rc::clone(&nested));
+        let results = importer.invoke_with_caller(caller, func.clone(), args);
+
+        // take back the fuel, the epoch deadline and the steps consumed by the nested calls
+        let nested = nested.borrow();
+        self.fuel = nested.fuel;
+        self.epoch = nested.epoch.clone();
+        self.steps = nested.steps;
+        let results = results.map_err(|e| match e.downcast_ref::<Trap>() {
+            // the frames of the callback are gone with the nested runtime
+            Some(trap) if trap.code() == &TrapCode::OutOfFuel => {
+                Error::CallbackOutOfFuel(func.module.clone(), func.field.clone()).into()
             }
+            _ => e,
+        })?;
+
+        // the results are pushed to the stack of the validated guest,
+        // so the importer must return the values of the declared types
+        let types: Vec<_> = results.iter().map(Value::value_type).collect();
+        if types != func.func_type.results {
+            let detail = format!("expected {:?}, actual {types:?}", func.func_type.results);
+            bail!(Error::HostResultMismatch(func.module, func.field, detail));
+        }
+        Ok(results)
+    }
+
+    // runtime to execute the guest function called back from the host function.
+    // it has its own stacks and the limits are reduced by the usage of this runtime.
+    // the callback consumes the steps, but it runs to the end even if the budget is used up
+    // because the host function can't be paused, then the caller is paused after it returns.
+    fn nested(&self, store: Arc<Mutex<Store>>, instance: InstanceAddr) -> Self {
+        let config = Config {
+            max_call_depth: self
+                .config
+                .max_call_depth
+                .saturating_sub(self.call_stack.len()),
+            max_value_stack: self.config.max_value_stack.saturating_sub(self.stack.len()),
+            ..self.config.clone()
         };
-        match result {
-            Ok(value) => Ok(value),
+        Self {
+            store,
+            config,
+            instance,
+            fuel: self.fuel,
+            epoch: self.epoch.clone(),
+            steps: self.steps,
+            callback: true,
+            ..Default::default()
+        }
+    }
+
+    // runtime without running the start function
+    pub(crate) fn with_store(store: Arc<Mutex<Store>>) -> Self {
+        Self {
+            store,
+            ..Default::default()
+        }
+    }
+
+    fn trap(&mut self, e: anyhow::Error) -> anyhow::Error {
+        let code = match e.downcast::<TrapCode>() {
+            Ok(code) => code,
+            // keep the stacks to be taken by the resumable call
+            Err(e) if matches!(e.downcast_ref::<Error>(), Some(Error::Paused)) => return e,
             Err(e) => {
                 self.stack = vec![]; // when traped, need to cleanup stack
                 self.call_stack = vec![];
-                Err(e)
+                return e;
             }
+        };
+        let trap = Trap::new(code.clone(), self.backtrace());
+        // keep the stacks to resume after refueling
+        if code != TrapCode::OutOfFuel {
+            self.stack = vec![];
+            self.call_stack = vec![];
         }
+        trap.into()
     }
 
-    fn get_func_by_idx(&mut self, idx: usize) -> Result<FuncInst> {
-        let store = self.store.borrow();
+    // capture the active frames before the call stack is cleaned up
+    fn backtrace(&self) -> Vec<FrameInfo> {
+        let store = self.store.lock().expect("cannot lock store");
+        self.call_stack
+            .iter()
+            .rev()
+            .map(|frame| {
+                let exports = store.instance(frame.instance).map(|inst| &inst.exports);
+                let func_name = exports.ok().and_then(|exports| {
+                    exports.values().find_map(|export| {
+                        matches!(export.desc, ExternalVal::Func(idx) if idx == frame.func_idx)
+                            .then(|| export.name.clone())
+                    })
+                });
+                FrameInfo {
+                    func_idx: frame.func_idx,
+                    func_name,
+                    pc: frame.pc.max(0) as usize,
+                }
+            })
+            .collect()
+    }
+
+    fn get_func_by_addr(&self, addr: FuncAddr) -> Result<FuncInst> {
+        let store = self.store.lock().expect("cannot lock store");
         let func = store
             .funcs
-            .get(idx)
-            .with_context(|| Error::NotFoundFunction(idx))?;
+            .get(addr)
+            .with_context(|| Error::NotFoundFunction(addr))?;
         Ok(func.clone())
     }
 
     fn execute(&mut self) -> Result<()> {
-        let stack = &mut self.stack;
-
         loop {
+            let stack = &mut self.stack;
             let Some(frame) = self.call_stack.last_mut() else {
                 trace!("call stack is empty, return");
                 break;
@@ -197,8 +483,30 @@ impl Runtime {
                 break;
             };
             trace!("pc: {}, inst: {:?}", frame.pc, &inst);
+            let instance = frame.instance;
+            if self.steps == Some(0) && !self.callback {
+                // pause before the instruction, it's executed when resumed
+                frame.pc -= 1;
+                bail!(Error::Paused);
+            }
+            if self.config.consume_fuel {
+                let cost = (self.config.fuel_cost)(inst);
+                if self.fuel < cost {
+                    // rewind to execute the instruction again when resumed
+                    frame.pc -= 1;
+                    bail!(TrapCode::OutOfFuel);
+                }
+                self.fuel -= cost;
+            }
+            if let Some(steps) = self.steps.as_mut() {
+                *steps = steps.saturating_sub(1);
+            }
+            // the epoch is checked at function entry and loop back-edge
+            if frame.pc == 0 {
+                self.epoch.check()?;
+            }
             match inst {
-                Instruction::Unreachable => bail!("unreachable"),
+                Instruction::Unreachable => bail!(TrapCode::Unreachable),
                 Instruction::Nop => {}
                 Instruction::LocalGet(idx) => {
                     local_get(&frame.locals, stack, *idx as usize)?;
@@ -209,12 +517,18 @@ impl Runtime {
                 Instruction::LocalTee(idx) => {
                     local_tee(&mut frame.locals, stack, *idx as usize)?;
                 }
-                Instruction::GlobalGet(idx) => {
-                    global_get(&mut self.store.borrow_mut(), stack, *idx as usize)?
-                }
-                Instruction::GlobalSet(idx) => {
-                    global_set(&mut self.store.borrow_mut(), stack, *idx as usize)?
-                }
+                Instruction::GlobalGet(idx) => global_get(
+                    &self.store.lock().expect("cannot lock store"),
+                    instance,
+                    stack,
+                    *idx as usize,
+                )?,
+                Instruction::GlobalSet(idx) => global_set(
+                    &self.store.lock().expect("cannot lock store"),
+                    instance,
+                    stack,
+                    *idx as usize,
+                )?,
                 Instruction::I32Add | Instruction::I64Add => add(stack)?,
                 Instruction::I32Sub | Instruction::I64Sub => sub(stack)?,
                 Instruction::I32Mul | Instruction::I64Mul => mul(stack)?,
@@ -309,6 +623,9 @@ impl Runtime {
                 Instruction::Br(level) => {
                     let labels = &mut frame.labels;
                     let pc = br(labels, stack, level)?;
+                    if pc < frame.pc {
+                        self.epoch.check()?;
+                    }
                     frame.pc = pc;
                 }
                 Instruction::BrIf(level) => {
@@ -316,6 +633,9 @@ impl Runtime {
                     if value.is_true() {
                         let labels = &mut frame.labels;
                         let pc = br(labels, stack, level)?;
+                        if pc < frame.pc {
+                            self.epoch.check()?;
+                        }
                         frame.pc = pc;
                     }
                 }
@@ -333,45 +653,47 @@ impl Runtime {
 
                     let labels = &mut frame.labels;
                     let pc = br(labels, stack, level)?;
+                    if pc < frame.pc {
+                        self.epoch.check()?;
+                    }
                     frame.pc = pc;
                 }
-                Instruction::Loop(block) => {
-                    let arity = block.block_type.result_count();
-                    let start_pc = frame.pc;
-                    let pc = get_end_address(&frame.insts, frame.pc)?;
-
+                Instruction::Loop(_) => {
+                    let target = frame.side_table.get(frame.pc as usize)?;
                     let label = Label {
-                        start: Some(start_pc),
+                        start: Some(frame.pc),
                         kind: LabelKind::Loop,
-                        pc,
-                        sp: stack.len(),
-                        arity,
+                        pc: target.end_pc,
+                        sp: stack.len() - target.param_arity,
+                        arity: target.arity,
+                        branch_arity: target.branch_arity,
                     };
                     trace!("push label '{:?}' in the loop", &label);
                     frame.labels.push(label);
                 }
-                Instruction::If(block) => {
+                Instruction::If(_) => {
                     let cond: Value = stack.pop1()?;
-
-                    // calc pc when the end of block
-                    let next_pc = get_end_address(&frame.insts, frame.pc)?;
+                    let target = frame.side_table.get(frame.pc as usize)?;
 
                     if !cond.is_true() {
                         // if the condition is false, skip the if block
-                        frame.pc = get_else_or_end_address(&frame.insts, frame.pc)? as isize;
-                    }
-
-                    // NOTE: if block has no any instruction, just continue
-                    if next_pc == frame.pc as usize {
-                        continue;
+                        match target.else_pc {
+                            Some(else_pc) => frame.pc = else_pc as isize,
+                            None => {
+                                // NOTE: no else block, just jump to the end
+                                frame.pc = target.end_pc as isize;
+                                continue;
+                            }
+                        }
                     }
 
                     let label = Label {
                         start: None,
                         kind: LabelKind::If,
-                        pc: next_pc,
-                        sp: stack.len(),
-                        arity: block.block_type.result_count(),
+                        pc: target.end_pc,
+                        sp: stack.len() - target.param_arity,
+                        arity: target.arity,
+                        branch_arity: target.branch_arity,
                     };
                     trace!("push label '{:?}' in the if block", &label);
                     frame.labels.push(label);
@@ -384,37 +706,32 @@ impl Runtime {
                     let Label { pc, .. } = label;
                     frame.pc = pc as isize;
                 }
-                Instruction::Block(block) => {
-                    let arity = block.block_type.result_count();
-                    let pc = get_end_address(&frame.insts, frame.pc)?;
-
+                Instruction::Block(_) => {
+                    let target = frame.side_table.get(frame.pc as usize)?;
                     let label = Label {
                         start: None,
                         kind: LabelKind::Block,
-                        pc,
-                        sp: stack.len(),
-                        arity,
+                        pc: target.end_pc,
+                        sp: stack.len() - target.param_arity,
+                        arity: target.arity,
+                        branch_arity: target.branch_arity,
                     };
                     trace!("push label '{:?}' in the block", &label);
                     frame.labels.push(label);
                 }
                 Instruction::Call(idx) => {
                     let idx = *idx as usize;
-                    let store = self.store.borrow();
-                    let func = store
-                        .funcs
-                        .get(idx)
-                        .with_context(|| Error::NotFoundFunction(idx))?;
+                    let store = self.store.lock().expect("cannot lock store");
+                    let func = store.get_func(instance, idx)?;
                     match func {
                         FuncInst::Internal(func) => {
-                            push_frame(stack, &mut self.call_stack, func);
+                            push_frame(stack, &mut self.call_stack, func, &self.config)?;
                         }
                         FuncInst::External(func) => {
-                            let result =
-                                invoke_external(Rc::clone(&self.store), stack, func.clone())?;
-                            if let Some(value) = result {
-                                stack.push(value);
-                            }
+                            let func = func.clone();
+                            drop(store);
+                            let results = self.invoke_external(func)?;
+                            self.stack.extend(results);
                         }
                     }
                 }
@@ -423,28 +740,32 @@ impl Runtime {
 
                     let func = {
                         let idx = *table_idx as usize;
-                        let tables = &self.store.borrow().tables;
-                        let table = tables
-                            .get(idx) // NOTE: table_idx is always 0 now
-                            .with_context(|| Error::NotFoundTable(idx))?;
-
-                        let table = Rc::clone(table);
-                        let table = table.borrow();
-                        let func = table
-                            .funcs
+                        let table = Arc::clone(
+                            self.store
+                                .lock()
+                                .expect("cannot lock store")
+                                .get_table(instance, idx)?,
+                        );
+                        let table = table.lock().expect("cannot lock table");
+                        let elem = table
+                            .elements
                             .get(elem_idx)
-                            .with_context(|| Error::UndefinedElement)?
+                            .with_context(|| TrapCode::UndefinedElement)?;
+                        let Value::FuncRef(func) = elem else {
+                            bail!(Error::UnexpectedStackValueType(format!("{elem:?}")));
+                        };
+                        let func = func
                             .as_ref()
-                            .with_context(|| Error::UninitializedElement(elem_idx))?;
+                            .with_context(|| TrapCode::UninitializedElement(elem_idx))?;
 
-                        (*func).clone()
+                        (**func).clone()
                     };
 
                     // validate expect func signature and actual func signature
                     let idx = *signature_idx as usize;
-                    let store = self.store.borrow();
+                    let store = self.store.lock().expect("cannot lock store");
                     let expect_func_type = store
-                        .module
+                        .instance(instance)?
                         .func_types
                         .get(idx)
                         .with_context(|| Error::NotFoundFuncType(idx))?;
@@ -462,33 +783,28 @@ impl Runtime {
                             expect_func_type,
                             func_type
                         );
-                        bail!(Error::TypeMismatchIndirectCall)
+                        bail!(TrapCode::IndirectCallTypeMismatch)
                     }
 
                     match func {
                         FuncInst::Internal(ref func) => {
-                            push_frame(stack, &mut self.call_stack, func);
+                            push_frame(stack, &mut self.call_stack, func, &self.config)?;
                         }
-                        FuncInst::External(ref func) => {
-                            let result =
-                                invoke_external(Rc::clone(&self.store), stack, func.clone())?;
-                            if let Some(value) = result {
-                                stack.push(value);
-                            }
+                        FuncInst::External(func) => {
+                            drop(store);
+                            let results = self.invoke_external(func)?;
+                            self.stack.extend(results);
                         }
                     };
                 }
                 // NOTE: only support 1 memory now
                 Instruction::MemoryGrow(idx) => {
                     let idx = *idx as usize;
-                    let store = self.store.borrow();
-                    let memory = store
-                        .memory
-                        .get(idx)
-                        .with_context(|| Error::NotFoundMemory(idx))?;
-                    let memory = Rc::clone(memory);
+                    let store = self.store.lock().expect("cannot lock store");
+                    let memory = store.get_memory(instance, idx)?;
+                    let memory = Arc::clone(memory);
                     let n = stack.pop1::<i32>()?;
-                    let mut memory = memory.borrow_mut();
+                    let mut memory = memory.lock().expect("cannot lock memory");
                     let size = memory.size();
                     match memory.grow(n as u32) {
                         Ok(_) => {
@@ -502,68 +818,128 @@ impl Runtime {
                 }
                 Instruction::MemorySize => {
                     let idx = 0;
-                    let store = self.store.borrow();
-                    let memory = store
-                        .memory
-                        .get(idx)
-                        .with_context(|| Error::NotFoundMemory(idx))?;
-                    let memory = memory.borrow();
+                    let store = self.store.lock().expect("cannot lock store");
+                    let memory = store.get_memory(instance, idx)?;
+                    let memory = memory.lock().expect("cannot lock memory");
                     let size = memory.size() as i32;
                     stack.push(size.into());
                 }
                 Instruction::MemoryCopy(_, _) => {
-                    let len = stack.pop1::<i32>()? as usize;
-                    let src = stack.pop1::<i32>()? as usize;
-                    let dst = stack.pop1::<i32>()? as usize;
-
-                    let store = self.store.borrow();
-                    let memory = store
-                        .memory
-                        .first()
-                        .with_context(|| Error::NotFoundMemory(dst))?;
-                    let mut memory = memory.borrow_mut();
-                    memory.data.copy_within(src..src + len, dst);
+                    let len = stack.pop1::<i32>()? as u32 as usize;
+                    let src = stack.pop1::<i32>()? as u32 as usize;
+                    let dst = stack.pop1::<i32>()? as u32 as usize;
+
+                    let store = self.store.lock().expect("cannot lock store");
+                    let memory = store.get_memory(instance, 0)?;
+                    let mut memory = memory.lock().expect("cannot lock memory");
+                    memory.copy(dst, src, len)?;
                 }
                 Instruction::MemoryFill(_) => {
-                    let len = stack.pop1::<i32>()? as usize;
+                    let len = stack.pop1::<i32>()? as u32 as usize;
                     let val = stack.pop1::<i32>()? as u8;
-                    let dst = stack.pop1::<i32>()? as usize;
-
-                    let store = self.store.borrow();
-                    let memory = store
-                        .memory
-                        .first()
-                        .with_context(|| Error::NotFoundMemory(dst))?;
-                    let mut memory = memory.borrow_mut();
-
-                    let data: Vec<_> = vec![val; len];
-                    let dst = memory.data[dst..dst + len].as_mut();
-                    dst.copy_from_slice(data.as_slice());
+                    let dst = stack.pop1::<i32>()? as u32 as usize;
+
+                    let store = self.store.lock().expect("cannot lock store");
+                    let memory = store.get_memory(instance, 0)?;
+                    let mut memory = memory.lock().expect("cannot lock memory");
+                    memory.fill(dst, val, len)?;
+                }
+                Instruction::I32Load(arg) => load!(stack, self.store, instance, i32, arg),
+                Instruction::I64Load(arg) => load!(stack, self.store, instance, i64, arg),
+                Instruction::F32Load(arg) => load!(stack, self.store, instance, f32, arg),
+                Instruction::F64Load(arg) => load!(stack, self.store, instance, f64, arg),
+                Instruction::I32Load8S(arg) => load!(stack, self.store, instance, i8, arg, i32),
+                Instruction::I32Load8U(arg) => load!(stack, self.store, instance, u8, arg, i32),
+                Instruction::I32Load16S(arg) => load!(stack, self.store, instance, i16, arg, i32),
+                Instruction::I32Load16U(arg) => load!(stack, self.store, instance, u16, arg, i32),
+                Instruction::I64Load8S(arg) => load!(stack, self.store, instance, i8, arg, i64),
+                Instruction::I64Load8U(arg) => load!(stack, self.store, instance, u8, arg, i64),
+                Instruction::I64Load16S(arg) => load!(stack, self.store, instance, i16, arg, i64),
+                Instruction::I64Load16U(arg) => load!(stack, self.store, instance, u16, arg, i64),
+                Instruction::I64Load32S(arg) => load!(stack, self.store, instance, i32, arg, i64),
+                Instruction::I64Load32U(arg) => load!(stack, self.store, instance, u32, arg, i64),
+                Instruction::I32Store(arg) => store!(stack, self.store, instance, i32, arg),
+                Instruction::I64Store(arg) => store!(stack, self.store, instance, i64, arg),
+                Instruction::F32Store(arg) => store!(stack, self.store, instance, f32, arg),
+                Instruction::F64Store(arg) => store!(stack, self.store, instance, f64, arg),
+                Instruction::I32Store8(arg) => store!(stack, self.store, instance, i32, arg, i8),
+                Instruction::I32Store16(arg) => store!(stack, self.store, instance, i32, arg, i16),
+                Instruction::I64Store16(arg) => store!(stack, self.store, instance, i64, arg, i16),
+                Instruction::I64Store8(arg) => store!(stack, self.store, instance, i64, arg, i8),
+                Instruction::I64Store32(arg) => store!(stack, self.store, instance, i64, arg, i32),
+                Instruction::TableGet(idx) => table_get(
+                    &self.store.lock().expect("cannot lock store"),
+                    instance,
+                    stack,
+                    *idx as usize,
+                )?,
+                Instruction::TableSet(idx) => table_set(
+                    &self.store.lock().expect("cannot lock store"),
+                    instance,
+                    stack,
+                    *idx as usize,
+                )?,
+                Instruction::TableSize(idx) => table_size(
+                    &self.store.lock().expect("cannot lock store"),
+                    instance,
+                    stack,
+                    *idx as usize,
+                )?,
+                Instruction::TableGrow(idx) => table_grow(
+                    &self.store.lock().expect("cannot lock store"),
+                    instance,
+                    stack,
+                    *idx as usize,
+                    self.config.max_table_elements,
+                )?,
+                Instruction::TableFill(idx) => table_fill(
+                    &self.store.lock().expect("cannot lock store"),
+                    instance,
+                    stack,
+                    *idx as usize,
+                )?,
+                Instruction::TableInit(elem_idx, table_idx) => table_init(
+                    &self.store.lock().expect("cannot lock store"),
+                    instance,
+                    stack,
+                    *elem_idx as usize,
+                    *table_idx as usize,
+                )?,
+                Instruction::ElemDrop(idx) => elem_drop(
+                    &mut self.store.lock().expect("cannot lock store"),
+                    instance,
+                    *idx as usize,
+                )?,
+                Instruction::TableCopy(dst, src) => table_copy(
+                    &self.store.lock().expect("cannot lock store"),
+                    instance,
+                    stack,
+                    *dst as usize,
+                    *src as usize,
+                )?,
+                Instruction::MemoryInit(idx) => memory_init(
+                    &self.store.lock().expect("cannot lock store"),
+                    instance,
+                    stack,
+                    *idx as usize,
+                )?,
+                Instruction::DataDrop(idx) => data_drop(
+                    &mut self.store.lock().expect("cannot lock store"),
+                    instance,
+                    *idx as usize,
+                )?,
+                Instruction::RefNull(value_type) => stack.push(Value::default_of(value_type)),
+                Instruction::RefIsNull => {
+                    let value: Value = stack.pop1()?;
+                    stack.push(Value::I32(value.is_null() as i32));
                 }
-                Instruction::I32Load(arg) => load!(stack, self.store, i32, arg),
-                Instruction::I64Load(arg) => load!(stack, self.store, i64, arg),
-                Instruction::F32Load(arg) => load!(stack, self.store, f32, arg),
-                Instruction::F64Load(arg) => load!(stack, self.store, f64, arg),
-                Instruction::I32Load8S(arg) => load!(stack, self.store, i8, arg, i32),
-                Instruction::I32Load8U(arg) => load!(stack, self.store, u8, arg, i32),
-                Instruction::I32Load16S(arg) => load!(stack, self.store, i16, arg, i32),
-                Instruction::I32Load16U(arg) => load!(stack, self.store, u16, arg, i32),
-                Instruction::I64Load8S(arg) => load!(stack, self.store, i8, arg, i64),
-                Instruction::I64Load8U(arg) => load!(stack, self.store, u8, arg, i64),
-                Instruction::I64Load16S(arg) => load!(stack, self.store, i16, arg, i64),
-                Instruction::I64Load16U(arg) => load!(stack, self.store, u16, arg, i64),
-                Instruction::I64Load32S(arg) => load!(stack, self.store, i32, arg, i64),
-                Instruction::I64Load32U(arg) => load!(stack, self.store, u32, arg, i64),
-                Instruction::I32Store(arg) => store!(stack, self.store, i32, arg),
-                Instruction::I64Store(arg) => store!(stack, self.store, i64, arg),
-                Instruction::F32Store(arg) => store!(stack, self.store, f32, arg),
-                Instruction::F64Store(arg) => store!(stack, self.store, f64, arg),
-                Instruction::I32Store8(arg) => store!(stack, self.store, i32, arg, i8),
-                Instruction::I32Store16(arg) => store!(stack, self.store, i32, arg, i16),
-                Instruction::I64Store16(arg) => store!(stack, self.store, i64, arg, i16),
-                Instruction::I64Store8(arg) => store!(stack, self.store, i64, arg, i8),
-                Instruction::I64Store32(arg) => store!(stack, self.store, i64, arg, i32),
-                Instruction::Select => {
+                Instruction::RefFunc(idx) => {
+                    let idx = *idx as usize;
+                    let store = self.store.lock().expect("cannot lock store");
+                    let func = store.get_func(instance, idx)?;
+                    stack.push(Value::FuncRef(Some(Arc::new(func.clone()))));
+                }
+                Instruction::Select | Instruction::SelectType(_) => {
                     let cond = stack.pop1::<i32>()?;
                     let val2 = stack.pop1::<Value>()?;
                     let val1 = stack.pop1::<Value>()?;
@@ -579,6 +955,14 @@ impl Runtime {
                 Instruction::I64TruncF32U => i64_trunc_f32_u(stack)?,
                 Instruction::I64TruncF64S => i64_trunc_f64_s(stack)?,
                 Instruction::I64TruncF64U => i64_trunc_f64_u(stack)?,
+                Instruction::I32TruncSatF32S => i32_trunc_sat_f32_s(stack)?,
+                Instruction::I32TruncSatF32U => i32_trunc_sat_f32_u(stack)?,
+                Instruction::I32TruncSatF64S => i32_trunc_sat_f64_s(stack)?,
+                Instruction::I32TruncSatF64U => i32_trunc_sat_f64_u(stack)?,
+                Instruction::I64TruncSatF32S => i64_trunc_sat_f32_s(stack)?,
+                Instruction::I64TruncSatF32U => i64_trunc_sat_f32_u(stack)?,
+                Instruction::I64TruncSatF64S => i64_trunc_sat_f64_s(stack)?,
+                Instruction::I64TruncSatF64U => i64_trunc_sat_f64_u(stack)?,
                 Instruction::F32ConvertI32S => f32_convert_i32_s(stack)?,
                 Instruction::F32ConvertI32U => f32_convert_i32_u(stack)?,
                 Instruction::F32ConvertI64S => f32_convert_i64_s(stack)?,
@@ -601,8 +985,15 @@ impl Runtime {
 
 #[cfg(test)]
 mod test {
-    use super::{Runtime, Value};
+    use super::{Instruction, Runtime, Value};
+    use crate::execution::config::Config;
+    use crate::execution::error::{Error, FrameInfo, Trap, TrapCode};
+    use crate::execution::host::{Caller, HostModule};
+    use crate::execution::interrupt::InterruptHandle;
+    use crate::execution::resumable::CallResult;
+    use crate::execution::store::Store;
     use anyhow::{Context, Result};
+    use std::sync::{Arc, Mutex};
 
     #[test]
     fn invoke() -> Result<()> {
@@ -687,4 +1078,745 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn reference_types() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"(module
+              (type $ret_i32 (func (result i32)))
+              (table $funcs 2 funcref)
+              (table $externs 1 externref)
+              (elem (i32.const 0) $one)
+              (global $g (mut externref) (ref.null extern))
+              (func $one (export "one") (result i32) (i32.const 1))
+              (func $two (export "two") (result i32) (i32.const 2))
+              (func (export "call") (param i32) (result i32)
+                (call_indirect $funcs (type $ret_i32) (local.get 0)))
+              (func (export "set-two")
+                (table.set $funcs (i32.const 1) (ref.func $two)))
+              (func (export "is-null") (param i32) (result i32)
+                (ref.is_null (table.get $funcs (local.get 0))))
+              (func (export "store-extern") (param externref)
+                (global.set $g (local.get 0))
+                (table.set $externs (i32.const 0) (local.get 0)))
+              (func (export "load-extern") (param i32) (result externref)
+                (table.get $externs (local.get 0)))
+              (func (export "grow-extern") (param i32) (result i32)
+                (table.grow $externs (global.get $g) (local.get 0)))
+              (func (export "size-extern") (result i32)
+                (table.size $externs))
+              (func (export "fill-extern") (param i32 i32)
+                (table.fill $externs (local.get 0) (ref.null extern) (local.get 1)))
+              (func (export "select") (param i32) (result externref)
+                (select (result externref) (global.get $g) (ref.null extern) (local.get 0)))
+            )"#,
+        )?;
+        let mut runtime = Runtime::from_bytes(wasm, None)?;
+
+        assert_eq!(runtime.call("call".into(), vec![0.into()])?, Some(1.into()));
+        assert_eq!(
+            runtime.call("is-null".into(), vec![1.into()])?,
+            Some(1.into())
+        );
+        runtime.call("set-two".into(), vec![])?;
+        assert_eq!(
+            runtime.call("is-null".into(), vec![1.into()])?,
+            Some(0.into())
+        );
+        assert_eq!(runtime.call("call".into(), vec![1.into()])?, Some(2.into()));
+
+        // host handles are passed through the guest as it is
+        let handle = Value::ExternRef(Some(42));
+        runtime.call("store-extern".into(), vec![handle.clone()])?;
+        let result = runtime.call("load-extern".into(), vec![0.into()])?;
+        assert_eq!(result, Some(handle.clone()));
+        assert_eq!(
+            runtime.call("grow-extern".into(), vec![2.into()])?,
+            Some(1.into())
+        );
+        assert_eq!(runtime.call("size-extern".into(), vec![])?, Some(3.into()));
+        let result = runtime.call("load-extern".into(), vec![2.into()])?;
+        assert_eq!(result, Some(handle.clone()));
+        runtime.call("fill-extern".into(), vec![1.into(), 2.into()])?;
+        let result = runtime.call("load-extern".into(), vec![2.into()])?;
+        assert_eq!(result, Some(Value::ExternRef(None)));
+        assert_eq!(runtime.call("select".into(), vec![1.into()])?, Some(handle));
+        assert_eq!(
+            runtime.call("select".into(), vec![0.into()])?,
+            Some(Value::ExternRef(None))
+        );
+
+        let result = runtime.call("load-extern".into(), vec![3.into()]);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "out of bounds table access"
+        );
+        let result = runtime.call("fill-extern".into(), vec![2.into(), 2.into()]);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "out of bounds table access"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn table_grow_limit() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"(module
+              (table 1 externref)
+              (func (export "grow") (param i32) (result i32)
+                (table.grow (ref.null extern) (local.get 0)))
+            )"#,
+        )?;
+        let store = Arc::new(Mutex::new(Store::from_bytes(wasm, None)?));
+
+        // the table without the maximum is still limited by the host
+        let mut runtime = Runtime::instantiate(Arc::clone(&store))?;
+        let result = runtime.call("grow".into(), vec![0x7fffffff.into()])?;
+        assert_eq!(result, Some((-1).into()));
+
+        let config = Config::new().max_table_elements(10);
+        let mut runtime = Runtime::instantiate_with_config(store, config)?;
+        assert_eq!(runtime.call("grow".into(), vec![9.into()])?, Some(1.into()));
+        assert_eq!(
+            runtime.call("grow".into(), vec![1.into()])?,
+            Some((-1).into())
+        );
+        assert_eq!(
+            runtime.call("grow".into(), vec![0.into()])?,
+            Some(10.into())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn bulk_memory() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"(module
+              (type $ret_i32 (func (result i32)))
+              (memory 1)
+              (table 4 funcref)
+              (data $passive "\01\02\03")
+              (data (i32.const 8) "\ff")
+              (elem $funcs func $one $two)
+              (elem declare func $two)
+              (func $one (result i32) (i32.const 1))
+              (func $two (result i32) (i32.const 2))
+              (func (export "load8") (param i32) (result i32)
+                (i32.load8_u (local.get 0)))
+              (func (export "memory-init") (param i32 i32 i32)
+                (memory.init $passive (local.get 0) (local.get 1) (local.get 2)))
+              (func (export "data-drop")
+                (data.drop $passive))
+              (func (export "call") (param i32) (result i32)
+                (call_indirect (type $ret_i32) (local.get 0)))
+              (func (export "table-init") (param i32 i32 i32)
+                (table.init $funcs (local.get 0) (local.get 1) (local.get 2)))
+              (func (export "table-copy") (param i32 i32 i32)
+                (table.copy (local.get 0) (local.get 1) (local.get 2)))
+              (func (export "elem-drop")
+                (elem.drop $funcs))
+            )"#,
+        )?;
+        let mut runtime = Runtime::from_bytes(wasm, None)?;
+        let args = |args: [i32; 3]| args.into_iter().map(Value::from).collect::<Vec<_>>();
+
+        assert_eq!(
+            runtime.call("load8".into(), vec![8.into()])?,
+            Some(255.into())
+        );
+        runtime.call("memory-init".into(), args([0, 1, 2]))?;
+        assert_eq!(
+            runtime.call("load8".into(), vec![0.into()])?,
+            Some(2.into())
+        );
+        assert_eq!(
+            runtime.call("load8".into(), vec![1.into()])?,
+            Some(3.into())
+        );
+        runtime.call("memory-init".into(), args([65536, 3, 0]))?;
+        for invalid in [[0, 2, 2], [65535, 0, 2]] {
+            let result = runtime.call("memory-init".into(), args(invalid));
+            assert_eq!(
+                result.unwrap_err().to_string(),
+                "out of bounds memory access"
+            );
+        }
+        runtime.call("data-drop".into(), vec![])?;
+        runtime.call("memory-init".into(), args([0, 0, 0]))?;
+        let result = runtime.call("memory-init".into(), args([0, 0, 1]));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "out of bounds memory access"
+        );
+
+        runtime.call("table-init".into(), args([1, 0, 2]))?;
+        assert_eq!(runtime.call("call".into(), vec![1.into()])?, Some(1.into()));
+        assert_eq!(runtime.call("call".into(), vec![2.into()])?, Some(2.into()));
+        let result = runtime.call("table-init".into(), args([3, 1, 2]));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "out of bounds table access"
+        );
+        // overlapping copy within the same table
+        runtime.call("table-copy".into(), args([0, 1, 2]))?;
+        assert_eq!(runtime.call("call".into(), vec![0.into()])?, Some(1.into()));
+        assert_eq!(runtime.call("call".into(), vec![1.into()])?, Some(2.into()));
+        let result = runtime.call("table-copy".into(), args([3, 0, 2]));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "out of bounds table access"
+        );
+        runtime.call("elem-drop".into(), vec![])?;
+        let result = runtime.call("table-init".into(), args([0, 0, 1]));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "out of bounds table access"
+        );
+
+        // active segments are bounds checked on instantiation
+        let wasm = wat::parse_str(r#"(module (memory 1) (data (i32.const 65535) "ab"))"#)?;
+        let result = Runtime::from_bytes(wasm, None);
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "out of bounds memory access"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn memory_out_of_bounds() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"(module
+              (memory 1)
+              (func (export "load") (param i32) (result i32)
+                (i32.load (local.get 0)))
+              (func (export "load-offset") (param i32) (result i32)
+                (i32.load offset=0xffffffff (local.get 0)))
+              (func (export "store") (param i32)
+                (i64.store align=1 (local.get 0) (i64.const -1)))
+              (func (export "copy") (param i32 i32 i32)
+                (memory.copy (local.get 0) (local.get 1) (local.get 2)))
+              (func (export "fill") (param i32 i32)
+                (memory.fill (local.get 0) (i32.const 7) (local.get 1)))
+              (func (export "grow") (param i32) (result i32)
+                (memory.grow (local.get 0)))
+            )"#,
+        )?;
+        let mut runtime = Runtime::from_bytes(wasm, None)?;
+
+        // unaligned accesses are allowed
+        runtime.call("store".into(), vec![1.into()])?;
+        assert_eq!(
+            runtime.call("load".into(), vec![3.into()])?,
+            Some((-1).into())
+        );
+        assert_eq!(
+            runtime.call("load".into(), vec![65532.into()])?,
+            Some(0.into())
+        );
+        runtime.call("copy".into(), vec![65532.into(), 1.into(), 4.into()])?;
+        assert_eq!(
+            runtime.call("load".into(), vec![65532.into()])?,
+            Some((-1).into())
+        );
+
+        let tests: [(&str, Vec<i32>); 7] = [
+            ("load", vec![65533]),
+            ("load", vec![-1]),
+            ("load-offset", vec![1]),
+            ("store", vec![65529]),
+            ("copy", vec![0, 65535, 2]),
+            ("copy", vec![65535, 0, 2]),
+            ("fill", vec![-1, 2]),
+        ];
+        for (name, args) in tests {
+            let args = args.into_iter().map(Value::from).collect();
+            let err = runtime.call(name.into(), args).unwrap_err();
+            let trap = err.downcast_ref::<Trap>().context("not a trap")?;
+            assert_eq!(trap.code(), &TrapCode::MemoryOutOfBounds);
+        }
+
+        // the grown pages are accessible
+        assert_eq!(runtime.call("grow".into(), vec![1.into()])?, Some(1.into()));
+        assert_eq!(
+            runtime.call("load".into(), vec![65536.into()])?,
+            Some(0.into())
+        );
+        runtime.call("fill".into(), vec![131071.into(), 1.into()])?;
+        Ok(())
+    }
+
+    #[test]
+    fn trap_backtrace() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"(module
+              (func $div (param i32) (result i32)
+                (i32.div_s (i32.const 1) (local.get 0)))
+              (func (export "outer") (param i32) (result i32)
+                (nop)
+                (call $div (local.get 0)))
+            )"#,
+        )?;
+        let mut runtime = Runtime::from_bytes(wasm, None)?;
+
+        let err = runtime.call("outer".into(), vec![0.into()]).unwrap_err();
+        assert_eq!(err.to_string(), "integer divide by zero");
+        let trap = err.downcast_ref::<Trap>().context("not a trap")?;
+        assert_eq!(trap.code(), &TrapCode::IntegerDivideByZero);
+        assert_eq!(
+            trap.backtrace(),
+            &[
+                FrameInfo {
+                    func_idx: 0,
+                    func_name: None,
+                    pc: 2,
+                },
+                FrameInfo {
+                    func_idx: 1,
+                    func_name: Some("outer".into()),
+                    pc: 2,
+                },
+            ]
+        );
+        assert_eq!(
+            format!("{trap:#}"),
+            "integer divide by zero\nwasm backtrace:\n  0: func[0] @ pc 2\n  1: func[1] <outer> @ pc 2"
+        );
+
+        // the runtime can be called again after the trap
+        assert_eq!(
+            runtime.call("outer".into(), vec![1.into()])?,
+            Some(1.into())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn stack_limits() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"(module
+              (func $rec (export "rec") (param i32) (result i32)
+                (if (result i32) (i32.eqz (local.get 0))
+                  (then (i32.const 0))
+                  (else (i32.add (i32.const 1) (call $rec (i32.sub (local.get 0) (i32.const 1)))))))
+              (func $inf (export "inf")
+                (call $inf))
+              (func (export "locals") (local i64 i64 i64 i64 i64 i64 i64 i64))
+            )"#,
+        )?;
+        let store = Arc::new(Mutex::new(Store::from_bytes(wasm, None)?));
+
+        let mut runtime = Runtime::instantiate(Arc::clone(&store))?;
+        let err = runtime.call("inf".into(), vec![]).unwrap_err();
+        assert_eq!(err.to_string(), "call stack exhausted");
+        let trap = err.downcast_ref::<Trap>().context("not a trap")?;
+        assert_eq!(trap.code(), &TrapCode::StackOverflow);
+        assert_eq!(trap.backtrace().len(), 1024);
+
+        let config = Config::new().max_call_depth(10);
+        let mut runtime = Runtime::instantiate_with_config(Arc::clone(&store), config)?;
+        assert_eq!(runtime.call("rec".into(), vec![9.into()])?, Some(9.into()));
+        let err = runtime.call("rec".into(), vec![10.into()]).unwrap_err();
+        assert_eq!(err.to_string(), "call stack exhausted");
+
+        // each frame leaves the constant 1 on the value stack, and the argument is the local
+        let config = Config::new().max_value_stack(6);
+        let mut runtime = Runtime::instantiate_with_config(store, config)?;
+        assert_eq!(runtime.call("rec".into(), vec![5.into()])?, Some(5.into()));
+        let err = runtime.call("rec".into(), vec![6.into()]).unwrap_err();
+        assert_eq!(err.to_string(), "call stack exhausted");
+
+        // the locals of the frame are also counted
+        let err = runtime.call("locals".into(), vec![]).unwrap_err();
+        assert_eq!(err.to_string(), "call stack exhausted");
+
+        // the missing argument is the error, not the panic
+        let err = runtime.call("rec".into(), vec![]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid arguments: expected [I32], actual []"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn call_arguments() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"(module
+              (func (export "add") (param i32 i64) (result i64)
+                (i64.add (i64.extend_i32_s (local.get 0)) (local.get 1)))
+            )"#,
+        )?;
+        let mut runtime = Runtime::from_bytes(wasm, None)?;
+
+        let tests = [
+            vec![1.into()],
+            vec![1.into(), 2i64.into(), 3.into()],
+            vec![1i64.into(), 2.into()],
+            vec![1.into(), Value::ExternRef(None)],
+        ];
+        for args in tests {
+            let err = runtime.call("add".into(), args.clone()).unwrap_err();
+            assert!(
+                matches!(
+                    err.downcast_ref::<Error>(),
+                    Some(Error::InvalidArguments(..))
+                ),
+                "{args:?}"
+            );
+            let err = runtime.call_resumable("add".into(), args, 100).err();
+            assert!(err.is_some());
+            assert!(runtime.stack.is_empty());
+        }
+
+        let results = runtime.call("add".into(), vec![1.into(), 2i64.into()])?;
+        assert_eq!(results, Some(3i64.into()));
+        assert!(runtime.stack.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn fuel() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"(module
+              (func $sum (export "sum") (param i32) (result i32) (local i32)
+                (loop
+                  (local.set 1 (i32.add (local.get 1) (local.get 0)))
+                  (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))
+                  (br_if 0))
+                (local.get 1))
+              (func (export "call-sum") (param i32) (result i32)
+                (i32.add (i32.const 1) (call $sum (local.get 0))))
+            )"#,
+        )?;
+        let store = Arc::new(Mutex::new(Store::from_bytes(wasm, None)?));
+
+        let mut runtime = Runtime::instantiate(Arc::clone(&store))?;
+        assert_eq!(runtime.fuel(), None);
+        assert!(runtime.add_fuel(10).is_err());
+
+        let config = Config::new().consume_fuel(true);
+        let mut runtime = Runtime::instantiate_with_config(Arc::clone(&store), config)?;
+        assert_eq!(runtime.fuel(), Some(0));
+        runtime.add_fuel(1000)?;
+        assert_eq!(
+            runtime.call("sum".into(), vec![10.into()])?,
+            Some(55.into())
+        );
+        // 10 iterations of 9 instructions, and local.get at last
+        assert_eq!(runtime.fuel(), Some(1000 - 91));
+
+        let err = runtime
+            .call("call-sum".into(), vec![1000.into()])
+            .unwrap_err();
+        assert_eq!(err.to_string(), "all fuel consumed");
+        let trap = err.downcast_ref::<Trap>().context("not a trap")?;
+        assert_eq!(trap.code(), &TrapCode::OutOfFuel);
+        assert_eq!(trap.backtrace().len(), 2);
+        assert_eq!(runtime.fuel(), Some(0));
+
+        // refuel until the suspended call is finished
+        let results = loop {
+            runtime.add_fuel(100)?;
+            match runtime.resume() {
+                Ok(results) => break results,
+                Err(e) => {
+                    let trap = e.downcast_ref::<Trap>().context("not a trap")?;
+                    assert_eq!(trap.code(), &TrapCode::OutOfFuel);
+                }
+            }
+        };
+        assert_eq!(results, vec![500501.into()]);
+        assert!(runtime.resume().is_err());
+
+        let config = Config::new()
+            .consume_fuel(true)
+            .fuel_cost(|inst| match inst {
+                Instruction::Call(_) => 100,
+                _ => 0,
+            });
+        let mut runtime = Runtime::instantiate_with_config(store, config)?;
+        runtime.add_fuel(99)?;
+        assert!(runtime.call("call-sum".into(), vec![10.into()]).is_err());
+        runtime.add_fuel(1)?;
+        assert_eq!(runtime.resume()?, vec![56.into()]);
+        assert_eq!(runtime.fuel(), Some(0));
+        Ok(())
+    }
+
+    #[test]
+    fn fuel_in_callback() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"(module
+              (import "env" "sum" (func $host_sum (param i32) (result i32)))
+              (func $sum (export "sum") (param i32) (result i32) (local i32)
+                (loop
+                  (local.set 1 (i32.add (local.get 1) (local.get 0)))
+                  (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))
+                  (br_if 0))
+                (local.get 1))
+              (func (export "run") (param i32) (result i32)
+                (i32.add (i32.const 1) (call $host_sum (local.get 0))))
+            )"#,
+        )?;
+        let env = HostModule::new("env").func("sum", |caller: Caller, n: i32| -> Result<i32> {
+            let results = caller.call("sum", vec![n.into()])?;
+            Ok(results[0].clone().into())
+        });
+        let store = Store::from_bytes(wasm, Some(vec![Box::new(env)]))?;
+        let config = Config::new().consume_fuel(true);
+        let mut runtime = Runtime::instantiate_with_config(Arc::new(Mutex::new(store)), config)?;
+
+        // the host function can't be resumed after refueling
+        runtime.add_fuel(100)?;
+        let err = runtime.call("run".into(), vec![100.into()]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::CallbackOutOfFuel(module, field)) if module == "env" && field == "sum"
+        ));
+        assert_eq!(runtime.fuel(), Some(0));
+        runtime.add_fuel(10000)?;
+        let err = runtime.resume().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::NothingToResume)
+        ));
+        assert!(runtime.stack.is_empty());
+
+        assert_eq!(
+            runtime.call("run".into(), vec![100.into()])?,
+            Some(5051.into())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn call_resumable() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"(module
+              (func $sum (export "sum") (param i32) (result i32) (local i32)
+                (loop
+                  (local.set 1 (i32.add (local.get 1) (local.get 0)))
+                  (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))
+                  (br_if 0))
+                (local.get 1))
+              (func (export "call-sum") (param i32) (result i32)
+                (i32.add (i32.const 1) (call $sum (local.get 0))))
+            )"#,
+        )?;
+        let store = Arc::new(Mutex::new(Store::from_bytes(wasm, None)?));
+        let mut runtime = Runtime::instantiate(Arc::clone(&store))?;
+
+        let result = runtime.call_resumable("sum".into(), vec![10.into()], 1000)?;
+        let CallResult::Finished(values) = result else {
+            panic!("call is paused");
+        };
+        assert_eq!(values, vec![55.into()]);
+
+        // two calls are interleaved on the runtime
+        let mut calls = vec![];
+        for n in [100, 1000] {
+            match runtime.call_resumable("call-sum".into(), vec![n.into()], 50)? {
+                CallResult::Paused(call) => calls.push(call),
+                CallResult::Finished(_) => panic!("call is finished"),
+            }
+        }
+        assert_eq!(calls[0].depth(), 2);
+        assert_eq!(runtime.call("sum".into(), vec![3.into()])?, Some(6.into()));
+
+        let mut results = vec![];
+        let mut pauses = 0;
+        while !calls.is_empty() {
+            let call = calls.remove(0);
+            match call.resume(&mut runtime, 50)? {
+                CallResult::Paused(call) => {
+                    pauses += 1;
+                    calls.push(call);
+                }
+                CallResult::Finished(values) => results.extend(values),
+            }
+        }
+        assert_eq!(results, vec![5051.into(), 500501.into()]);
+        // 9 instructions per iteration, 50 steps at a time
+        assert_eq!(pauses, 9 * 100 / 50 + 9 * 1000 / 50 - 2);
+
+        let CallResult::Paused(call) = runtime.call_resumable("sum".into(), vec![10.into()], 0)?
+        else {
+            panic!("call is finished");
+        };
+        let mut other = Runtime::with_store(Arc::new(Mutex::new(Store::default())));
+        let err = call.resume(&mut other, 100).unwrap_err();
+        assert_eq!(err.to_string(), "resumable call belongs to another store");
+        Ok(())
+    }
+
+    #[test]
+    fn call_resumable_with_callback() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"(module
+              (import "env" "sum" (func $host_sum (param i32) (result i32)))
+              (func $sum (export "sum") (param i32) (result i32) (local i32)
+                (loop
+                  (local.set 1 (i32.add (local.get 1) (local.get 0)))
+                  (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))
+                  (br_if 0))
+                (local.get 1))
+              (func (export "run") (param i32) (result i32)
+                (i32.add (i32.const 1) (call $host_sum (local.get 0))))
+            )"#,
+        )?;
+        let env = HostModule::new("env").func("sum", |caller: Caller, n: i32| -> Result<i32> {
+            let results = caller.call("sum", vec![n.into()])?;
+            Ok(results[0].clone().into())
+        });
+        let mut runtime = Runtime::from_bytes(wasm, Some(vec![Box::new(env)]))?;
+
+        // the callback uses up the budget, so the call is paused after the host function
+        let result = runtime.call_resumable("run".into(), vec![100.into()], 10)?;
+        let CallResult::Paused(call) = result else {
+            panic!("call is finished");
+        };
+        assert_eq!(call.depth(), 1);
+        let CallResult::Finished(values) = call.resume(&mut runtime, 10)? else {
+            panic!("call is paused");
+        };
+        assert_eq!(values, vec![5051.into()]);
+
+        // the callback within the budget doesn't pause the call
+        let result = runtime.call_resumable("run".into(), vec![10.into()], 1000)?;
+        let CallResult::Finished(values) = result else {
+            panic!("call is paused");
+        };
+        assert_eq!(values, vec![56.into()]);
+        Ok(())
+    }
+
+    #[test]
+    fn epoch_interruption() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"(module
+              (func (export "spin")
+                (loop (br 0)))
+              (func (export "add") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1)))
+            )"#,
+        )?;
+        let store = Arc::new(Mutex::new(Store::from_bytes(wasm, None)?));
+
+        let mut runtime = Runtime::instantiate(Arc::clone(&store))?;
+        let handle = runtime.interrupt_handle();
+        runtime.set_epoch_deadline(1);
+        let watchdog = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            handle.increment_epoch();
+        });
+        let err = runtime.call("spin".into(), vec![]).unwrap_err();
+        watchdog.join().unwrap();
+        assert_eq!(err.to_string(), "interrupted");
+        let trap = err.downcast_ref::<Trap>().context("not a trap")?;
+        assert_eq!(trap.code(), &TrapCode::Interrupted);
+
+        // the deadline is already reached
+        let err = runtime.call("add".into(), vec![1.into(), 2.into()]);
+        assert!(err.is_err());
+        runtime.set_epoch_deadline(1);
+        assert_eq!(
+            runtime.call("add".into(), vec![1.into(), 2.into()])?,
+            Some(3.into())
+        );
+
+        let mut runtime = Runtime::instantiate(store)?;
+        let count = Arc::new(Mutex::new(0));
+        let counter = Arc::clone(&count);
+        runtime.set_epoch_deadline(0);
+        runtime.epoch_deadline_callback(move || {
+            let mut counter = counter.lock().unwrap();
+            *counter += 1;
+            if *counter == 5 {
+                anyhow::bail!("timeout");
+            }
+            Ok(0)
+        });
+        let err = runtime.call("spin".into(), vec![]).unwrap_err();
+        assert_eq!(err.to_string(), "timeout");
+        assert_eq!(*count.lock().unwrap(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn interrupt_handle_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<InterruptHandle>();
+    }
+
+    #[test]
+    fn run_on_another_thread() -> Result<()> {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Runtime>();
+        assert_send_sync::<Store>();
+
+        let wasm = wat::parse_str(
+            r#"(module
+              (memory (export "memory") 1)
+              (func (export "store") (param i32 i32)
+                (i32.store (local.get 0) (local.get 1)))
+              (func (export "load") (param i32) (result i32)
+                (i32.load (local.get 0)))
+            )"#,
+        )?;
+        let mut runtime = Runtime::from_bytes(wasm, None)?;
+        runtime.call("store".into(), vec![0.into(), 42.into()])?;
+
+        let handle = std::thread::spawn(move || runtime.call("load".into(), vec![0.into()]));
+        let result = handle.join().expect("cannot join thread")?;
+        assert_eq!(result, Some(42.into()));
+        Ok(())
+    }
+
+    #[test]
+    fn multi_value() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"(module
+              (func $swap (export "swap") (param i32 i32) (result i32 i32)
+                (local.get 1) (local.get 0))
+              (func (export "call-swap") (result i32)
+                (call $swap (i32.const 1) (i32.const 3))
+                (i32.sub))
+              (func (export "block-params") (result i32 i64)
+                (i32.const 1) (i32.const 2)
+                (block (param i32 i32) (result i32 i64)
+                  (i32.add) (i64.const 7)))
+              (func (export "if-params") (param i32) (result i32)
+                (i32.const 10) (i32.const 3) (local.get 0)
+                (if (param i32 i32) (result i32)
+                  (then (i32.add))
+                  (else (i32.sub))))
+              (func (export "loop-params") (param i32) (result i32)
+                (i32.const 0) (local.get 0)
+                (loop (param i32 i32) (result i32)
+                  (local.set 0)
+                  (i32.add (local.get 0))
+                  (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))
+                  (br_if 0 (local.get 0))
+                  (drop)))
+            )"#,
+        )?;
+        let mut runtime = Runtime::from_bytes(wasm, None)?;
+
+        let tests = [
+            ("swap", vec![1, 2], vec![Value::I32(2), Value::I32(1)]),
+            ("call-swap", vec![], vec![Value::I32(2)]),
+            ("block-params", vec![], vec![Value::I32(3), Value::I64(7)]),
+            ("if-params", vec![1], vec![Value::I32(13)]),
+            ("if-params", vec![0], vec![Value::I32(7)]),
+            ("loop-params", vec![4], vec![Value::I32(10)]),
+        ];
+        for (name, args, want) in tests {
+            let args = args.into_iter().map(Value::from).collect();
+            let got = runtime.call_multi(name.into(), args)?;
+            assert_eq!(got, want, "func {} fail", name);
+        }
+        Ok(())
+    }
 }
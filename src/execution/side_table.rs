@@ -0,0 +1,142 @@
+use super::error::Error;
+use crate::binary::{
+    instruction::Instruction,
+    types::{BlockType, FuncType},
+};
+use anyhow::{Context as _, Result};
+
+// jump target of the block, loop, if and else instructions
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlTarget {
+    pub else_pc: Option<usize>, // pc of the else instruction, only for if
+    pub end_pc: usize,          // pc of the matching end instruction
+    pub param_arity: usize,     // param arity of the block
+    pub arity: usize,           // result arity when reaching the end
+    pub branch_arity: usize,    // arity when branching to the label
+}
+
+// side table of the structured control instructions in a function,
+// which is computed once so that the runtime doesn't need to scan
+// the instructions to find the end of block every time.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SideTable(Vec<Option<ControlTarget>>);
+
+impl SideTable {
+    pub fn new(insts: &[Instruction], func_types: &[FuncType]) -> Result<Self> {
+        let mut targets: Vec<Option<ControlTarget>> = vec![None; insts.len()];
+        let mut blocks: Vec<usize> = vec![];
+
+        for (pc, inst) in insts.iter().enumerate() {
+            match inst {
+                Instruction::Block(block) | Instruction::Loop(block) | Instruction::If(block) => {
+                    let (params, results) = block_arity(&block.block_type, func_types)?;
+                    // NOTE: branching to the loop goes back to the start of the loop,
+                    // so the branch carries the params of the loop instead of the results.
+                    let branch_arity = match inst {
+                        Instruction::Loop(_) => params,
+                        _ => results,
+                    };
+                    targets[pc] = Some(ControlTarget {
+                        else_pc: None,
+                        end_pc: 0,
+                        param_arity: params,
+                        arity: results,
+                        branch_arity,
+                    });
+                    blocks.push(pc);
+                }
+                Instruction::Else => {
+                    let start = *blocks
+                        .last()
+                        .with_context(|| Error::NotFoundControlTarget(pc))?;
+                    let target = targets[start]
+                        .as_mut()
+                        .with_context(|| Error::NotFoundControlTarget(start))?;
+                    target.else_pc = Some(pc);
+                }
+                Instruction::End => {
+                    // NOTE: the end of function doesn't have a block
+                    let Some(start) = blocks.pop() else {
+                        continue;
+                    };
+                    let target = targets[start]
+                        .as_mut()
+                        .with_context(|| Error::NotFoundControlTarget(start))?;
+                    target.end_pc = pc;
+
+                    if let Some(else_pc) = target.else_pc {
+                        targets[else_pc] = Some(ControlTarget {
+                            else_pc: None,
+                            ..target.clone()
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self(targets))
+    }
+
+    pub fn get(&self, pc: usize) -> Result<&ControlTarget> {
+        self.0
+            .get(pc)
+            .and_then(Option::as_ref)
+            .with_context(|| Error::NotFoundControlTarget(pc))
+    }
+}
+
+fn block_arity(block_type: &BlockType, func_types: &[FuncType]) -> Result<(usize, usize)> {
+    let (params, results) = block_type
+        .signature(func_types)
+        .with_context(|| Error::InvalidBlockType(block_type.clone()))?;
+    Ok((params.len(), results.len()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ControlTarget, SideTable};
+    use crate::binary::{
+        instruction::Instruction,
+        types::{Block, BlockType, FuncType, ValueType},
+    };
+    use anyhow::Result;
+
+    #[test]
+    fn nested_blocks() -> Result<()> {
+        let block = |block_type| Block { block_type };
+        let insts = vec![
+            Instruction::Block(block(BlockType::Empty)),     // 0
+            Instruction::Loop(block(BlockType::TypeIdx(0))), // 1
+            Instruction::I32Const(1),                        // 2
+            Instruction::If(block(BlockType::Value(vec![ValueType::I32]))), // 3
+            Instruction::I32Const(2),                        // 4
+            Instruction::Else,                               // 5
+            Instruction::I32Const(3),                        // 6
+            Instruction::End,                                // 7
+            Instruction::End,                                // 8
+            Instruction::Drop,                               // 9
+            Instruction::End,                                // 10
+            Instruction::End,                                // 11
+        ];
+        let func_types = vec![FuncType {
+            params: vec![ValueType::I32],
+            results: vec![ValueType::I32, ValueType::I32],
+        }];
+        let table = SideTable::new(&insts, &func_types)?;
+
+        let target = |else_pc, end_pc, param_arity, arity, branch_arity| ControlTarget {
+            else_pc,
+            end_pc,
+            param_arity,
+            arity,
+            branch_arity,
+        };
+        assert_eq!(table.get(0)?, &target(None, 10, 0, 0, 0));
+        assert_eq!(table.get(1)?, &target(None, 8, 1, 2, 1));
+        assert_eq!(table.get(3)?, &target(Some(5), 7, 0, 1, 1));
+        assert_eq!(table.get(5)?, &target(None, 7, 0, 1, 1));
+        assert!(table.get(2).is_err());
+        Ok(())
+    }
+}
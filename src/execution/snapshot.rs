@@ -0,0 +1,454 @@
+use super::{
+    error::Error,
+    indices::{FuncAddr, FuncIdx, InstanceAddr},
+    module::{FuncInst, MAX_PAGES, PAGE_SIZE},
+    store::Store,
+    value::Value,
+};
+use anyhow::{bail, Context as _, Result};
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read, Write},
+    sync::Arc,
+};
+
+const MAGIC: &[u8; 4] = b"\0cws";
+const VERSION: u32 = 1;
+
+// value in the snapshot, the function reference is kept as the address in the store
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotValue {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    FuncRef(Option<FuncAddr>),
+    ExternRef(Option<usize>),
+}
+
+// image of the mutable state of the store, which can be restored to the store
+// which has the same instances, e.g. the one instantiated from the same modules.
+// NOTE: the dropped element and data segments are not included.
+//
+// the binary format is like the wasm binary:
+//   magic "\0cws", version u32 (little endian),
+//   vec(memory data), vec(global value), vec(vec(table element))
+// where vec is the u32 (leb128) length followed by the items,
+// and the value is the type byte of the wasm followed by the payload.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snapshot {
+    pub memories: Vec<Vec<u8>>,
+    pub globals: Vec<SnapshotValue>,
+    pub tables: Vec<Vec<SnapshotValue>>,
+}
+
+impl Store {
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        let memories = self
+            .memory
+            .iter()
+            .map(|memory| memory.lock().expect("cannot lock memory").data.clone())
+            .collect();
+
+        // the internal functions are looked up by the instance and the index,
+        // instead of comparing the function bodies
+        let func_addrs: HashMap<(InstanceAddr, FuncIdx), FuncAddr> = self
+            .funcs
+            .iter()
+            .enumerate()
+            .filter_map(|(addr, func)| match func {
+                FuncInst::Internal(func) => Some(((func.code.instance, func.code.func_idx), addr)),
+                FuncInst::External(_) => None,
+            })
+            .collect();
+
+        let mut globals = Vec::with_capacity(self.globals.len());
+        for global in self.globals.iter() {
+            let value = &global.lock().expect("cannot lock global").value;
+            globals.push(self.snapshot_value(value, &func_addrs)?);
+        }
+
+        let mut tables = Vec::with_capacity(self.tables.len());
+        for table in self.tables.iter() {
+            let table = table.lock().expect("cannot lock table");
+            let elements = table
+                .elements
+                .iter()
+                .map(|value| self.snapshot_value(value, &func_addrs))
+                .collect::<Result<Vec<_>>>()?;
+            tables.push(elements);
+        }
+
+        Ok(Snapshot {
+            memories,
+            globals,
+            tables,
+        })
+    }
+
+    // the snapshot is checked entirely before the store is modified
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<()> {
+        if snapshot.memories.len() != self.memory.len()
+            || snapshot.globals.len() != self.globals.len()
+            || snapshot.tables.len() != self.tables.len()
+        {
+            bail!(Error::SnapshotMismatch(
+                "the number of memories, globals or tables is different".into()
+            ));
+        }
+
+        for (i, (memory, data)) in self.memory.iter().zip(snapshot.memories.iter()).enumerate() {
+            let max = memory.lock().expect("cannot lock memory").max;
+            let pages = data.len() / PAGE_SIZE as usize;
+            if data.len() % PAGE_SIZE as usize != 0 || pages > max.unwrap_or(MAX_PAGES) as usize {
+                bail!(Error::SnapshotMismatch(format!(
+                    "invalid size of memory {i}"
+                )));
+            }
+        }
+
+        // the immutable globals keep their values, but the types are still checked
+        let mut globals = Vec::with_capacity(snapshot.globals.len());
+        for (i, (global, value)) in self.globals.iter().zip(snapshot.globals.iter()).enumerate() {
+            let value = self.restore_value(value)?;
+            let global = global.lock().expect("cannot lock global");
+            if value.value_type() != global.value.value_type() {
+                bail!(Error::SnapshotMismatch(format!(
+                    "type of global {i} is different"
+                )));
+            }
+            globals.push(global.mutability.then_some(value));
+        }
+
+        let mut tables = Vec::with_capacity(snapshot.tables.len());
+        for (i, (table, elements)) in self.tables.iter().zip(snapshot.tables.iter()).enumerate() {
+            let table = table.lock().expect("cannot lock table");
+            if elements.len() > table.max.unwrap_or(u32::MAX) as usize {
+                bail!(Error::SnapshotMismatch(format!(
+                    "invalid size of table {i}"
+                )));
+            }
+            let elements = elements
+                .iter()
+                .map(|value| {
+                    let value = self.restore_value(value)?;
+                    if value.value_type() != table.elem_type {
+                        bail!(Error::SnapshotMismatch(format!(
+                            "type of table {i} is different"
+                        )));
+                    }
+                    Ok(value)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            tables.push(elements);
+        }
+
+        for (memory, data) in self.memory.iter().zip(snapshot.memories.iter()) {
+            memory.lock().expect("cannot lock memory").data = data.clone();
+        }
+        for (global, value) in self.globals.iter().zip(globals) {
+            if let Some(value) = value {
+                global.lock().expect("cannot lock global").value = value;
+            }
+        }
+        for (table, elements) in self.tables.iter().zip(tables) {
+            table.lock().expect("cannot lock table").elements = elements;
+        }
+        Ok(())
+    }
+
+    fn snapshot_value(
+        &self,
+        value: &Value,
+        func_addrs: &HashMap<(InstanceAddr, FuncIdx), FuncAddr>,
+    ) -> Result<SnapshotValue> {
+        let value = match value {
+            Value::I32(v) => SnapshotValue::I32(*v),
+            Value::I64(v) => SnapshotValue::I64(*v),
+            Value::F32(v) => SnapshotValue::F32(*v),
+            Value::F64(v) => SnapshotValue::F64(*v),
+            Value::FuncRef(None) => SnapshotValue::FuncRef(None),
+            Value::FuncRef(Some(func)) => {
+                let addr = match func.as_ref() {
+                    FuncInst::Internal(func) => func_addrs
+                        .get(&(func.code.instance, func.code.func_idx))
+                        .copied(),
+                    // the external functions are compared without the bodies
+                    FuncInst::External(_) => self.funcs.iter().position(|f| f == func.as_ref()),
+                };
+                let addr = addr.with_context(|| {
+                    Error::SnapshotMismatch("unknown function reference".into())
+                })?;
+                SnapshotValue::FuncRef(Some(addr))
+            }
+            Value::ExternRef(v) => SnapshotValue::ExternRef(*v),
+        };
+        Ok(value)
+    }
+
+    fn restore_value(&self, value: &SnapshotValue) -> Result<Value> {
+        let value = match value {
+            SnapshotValue::I32(v) => Value::I32(*v),
+            SnapshotValue::I64(v) => Value::I64(*v),
+            SnapshotValue::F32(v) => Value::F32(*v),
+            SnapshotValue::F64(v) => Value::F64(*v),
+            SnapshotValue::FuncRef(None) => Value::FuncRef(None),
+            SnapshotValue::FuncRef(Some(addr)) => {
+                let func = self
+                    .funcs
+                    .get(*addr)
+                    .with_context(|| Error::NotFoundFunction(*addr))?;
+                Value::FuncRef(Some(Arc::new(func.clone())))
+            }
+            SnapshotValue::ExternRef(v) => Value::ExternRef(*v),
+        };
+        Ok(value)
+    }
+}
+
+impl Snapshot {
+    pub fn from_bytes<T: AsRef<[u8]>>(b: T) -> Result<Self> {
+        Self::read_from(&mut Cursor::new(b))
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        self.write_to(&mut buf)?;
+        Ok(buf)
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+
+        write_len(writer, self.memories.len())?;
+        for data in self.memories.iter() {
+            write_len(writer, data.len())?;
+            writer.write_all(data)?;
+        }
+
+        write_len(writer, self.globals.len())?;
+        for value in self.globals.iter() {
+            write_value(writer, value)?;
+        }
+
+        write_len(writer, self.tables.len())?;
+        for elements in self.tables.iter() {
+            write_len(writer, elements.len())?;
+            for value in elements {
+                write_value(writer, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!(Error::InvalidSnapshot("invalid magic".into()));
+        }
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version)?;
+        if u32::from_le_bytes(version) != VERSION {
+            bail!(Error::InvalidSnapshot("unsupported version".into()));
+        }
+
+        let mut memories = vec![];
+        for _ in 0..read_len(reader)? {
+            let len = read_len(reader)?;
+            let mut data = vec![];
+            reader.take(len as u64).read_to_end(&mut data)?;
+            if data.len() != len {
+                bail!(Error::InvalidSnapshot(
+                    "unexpected end of memory data".into()
+                ));
+            }
+            memories.push(data);
+        }
+
+        let mut globals = vec![];
+        for _ in 0..read_len(reader)? {
+            globals.push(read_value(reader)?);
+        }
+
+        let mut tables = vec![];
+        for _ in 0..read_len(reader)? {
+            let mut elements = vec![];
+            for _ in 0..read_len(reader)? {
+                elements.push(read_value(reader)?);
+            }
+            tables.push(elements);
+        }
+
+        Ok(Self {
+            memories,
+            globals,
+            tables,
+        })
+    }
+}
+
+fn write_len(writer: &mut impl Write, len: usize) -> Result<()> {
+    leb128::write::unsigned(writer, len as u64)?;
+    Ok(())
+}
+
+fn read_len(reader: &mut impl Read) -> Result<usize> {
+    let len = leb128::read::unsigned(reader)?;
+    Ok(u32::try_from(len)? as usize)
+}
+
+// the reference is written as the address plus one, and zero is the null
+fn write_value(writer: &mut impl Write, value: &SnapshotValue) -> Result<()> {
+    match value {
+        SnapshotValue::I32(v) => {
+            writer.write_all(&[0x7F])?;
+            leb128::write::signed(writer, *v as i64)?;
+        }
+        SnapshotValue::I64(v) => {
+            writer.write_all(&[0x7E])?;
+            leb128::write::signed(writer, *v)?;
+        }
+        SnapshotValue::F32(v) => {
+            writer.write_all(&[0x7D])?;
+            writer.write_all(&v.to_le_bytes())?;
+        }
+        SnapshotValue::F64(v) => {
+            writer.write_all(&[0x7C])?;
+            writer.write_all(&v.to_le_bytes())?;
+        }
+        SnapshotValue::FuncRef(v) => {
+            writer.write_all(&[0x70])?;
+            leb128::write::unsigned(writer, v.map_or(0, |addr| addr as u64 + 1))?;
+        }
+        SnapshotValue::ExternRef(v) => {
+            writer.write_all(&[0x6F])?;
+            leb128::write::unsigned(writer, v.map_or(0, |addr| addr as u64 + 1))?;
+        }
+    }
+    Ok(())
+}
+
+fn read_value(reader: &mut impl Read) -> Result<SnapshotValue> {
+    let mut ty = [0u8; 1];
+    reader.read_exact(&mut ty)?;
+    let value = match ty[0] {
+        0x7F => SnapshotValue::I32(i32::try_from(leb128::read::signed(reader)?)?),
+        0x7E => SnapshotValue::I64(leb128::read::signed(reader)?),
+        0x7D => {
+            let mut buf = [0u8; 4];
+            reader.read_exact(&mut buf)?;
+            SnapshotValue::F32(f32::from_le_bytes(buf))
+        }
+        0x7C => {
+            let mut buf = [0u8; 8];
+            reader.read_exact(&mut buf)?;
+            SnapshotValue::F64(f64::from_le_bytes(buf))
+        }
+        0x70 => SnapshotValue::FuncRef(read_ref(reader)?),
+        0x6F => SnapshotValue::ExternRef(read_ref(reader)?),
+        ty => bail!(Error::InvalidSnapshot(format!(
+            "invalid value type: {ty:#x}"
+        ))),
+    };
+    Ok(value)
+}
+
+fn read_ref(reader: &mut impl Read) -> Result<Option<usize>> {
+    let v = leb128::read::unsigned(reader)?;
+    Ok(v.checked_sub(1).map(|addr| addr as usize))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Snapshot, SnapshotValue};
+    use crate::{Runtime, Store, Value};
+    use anyhow::Result;
+
+    #[test]
+    fn snapshot_and_restore() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"(module
+              (memory (export "memory") 1)
+              (global $count (mut i32) (i32.const 0))
+              (global $ratio (mut f64) (f64.const 0.5))
+              (table $table 2 funcref)
+              (type $get (func (result i32)))
+              (func $count (result i32) (global.get $count))
+              (func $init
+                (i32.store (i32.const 0) (i32.const 7))
+                (global.set $count (i32.const 10))
+                (table.set $table (i32.const 1) (ref.func $count)))
+              (func (export "step") (result i32)
+                (i32.store (i32.const 0) (i32.add (i32.load (i32.const 0)) (i32.const 1)))
+                (global.set $count (i32.add (global.get $count) (i32.const 1)))
+                (global.set $ratio (f64.mul (global.get $ratio) (f64.const 2)))
+                (table.set $table (i32.const 1) (ref.null func))
+                (i32.load (i32.const 0)))
+              (func (export "count") (result i32)
+                (call_indirect (type $get) (i32.const 1)))
+              (elem declare func $count)
+              (start $init)
+            )"#,
+        )?;
+        let mut runtime = Runtime::from_bytes(&wasm, None)?;
+        let snapshot = runtime.store.lock().unwrap().snapshot()?;
+        assert_eq!(snapshot.memories[0][..4], [7, 0, 0, 0]);
+        assert_eq!(
+            snapshot.globals,
+            vec![SnapshotValue::I32(10), SnapshotValue::F64(0.5)]
+        );
+        assert_eq!(
+            snapshot.tables,
+            vec![vec![
+                SnapshotValue::FuncRef(None),
+                SnapshotValue::FuncRef(Some(0))
+            ]]
+        );
+
+        // the snapshot is written to and read from the bytes
+        let bytes = snapshot.to_bytes()?;
+        assert_eq!(Snapshot::from_bytes(&bytes)?, snapshot);
+
+        for _ in 0..2 {
+            assert_eq!(runtime.call("step".into(), vec![])?, Some(Value::I32(8)));
+            assert!(runtime.call("count".into(), vec![]).is_err());
+            runtime
+                .store
+                .lock()
+                .unwrap()
+                .restore(&Snapshot::from_bytes(&bytes)?)?;
+            assert_eq!(runtime.call("count".into(), vec![])?, Some(Value::I32(10)));
+        }
+
+        let mut other = Store::from_bytes(wat::parse_str("(module)")?, None)?;
+        assert!(other.restore(&snapshot).is_err());
+
+        // the values must fit the types and the limits of the store
+        let mut store = runtime.store.lock().unwrap();
+        let invalid = [
+            Snapshot {
+                memories: vec![vec![0; 100]],
+                ..snapshot.clone()
+            },
+            Snapshot {
+                globals: vec![SnapshotValue::F64(1.0), SnapshotValue::F64(0.5)],
+                ..snapshot.clone()
+            },
+            Snapshot {
+                tables: vec![vec![SnapshotValue::ExternRef(Some(1))]],
+                ..snapshot.clone()
+            },
+        ];
+        for invalid in invalid {
+            assert!(store.restore(&invalid).is_err());
+        }
+        assert_eq!(store.snapshot()?, snapshot);
+        drop(store);
+
+        assert!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Snapshot::from_bytes(b"\0asm\x01\0\0\0").is_err());
+        Ok(())
+    }
+}
@@ -1,18 +1,21 @@
-use super::{error::Error, module::*, value::Value};
+use super::{
+    compiled::Module,
+    error::Error,
+    indices::*,
+    module::*,
+    value::{ExternalVal, Value},
+};
 use crate::{
-    binary::{
-        module::{Decoder, Module},
-        types::{Expr, ExprValue, FuncType, Mutability},
+    binary::types::{
+        DataMode, ElementMode, Expr, ExprValue, FuncType, ImportKind, Limits, Mutability,
     },
     Importer,
 };
 use anyhow::{bail, Context, Result};
 use std::{
-    cell::RefCell,
     collections::HashMap,
-    fs,
-    io::{Cursor, Read},
-    rc::Rc,
+    io::Read,
+    sync::{Arc, Mutex},
 };
 
 #[derive(Debug)]
@@ -23,22 +26,32 @@ pub enum Exports {
     Global(GlobalInst),
 }
 
+// address of the external value which is imported by the module
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ExternAddr {
+    Func(FuncAddr),
+    Table(TableAddr),
+    Memory(MemAddr),
+    Global(GlobalAddr),
+}
+
+// the instances in the store share the address spaces,
+// and each module instance maps its indices to the addresses.
+// https://webassembly.github.io/spec/core/exec/runtime.html#store
 #[derive(Default)]
 pub struct Store {
     pub funcs: Vec<FuncInst>,
     pub tables: Vec<TableInst>,
     pub memory: Vec<MemoryInst>,
     pub globals: Vec<GlobalInst>,
-    pub imports: Option<HashMap<String, Box<dyn Importer>>>,
-    pub module: ModuleInst,
-    pub start: Option<u32>,
+    pub elems: Vec<ElemInst>,
+    pub datas: Vec<DataInst>,
+    pub instances: Vec<ModuleInst>,
 }
 
 impl Store {
     pub fn from_file(file: &str, imports: Option<Vec<Box<dyn Importer>>>) -> Result<Self> {
-        let file = fs::File::open(file)?;
-        let mut decoder = Decoder::new(file);
-        let module = decoder.decode()?;
+        let module = Module::from_file(file)?;
         Self::new(&module, imports)
     }
 
@@ -46,8 +59,7 @@ impl Store {
         reader: &mut impl Read,
         imports: Option<Vec<Box<dyn Importer>>>,
     ) -> Result<Self> {
-        let mut decoder = Decoder::new(reader);
-        let module = decoder.decode()?;
+        let module = Module::from_reader(reader)?;
         Self::new(&module, imports)
     }
 
@@ -55,244 +67,488 @@ impl Store {
         b: T,
         imports: Option<Vec<Box<dyn Importer>>>,
     ) -> Result<Self> {
-        let buf = Cursor::new(b);
-        let mut decoder = Decoder::new(buf);
-        let module = decoder.decode()?;
+        let module = Module::from_bytes(b)?;
         Self::new(&module, imports)
     }
 
+    // store with the single instance of the module, the imports are resolved by the importers
     pub fn new(module: &Module, importers: Option<Vec<Box<dyn Importer>>>) -> Result<Self> {
-        let func_type_idxs = match module.function_section {
-            Some(ref functions) => functions.clone(),
-            _ => vec![],
-        };
+        if module.module.import_section.is_some() && importers.is_none() {
+            bail!("module has import section, but not found any imported module");
+        }
 
-        let mut funcs = vec![];
-        let mut tables = vec![];
-        let mut globals = vec![];
-        let mut memories = vec![];
+        let hosts = importers
+            .unwrap_or_default()
+            .into_iter()
+            .map(|importer| (importer.name().to_string(), Arc::from(importer)))
+            .collect();
 
-        if let Some(ref import_section) = module.import_section {
-            let importers = importers
-                .as_ref()
-                .with_context(|| "module has import section, but not found any imported module")?;
+        let mut store = Self::default();
+        let externs = store.resolve_imports(module, &HashMap::new(), &hosts)?;
+        store.instantiate(module, externs)?;
+        Ok(store)
+    }
 
-            for import_info in import_section {
-                let module_name = import_info.module.as_str();
-                let field = import_info.field.as_str();
+    // the imports are resolved by the named instances first, then by the importers
+    // https://webassembly.github.io/spec/core/exec/modules.html#exec-instantiation
+    pub(crate) fn resolve_imports(
+        &mut self,
+        module: &Module,
+        instances: &HashMap<String, InstanceAddr>,
+        hosts: &HashMap<String, Arc<dyn Importer>>,
+    ) -> Result<Vec<ExternAddr>> {
+        let Some(ref import_section) = module.module.import_section else {
+            return Ok(vec![]);
+        };
+        let func_types = module.module.type_section.as_deref().unwrap_or_default();
 
-                let importers: Vec<_> = importers
-                    .iter()
-                    .filter(|importer| importer.name() == module_name)
-                    .collect();
-                if importers.is_empty() {
-                    bail!("not found import module: {}", module_name);
-                }
-                let importer = importers.first().unwrap();
-
-                match import_info.kind {
-                    crate::binary::types::ImportKind::Func(typeidx) => {
-                        let idx = typeidx as usize;
-                        let func_type = module
-                            .type_section
-                            .as_ref()
-                            .with_context(|| Error::NotFoundTypeSection)?
-                            .get(idx)
-                            .with_context(|| Error::NotFoundFuncType(idx))?;
-
-                        let func_type = FuncType {
-                            params: func_type.params.clone(),
-                            results: func_type.results.clone(),
-                        };
-                        let func = FuncInst::External(ExternalFuncInst {
-                            module: module_name.to_string(),
-                            field: field.to_string(),
-                            func_type,
-                        });
-                        funcs.push(func);
+        let mut externs = Vec::with_capacity(import_section.len());
+        for import_info in import_section {
+            let module_name = import_info.module.as_str();
+            let field = import_info.field.as_str();
+            let incompatible = || Error::IncompatibleImportType(module_name.into(), field.into());
+
+            if let Some(&addr) = instances.get(module_name) {
+                let inst = self.instance(addr)?;
+                let export = inst
+                    .exports
+                    .get(field)
+                    .with_context(|| Error::UnknownImport(module_name.into(), field.into()))?;
+                let resolved = match (&import_info.kind, &export.desc) {
+                    (ImportKind::Func(_), ExternalVal::Func(idx)) => {
+                        ExternAddr::Func(inst.func_addrs[*idx as usize])
                     }
-                    crate::binary::types::ImportKind::Table(_) => {
-                        let table = importer
-                            .resolve_table(module_name, field)?
-                            .with_context(|| Error::NoImports)?; // TODO: define error enum
-                        tables.push(table);
+                    (ImportKind::Table(_), ExternalVal::Table(idx)) => {
+                        ExternAddr::Table(inst.table_addrs[*idx as usize])
                     }
-                    crate::binary::types::ImportKind::Global(_) => {
-                        let global = importer
-                            .resolve_global(module_name, field)?
-                            .with_context(|| Error::NoImports)?;
-                        globals.push(global);
+                    (ImportKind::Memory(_), ExternalVal::Memory(idx)) => {
+                        ExternAddr::Memory(inst.mem_addrs[*idx as usize])
                     }
-                    crate::binary::types::ImportKind::Memory(_) => {
-                        let memory = importer
-                            .resolve_memory(module_name, field)?
-                            .with_context(|| Error::NoImports)?;
-                        memories.push(memory);
+                    (ImportKind::Global(_), ExternalVal::Global(idx)) => {
+                        ExternAddr::Global(inst.global_addrs[*idx as usize])
                     }
+                    _ => bail!(incompatible()),
+                };
+                if !self.import_matches(&import_info.kind, resolved, func_types)? {
+                    bail!(incompatible());
                 }
+                externs.push(resolved);
+                continue;
             }
-        }
 
-        if let Some(ref section) = module.global_section {
-            for global in section {
-                let value = match global.init_expr {
-                    ExprValue::I32(v) => Value::I32(v),
-                    ExprValue::I64(v) => Value::I64(v),
-                    ExprValue::F32(v) => Value::F32(v),
-                    ExprValue::F64(v) => Value::F64(v),
-                };
-                let global = InternalGlobalInst {
-                    value,
-                    mutability: global.global_type.mutability == Mutability::Var,
-                };
-                globals.push(Rc::new(RefCell::new(global)));
+            let importer = hosts
+                .get(module_name)
+                .with_context(|| format!("not found import module: {}", module_name))?;
+            let importer = Arc::clone(importer);
+
+            let resolved = match import_info.kind {
+                ImportKind::Func(typeidx) => {
+                    let idx = typeidx as usize;
+                    let func_type = func_types
+                        .get(idx)
+                        .with_context(|| Error::NotFoundFuncType(idx))?
+                        .clone();
+
+                    // check the signature if the importer can resolve the function
+                    if let Some(host_type) = importer.resolve_func(module_name, field)? {
+                        if host_type != func_type {
+                            bail!(incompatible());
+                        }
+                    }
+
+                    self.funcs.push(FuncInst::External(ExternalFuncInst {
+                        module: module_name.to_string(),
+                        field: field.to_string(),
+                        func_type,
+                        importer,
+                    }));
+                    ExternAddr::Func(self.funcs.len() - 1)
+                }
+                ImportKind::Table(_) => {
+                    let table = importer
+                        .resolve_table(module_name, field)?
+                        .with_context(|| Error::NoImports)?; // TODO: define error enum
+                    self.tables.push(table);
+                    ExternAddr::Table(self.tables.len() - 1)
+                }
+                ImportKind::Global(_) => {
+                    let global = importer
+                        .resolve_global(module_name, field)?
+                        .with_context(|| Error::NoImports)?;
+                    self.globals.push(global);
+                    ExternAddr::Global(self.globals.len() - 1)
+                }
+                ImportKind::Memory(_) => {
+                    let memory = importer
+                        .resolve_memory(module_name, field)?
+                        .with_context(|| Error::NoImports)?;
+                    self.memory.push(memory);
+                    ExternAddr::Memory(self.memory.len() - 1)
+                }
+            };
+            if !self.import_matches(&import_info.kind, resolved, func_types)? {
+                bail!(incompatible());
             }
+            externs.push(resolved);
         }
+        Ok(externs)
+    }
+
+    // the external value must be a subtype of the imported type
+    // https://webassembly.github.io/spec/core/valid/types.html#import-subtyping
+    fn import_matches(
+        &self,
+        kind: &ImportKind,
+        addr: ExternAddr,
+        func_types: &[FuncType],
+    ) -> Result<bool> {
+        // the current size is used as the minimum of the external value
+        let limits_match = |min: u32, max: Option<u32>, limits: &Limits| {
+            let max_matches = match limits.max {
+                Some(expected) => max.is_some_and(|max| max <= expected),
+                None => true,
+            };
+            min >= limits.min && max_matches
+        };
 
-        if let Some(ref code_section) = module.code_section {
-            if code_section.len() != func_type_idxs.len() {
-                bail!("code section length must be equal to function section length");
+        let matches = match (kind, addr) {
+            (ImportKind::Func(typeidx), ExternAddr::Func(addr)) => {
+                let typeidx = *typeidx as usize;
+                let func_type = func_types
+                    .get(typeidx)
+                    .with_context(|| Error::NotFoundFuncType(typeidx))?;
+                self.funcs[addr].func_type() == func_type
             }
-            for (func_body, typeidx) in code_section.iter().zip(func_type_idxs.iter()) {
-                let func_type = module
-                    .type_section
-                    .as_ref()
-                    .with_context(|| "cannot get type section")?
-                    .get(*typeidx as usize)
-                    .with_context(|| "cannot get func type from type section")?
-                    .clone();
-
-                let mut locals = Vec::with_capacity(func_body.locals.len());
-                for local in func_body.locals.iter() {
-                    for _ in 0..local.type_count {
-                        locals.push(local.value_type.clone());
-                    }
-                }
+            (ImportKind::Table(table), ExternAddr::Table(addr)) => {
+                let inst = self.tables[addr].lock().expect("cannot lock table");
+                inst.elem_type == table.elem_type
+                    && limits_match(inst.elements.len() as u32, inst.max, &table.limits)
+            }
+            (ImportKind::Memory(memory), ExternAddr::Memory(addr)) => {
+                let inst = self.memory[addr].lock().expect("cannot lock memory");
+                let pages = (inst.data.len() / PAGE_SIZE as usize) as u32;
+                limits_match(pages, inst.max, &memory.limits)
+            }
+            (ImportKind::Global(global_type), ExternAddr::Global(addr)) => {
+                let inst = self.globals[addr].lock().expect("cannot lock global");
+                inst.value.value_type() == global_type.value_type
+                    && inst.mutability == (global_type.mutability == Mutability::Var)
+            }
+            _ => false,
+        };
+        Ok(matches)
+    }
+
+    // allocate the module instance with the resolved imports and initialize it
+    // https://webassembly.github.io/spec/core/exec/modules.html#alloc-module
+    pub(crate) fn instantiate(
+        &mut self,
+        module: &Module,
+        externs: Vec<ExternAddr>,
+    ) -> Result<InstanceAddr> {
+        let instance = self.instances.len();
+        let mut inst = ModuleInst::allocate(&module.module);
 
-                // NOTE: locals length must be func_type.params + func_body.locals
-                let func = InternalFuncInst {
-                    func_type,
-                    code: Func {
-                        type_idx: *typeidx,
-                        locals,
-                        body: func_body.code.clone(),
-                    },
+        for addr in externs {
+            match addr {
+                ExternAddr::Func(addr) => inst.func_addrs.push(addr),
+                ExternAddr::Table(addr) => inst.table_addrs.push(addr),
+                ExternAddr::Memory(addr) => inst.mem_addrs.push(addr),
+                ExternAddr::Global(addr) => inst.global_addrs.push(addr),
+            }
+        }
+
+        for func in module.funcs.iter() {
+            let func_type = inst
+                .func_types
+                .get(func.type_idx as usize)
+                .with_context(|| "cannot get func type from type section")?
+                .clone();
+
+            // NOTE: locals length must be func_type.params + func_body.locals
+            let func = InternalFuncInst {
+                func_type,
+                code: Func {
+                    instance,
+                    func_idx: inst.func_addrs.len() as u32,
+                    type_idx: func.type_idx,
+                    locals: func.locals.clone(),
+                    body: Arc::clone(&func.body),
+                    side_table: Arc::clone(&func.side_table),
+                },
+            };
+            inst.func_addrs.push(self.funcs.len());
+            self.funcs.push(FuncInst::Internal(func));
+        }
+
+        // NOTE: globals are allocated after the functions
+        // because the initializer can refer to the function by ref.func
+        if let Some(ref section) = module.module.global_section {
+            for global in section {
+                let value = self.eval(&inst, &Expr::Value(global.init_expr.clone()))?;
+                let global = InternalGlobalInst {
+                    value,
+                    mutability: global.global_type.mutability == Mutability::Var,
                 };
-                funcs.push(FuncInst::Internal(func));
+                inst.global_addrs.push(self.globals.len());
+                self.globals.push(Arc::new(Mutex::new(global)));
             }
         }
 
         // NOTE: only support one memory now
-        if let Some(ref section) = module.memory_section {
+        if let Some(ref section) = module.module.memory_section {
             for memory in section {
                 let min = memory.limits.min * PAGE_SIZE;
                 let memory = InternalMemoryInst {
                     data: vec![0; min as usize],
                     max: memory.limits.max,
                 };
-                memories.push(Rc::new(RefCell::new(memory)));
+                inst.mem_addrs.push(self.memory.len());
+                self.memory.push(Arc::new(Mutex::new(memory)));
             }
         }
 
-        // eval for offset in the table
-        let eval = |globals: &Vec<GlobalInst>, offset: Expr| -> Result<usize> {
-            match offset {
-                Expr::Value(value) => Ok(i32::from(value) as usize),
-                Expr::GlobalIndex(idx) => {
-                    let global = globals
-                        .get(idx)
-                        .with_context(|| "not found offset from globals")?
-                        .borrow();
-                    Ok(i32::from(global.value.clone()) as usize)
-                }
+        // table
+        if let Some(ref table_section) = module.module.table_section {
+            for table in table_section {
+                let min = table.limits.min as usize;
+                let table_inst = InternalTableInst {
+                    elem_type: table.elem_type.clone(),
+                    elements: vec![Value::default_of(&table.elem_type); min],
+                    max: table.limits.max,
+                };
+                inst.table_addrs.push(self.tables.len());
+                self.tables.push(Arc::new(Mutex::new(table_inst)));
             }
-        };
+        }
 
-        // table will be shared by all module instance
-        // so if element is exists in the same index, overwrite the table
-        let update_funcs_in_table = |entries: &mut Vec<Option<FuncInst>>| -> Result<()> {
-            if let Some(elems) = module.element_section.as_ref() {
-                for elem in elems {
-                    let offset = eval(&globals, elem.offset.clone())?;
-                    if entries.len() <= offset {
-                        entries.resize(entries.len() + offset + elem.init.len(), None);
-                    }
-                    for (i, func_idx) in elem.init.iter().enumerate() {
-                        let func = funcs
-                            .get(*func_idx as usize)
-                            .with_context(|| format!("not found function by {func_idx}"))?;
-                        entries[offset + i] = Some(func.clone());
+        if let Some(ref section) = module.module.element_section {
+            for elem in section {
+                let elements = elem
+                    .init
+                    .iter()
+                    .map(|expr| self.eval(&inst, expr))
+                    .collect::<Result<Vec<_>>>()?;
+                inst.elem_addrs.push(self.elems.len());
+                self.elems.push(ElemInst { elements });
+            }
+        }
+
+        if let Some(ref section) = module.module.data {
+            for data in section {
+                inst.data_addrs.push(self.datas.len());
+                self.datas.push(DataInst {
+                    data: data.init.clone(),
+                });
+            }
+        }
+
+        // NOTE: the instance is added before the segments are applied,
+        // because the functions written to the shared tables refer to it
+        // even if the instantiation fails.
+        self.instances.push(inst);
+
+        // active segments are executed as table.init and memory.init followed by
+        // elem.drop and data.drop, declarative segments are only dropped.
+        // the segments are applied in order, so writes before the failed one persist.
+        // https://webassembly.github.io/spec/core/exec/modules.html#exec-instantiation
+        if let Some(ref section) = module.module.element_section {
+            for (i, elem) in section.iter().enumerate() {
+                match elem.mode {
+                    ElementMode::Active {
+                        table_index,
+                        ref offset,
+                    } => {
+                        let table = Arc::clone(self.get_table(instance, table_index as usize)?);
+                        let offset = i32::from(self.eval(&self.instances[instance], offset)?);
+                        let elem = self.get_elem_mut(instance, i)?;
+                        let elements = std::mem::take(&mut elem.elements);
+                        table.lock().expect("cannot lock table").init(
+                            offset as u32 as usize,
+                            &elements,
+                            0,
+                            elements.len(),
+                        )?;
                     }
+                    ElementMode::Declarative => self.get_elem_mut(instance, i)?.elements.clear(),
+                    ElementMode::Passive => {}
                 }
-            };
-            Ok(())
-        };
-
-        // table
-        if let Some(ref table_section) = module.table_section {
-            let table = table_section
-                .first() // NOTE: only support one table now
-                .with_context(|| "cannot get table from table section")?; // NOTE: only support one table now
-            let min = table.limits.min as usize;
-
-            let mut entries = vec![None; min];
-            update_funcs_in_table(&mut entries)?;
-
-            let table_inst = InternalTableInst {
-                funcs: entries,
-                max: table.limits.max,
-            };
-            tables.push(Rc::new(RefCell::new(table_inst)));
-        } else {
-            // update table if element section exists
-            if !tables.is_empty() {
-                let entries = &mut tables
-                    .first()
-                    .with_context(|| "not found table")?
-                    .borrow_mut()
-                    .funcs;
-                update_funcs_in_table(entries)?;
             }
         }
 
-        // copy data to memory
-        if let Some(ref data_list) = module.data {
-            for data in data_list {
-                let offset = eval(&globals, data.offset.clone())?;
-                let init_data = &data.init;
-                let mut memory = memories
-                    .get(data.memory_index as usize)
-                    .with_context(|| "not found memory")?
-                    .borrow_mut();
-                if offset + init_data.len() > memory.data.len() {
-                    bail!("data is too large to fit in memory");
+        if let Some(ref section) = module.module.data {
+            for (i, data) in section.iter().enumerate() {
+                if let DataMode::Active {
+                    memory_index,
+                    ref offset,
+                } = data.mode
+                {
+                    let memory = Arc::clone(self.get_memory(instance, memory_index as usize)?);
+                    let offset = i32::from(self.eval(&self.instances[instance], offset)?);
+                    let bytes = std::mem::take(&mut self.get_data_mut(instance, i)?.data);
+                    memory.lock().expect("cannot lock memory").init(
+                        offset as u32 as usize,
+                        &bytes,
+                        0,
+                        bytes.len(),
+                    )?;
                 }
-                memory.data[offset..offset + init_data.len()].copy_from_slice(init_data);
             }
         }
 
-        let module_inst = ModuleInst::allocate(module);
+        Ok(instance)
+    }
+
+    pub fn instance(&self, addr: InstanceAddr) -> Result<&ModuleInst> {
+        self.instances
+            .get(addr)
+            .with_context(|| Error::NotFoundInstance(addr))
+    }
 
-        let imports = if let Some(imports) = importers {
-            let mut map = HashMap::new();
-            for importer in imports {
-                map.insert(importer.name().to_string(), importer);
+    pub fn get_func(&self, instance: InstanceAddr, idx: usize) -> Result<&FuncInst> {
+        self.instance(instance)?
+            .func_addrs
+            .get(idx)
+            .and_then(|addr| self.funcs.get(*addr))
+            .with_context(|| Error::NotFoundFunction(idx))
+    }
+
+    pub fn get_table(&self, instance: InstanceAddr, idx: usize) -> Result<&TableInst> {
+        self.instance(instance)?
+            .table_addrs
+            .get(idx)
+            .and_then(|addr| self.tables.get(*addr))
+            .with_context(|| Error::NotFoundTable(idx))
+    }
+
+    pub fn get_memory(&self, instance: InstanceAddr, idx: usize) -> Result<&MemoryInst> {
+        self.instance(instance)?
+            .mem_addrs
+            .get(idx)
+            .and_then(|addr| self.memory.get(*addr))
+            .with_context(|| Error::NotFoundMemory(idx))
+    }
+
+    pub fn get_global(&self, instance: InstanceAddr, idx: usize) -> Result<&GlobalInst> {
+        self.instance(instance)?
+            .global_addrs
+            .get(idx)
+            .and_then(|addr| self.globals.get(*addr))
+            .with_context(|| Error::NotFoundGlobalVariable(idx))
+    }
+
+    pub fn get_elem(&self, instance: InstanceAddr, idx: usize) -> Result<&ElemInst> {
+        self.instance(instance)?
+            .elem_addrs
+            .get(idx)
+            .and_then(|addr| self.elems.get(*addr))
+            .with_context(|| Error::NotFoundElemSegment(idx))
+    }
+
+    pub fn get_elem_mut(&mut self, instance: InstanceAddr, idx: usize) -> Result<&mut ElemInst> {
+        let addr = self.instance(instance)?.elem_addrs.get(idx).copied();
+        addr.and_then(|addr| self.elems.get_mut(addr))
+            .with_context(|| Error::NotFoundElemSegment(idx))
+    }
+
+    pub fn get_data(&self, instance: InstanceAddr, idx: usize) -> Result<&DataInst> {
+        self.instance(instance)?
+            .data_addrs
+            .get(idx)
+            .and_then(|addr| self.datas.get(*addr))
+            .with_context(|| Error::NotFoundDataSegment(idx))
+    }
+
+    pub fn get_data_mut(&mut self, instance: InstanceAddr, idx: usize) -> Result<&mut DataInst> {
+        let addr = self.instance(instance)?.data_addrs.get(idx).copied();
+        addr.and_then(|addr| self.datas.get_mut(addr))
+            .with_context(|| Error::NotFoundDataSegment(idx))
+    }
+
+    // get exported instances of the module instance by name, like table, memory, global
+    pub fn exports(&self, instance: InstanceAddr, name: &str) -> Result<Exports> {
+        let export_inst = self
+            .instance(instance)?
+            .exports
+            .get(name)
+            .with_context(|| Error::NotFoundExportInstance(name.into()))?;
+
+        let exports = match export_inst.desc {
+            ExternalVal::Table(idx) => {
+                let table = self
+                    .get_table(instance, idx as usize)
+                    .with_context(|| Error::NotFoundExportedTable(idx))?;
+                Exports::Table(Arc::clone(table))
+            }
+            ExternalVal::Memory(idx) => {
+                let memory = self
+                    .get_memory(instance, idx as usize)
+                    .with_context(|| Error::NotFoundExportedMemory(idx))?;
+                Exports::Memory(Arc::clone(memory))
+            }
+            ExternalVal::Global(idx) => {
+                let global = self
+                    .get_global(instance, idx as usize)
+                    .with_context(|| Error::NotFoundExportedGlobal(idx))?;
+                Exports::Global(Arc::clone(global))
+            }
+            ExternalVal::Func(idx) => {
+                let func = self
+                    .get_func(instance, idx as usize)
+                    .with_context(|| Error::NotFoundExportedFunction(idx))?;
+                Exports::Func(func.clone())
             }
-            Some(map)
-        } else {
-            None
         };
 
-        let store = Self {
-            funcs,
-            tables,
-            memory: memories,
-            globals,
-            imports,
-            module: module_inst,
-            start: module.start_section,
+        Ok(exports)
+    }
+
+    // address of the exported function in the store
+    pub(crate) fn export_func_addr(&self, instance: InstanceAddr, name: &str) -> Result<FuncAddr> {
+        let inst = self.instance(instance)?;
+        let export_inst = inst
+            .exports
+            .get(name)
+            .with_context(|| Error::NotFoundExportInstance(name.into()))?;
+        let external_val = &export_inst.desc;
+
+        let ExternalVal::Func(idx) = external_val else {
+            bail!("invalid export desc: {:?}", external_val);
         };
+        inst.func_addrs
+            .get(*idx as usize)
+            .copied()
+            .with_context(|| Error::NotFoundExportedFunction(*idx))
+    }
 
-        Ok(store)
+    // the indices in the expression are resolved by the module instance
+    // which may not be added to the store yet
+    // https://webassembly.github.io/spec/core/exec/instructions.html#expressions
+    fn eval(&self, inst: &ModuleInst, expr: &Expr) -> Result<Value> {
+        let value = match expr {
+            Expr::Value(ExprValue::I32(v)) => Value::I32(*v),
+            Expr::Value(ExprValue::I64(v)) => Value::I64(*v),
+            Expr::Value(ExprValue::F32(v)) => Value::F32(*v),
+            Expr::Value(ExprValue::F64(v)) => Value::F64(*v),
+            Expr::Value(ExprValue::RefNull(value_type)) => Value::default_of(value_type),
+            Expr::Value(ExprValue::RefFunc(idx)) => {
+                let idx = *idx as usize;
+                let func = inst
+                    .func_addrs
+                    .get(idx)
+                    .and_then(|addr| self.funcs.get(*addr))
+                    .with_context(|| Error::NotFoundFunction(idx))?;
+                Value::FuncRef(Some(Arc::new(func.clone())))
+            }
+            Expr::GlobalIndex(idx) => {
+                let global = inst
+                    .global_addrs
+                    .get(*idx)
+                    .and_then(|addr| self.globals.get(*addr))
+                    .with_context(|| Error::NotFoundGlobalVariable(*idx))?;
+                global.lock().expect("cannot lock global").value.clone()
+            }
+        };
+        Ok(value)
     }
 }
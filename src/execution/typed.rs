@@ -0,0 +1,180 @@
+use super::{
+    error::Error,
+    host::{WasmResults, WasmTy},
+    indices::FuncAddr,
+    runtime::Runtime,
+    store::Store,
+    value::Value,
+};
+use crate::binary::types::{FuncType, ValueType};
+use anyhow::{bail, Result};
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+
+// arguments of the typed function
+pub trait WasmParams {
+    fn value_types() -> Vec<ValueType>;
+    fn into_values(self) -> Vec<Value>;
+}
+
+macro_rules! impl_wasm_params {
+    ($($ty: ident),*) => {
+        impl<$($ty: WasmTy),*> WasmParams for ($($ty,)*) {
+            fn value_types() -> Vec<ValueType> {
+                vec![$($ty::value_type()),*]
+            }
+
+            #[allow(non_snake_case)]
+            fn into_values(self) -> Vec<Value> {
+                let ($($ty,)*) = self;
+                vec![$($ty.into_value()),*]
+            }
+        }
+    };
+}
+
+impl_wasm_params!();
+impl_wasm_params!(A1);
+impl_wasm_params!(A1, A2);
+impl_wasm_params!(A1, A2, A3);
+impl_wasm_params!(A1, A2, A3, A4);
+impl_wasm_params!(A1, A2, A3, A4, A5);
+impl_wasm_params!(A1, A2, A3, A4, A5, A6);
+impl_wasm_params!(A1, A2, A3, A4, A5, A6, A7);
+impl_wasm_params!(A1, A2, A3, A4, A5, A6, A7, A8);
+
+macro_rules! impl_wasm_params_single {
+    ($($ty: ty),*) => {
+        $(
+            impl WasmParams for $ty {
+                fn value_types() -> Vec<ValueType> {
+                    vec![<$ty>::value_type()]
+                }
+
+                fn into_values(self) -> Vec<Value> {
+                    vec![self.into_value()]
+                }
+            }
+        )*
+    };
+}
+
+impl_wasm_params_single!(i32, i64, f32, f64);
+
+// handle of the exported function whose signature is checked at creation
+//
+//   let add = runtime.get_typed_func::<(i32, i32), i32>("add")?;
+//   let result = add.call(&mut runtime, (1, 2))?;
+pub struct TypedFunc<Params, Results> {
+    store: Arc<Mutex<Store>>, // the address is only valid in this store
+    addr: FuncAddr,
+    _marker: PhantomData<fn(Params) -> Results>,
+}
+
+impl<Params, Results> Clone for TypedFunc<Params, Results> {
+    fn clone(&self) -> Self {
+        Self {
+            store: Arc::clone(&self.store),
+            addr: self.addr,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<Params, Results> TypedFunc<Params, Results>
+where
+    Params: WasmParams,
+    Results: WasmResults,
+{
+    pub(crate) fn new(
+        store: Arc<Mutex<Store>>,
+        addr: FuncAddr,
+        func_type: &FuncType,
+    ) -> Result<Self> {
+        let expected = FuncType {
+            params: Params::value_types(),
+            results: Results::value_types(),
+        };
+        if func_type != &expected {
+            bail!(Error::TypedFuncMismatch(
+                format!("{:?} -> {:?}", expected.params, expected.results),
+                format!("{:?} -> {:?}", func_type.params, func_type.results),
+            ));
+        }
+        Ok(Self {
+            store,
+            addr,
+            _marker: PhantomData,
+        })
+    }
+
+    // the runtime must share the store with the one which the function is taken from
+    pub fn call(&self, runtime: &mut Runtime, params: Params) -> Result<Results> {
+        if !Arc::ptr_eq(&self.store, &runtime.store) {
+            bail!(Error::ForeignTypedFunc);
+        }
+        let results = runtime.call_by_addr(self.addr, params.into_values())?;
+        Results::from_values(results)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Runtime;
+    use anyhow::Result;
+    use std::sync::Arc;
+
+    #[test]
+    fn typed_func() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"(module
+              (func (export "convert") (param i32 i64) (result f64)
+                (f64.add
+                  (f64.convert_i32_s (local.get 0))
+                  (f64.convert_i64_s (local.get 1))))
+              (func (export "swap") (param i32 f32) (result f32 i32)
+                (local.get 1) (local.get 0))
+              (func (export "nop"))
+              (func (export "trap") (param i32) (result i32)
+                (i32.div_s (i32.const 1) (local.get 0)))
+            )"#,
+        )?;
+        let mut runtime = Runtime::from_bytes(&wasm, None)?;
+
+        let convert = runtime.get_typed_func::<(i32, i64), f64>("convert")?;
+        assert_eq!(convert.call(&mut runtime, (1, 2))?, 3.0);
+        assert_eq!(convert.call(&mut runtime, (-5, 10))?, 5.0);
+
+        let swap = runtime.get_typed_func::<(i32, f32), (f32, i32)>("swap")?;
+        assert_eq!(swap.call(&mut runtime, (7, 1.5))?, (1.5, 7));
+
+        let nop = runtime.get_typed_func::<(), ()>("nop")?;
+        nop.call(&mut runtime, ())?;
+
+        let trap = runtime.get_typed_func::<i32, i32>("trap")?;
+        assert_eq!(trap.call(&mut runtime, 1)?, 1);
+        let err = trap.call(&mut runtime, 0).unwrap_err();
+        assert_eq!(err.to_string(), "integer divide by zero");
+
+        let err = runtime
+            .get_typed_func::<(i32, i32), f64>("convert")
+            .err()
+            .map(|e| e.to_string());
+        assert_eq!(
+            err,
+            Some(
+                "function type mismatch: expected [I32, I32] -> [F64], actual [I32, I64] -> [F64]"
+                    .into()
+            )
+        );
+        assert!(runtime.get_typed_func::<(), ()>("unknown").is_err());
+
+        // the address of the function is not valid in another store
+        let mut other = Runtime::from_bytes(&wasm, None)?;
+        let err = convert.call(&mut other, (1, 2)).unwrap_err();
+        assert_eq!(err.to_string(), "typed function belongs to another store");
+        let mut shared = Runtime::instantiate(Arc::clone(&runtime.store))?;
+        assert_eq!(convert.call(&mut shared, (1, 2))?, 3.0);
+        Ok(())
+    }
+}
@@ -1,29 +1,59 @@
 #![allow(unused)]
 
 use super::indices::*;
-use super::module::ModuleInst;
+use super::module::{FuncInst, ModuleInst};
+use super::side_table::SideTable;
 use super::{float::*, integer::*};
 use crate::binary::instruction::*;
 use crate::binary::types::ExportDesc;
-use crate::binary::types::FuncType;
-use crate::execution::error::Error;
+use crate::binary::types::{FuncType, ValueType};
+use crate::execution::error::{Error, TrapCode};
 use anyhow::{bail, Context as _, Result};
 use log::trace;
 use num_traits::NumCast;
 use std::fmt::Display;
 use std::mem::size_of;
-use std::rc::Rc;
+use std::sync::Arc;
 
 // https://webassembly.github.io/spec/core/exec/runtime.html#syntax-val
-#[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     I32(i32),
     I64(i64),
     F32(f32),
     F64(f64),
+    FuncRef(Option<Arc<FuncInst>>), // None is ref.null func
+    ExternRef(Option<usize>),       // opaque handle from the host, None is ref.null extern
 }
 
 impl Value {
+    // https://webassembly.github.io/spec/core/exec/runtime.html#default-val
+    pub fn default_of(value_type: &ValueType) -> Self {
+        match value_type {
+            ValueType::I32 => Value::I32(0),
+            ValueType::I64 => Value::I64(0),
+            ValueType::F32 => Value::F32(0.0),
+            ValueType::F64 => Value::F64(0.0),
+            ValueType::FuncRef => Value::FuncRef(None),
+            ValueType::ExternRef => Value::ExternRef(None),
+        }
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::I32(_) => ValueType::I32,
+            Value::I64(_) => ValueType::I64,
+            Value::F32(_) => ValueType::F32,
+            Value::F64(_) => ValueType::F64,
+            Value::FuncRef(_) => ValueType::FuncRef,
+            Value::ExternRef(_) => ValueType::ExternRef,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::FuncRef(None) | Value::ExternRef(None))
+    }
+
     pub fn is_true(&self) -> bool {
         match *self {
             Value::I32(v) => 0 != v,
@@ -50,6 +80,10 @@ impl Display for Value {
             Self::F64(n) => {
                 write!(f, "{n}")
             }
+            Self::FuncRef(None) => write!(f, "ref.null func"),
+            Self::FuncRef(Some(_)) => write!(f, "ref.func"),
+            Self::ExternRef(None) => write!(f, "ref.null extern"),
+            Self::ExternRef(Some(n)) => write!(f, "ref.extern {n}"),
         }
     }
 }
@@ -68,16 +102,20 @@ pub struct Label {
     pub pc: usize,            // next pc
     pub sp: usize,            // stack pointer
     pub arity: usize,         // result arity
+    pub branch_arity: usize,  // arity when branching to the label
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct Frame {
-    pub pc: isize,               // next pc
-    pub sp: usize,               // stack pointer when frame created
-    pub insts: Vec<Instruction>, // function instructions
-    pub arity: usize,            // result arity
-    pub locals: Vec<Value>,      // local variables
-    pub labels: Vec<Label>,      // labels for if, loop, block
+    pub instance: InstanceAddr, // module instance of the executing function
+    pub func_idx: FuncIdx,      // index of the executing function
+    pub pc: isize,              // next pc
+    pub sp: usize,              // stack pointer when frame created
+    pub insts: Arc<Vec<Instruction>>, // function instructions
+    pub side_table: Arc<SideTable>, // jump targets of the blocks
+    pub arity: usize,           // result arity
+    pub locals: Vec<Value>,     // local variables
+    pub labels: Vec<Label>,     // labels for if, loop, block
 }
 
 // trait for stack access
@@ -294,16 +332,16 @@ macro_rules! funop {
 macro_rules! validate {
     ($num: expr) => {
         if $num.is_nan() {
-            bail!("invalid conversion to integer")
+            bail!(TrapCode::InvalidConversionToInteger)
         }
         if $num.is_infinite() {
-            bail!("integer overflow")
+            bail!(TrapCode::IntegerOverflow)
         }
     };
     ($num: expr, $ty: ty) => {
         validate!($num);
         let x: Option<$ty> = NumCast::from($num);
-        x.with_context(|| "integer overflow")?;
+        x.with_context(|| TrapCode::IntegerOverflow)?;
     };
 }
 
@@ -413,6 +451,65 @@ impl Value {
         }
     }
 
+    // NOTE: the float to int `as` cast in Rust saturates on overflow and converts NaN to 0,
+    // which is the same as the trunc_sat semantics.
+    // https://webassembly.github.io/spec/core/exec/numerics.html#op-trunc-sat-s
+    pub fn i32_trunc_sat_f32_s(&self) -> Result<Self> {
+        match self {
+            Value::F32(f) => Ok(Value::I32(*f as i32)),
+            _ => panic!("unexpected value. {self}"),
+        }
+    }
+
+    pub fn i32_trunc_sat_f32_u(&self) -> Result<Self> {
+        match self {
+            Value::F32(f) => Ok(Value::I32(*f as u32 as i32)),
+            _ => panic!("unexpected value. {self}"),
+        }
+    }
+
+    pub fn i32_trunc_sat_f64_s(&self) -> Result<Self> {
+        match self {
+            Value::F64(f) => Ok(Value::I32(*f as i32)),
+            _ => panic!("unexpected value. {self}"),
+        }
+    }
+
+    pub fn i32_trunc_sat_f64_u(&self) -> Result<Self> {
+        match self {
+            Value::F64(f) => Ok(Value::I32(*f as u32 as i32)),
+            _ => panic!("unexpected value. {self}"),
+        }
+    }
+
+    pub fn i64_trunc_sat_f32_s(&self) -> Result<Self> {
+        match self {
+            Value::F32(f) => Ok(Value::I64(*f as i64)),
+            _ => panic!("unexpected value. {self}"),
+        }
+    }
+
+    pub fn i64_trunc_sat_f32_u(&self) -> Result<Self> {
+        match self {
+            Value::F32(f) => Ok(Value::I64(*f as u64 as i64)),
+            _ => panic!("unexpected value. {self}"),
+        }
+    }
+
+    pub fn i64_trunc_sat_f64_s(&self) -> Result<Self> {
+        match self {
+            Value::F64(f) => Ok(Value::I64(*f as i64)),
+            _ => panic!("unexpected value. {self}"),
+        }
+    }
+
+    pub fn i64_trunc_sat_f64_u(&self) -> Result<Self> {
+        match self {
+            Value::F64(f) => Ok(Value::I64(*f as u64 as i64)),
+            _ => panic!("unexpected value. {self}"),
+        }
+    }
+
     pub fn i64_extend_i32_s(&self) -> Result<Self> {
         match self {
             Value::I32(l) => Ok(Value::I64(*l as i64)),
@@ -538,19 +635,19 @@ macro_rules! impl_numeric {
         $(
             impl Numeric for $ty {
                 fn read(buf: &[u8], addr: usize) -> Result<$ty> {
-                    if addr + size_of::<$ty>() > buf.len() {
-                        bail!("out of bounds memory access");
-                    }
-                    let end = addr + size_of::<$ty>();
-                    Ok(<$ty>::from_le_bytes(buf[addr..end].try_into()?))
+                    let bytes = addr
+                        .checked_add(size_of::<$ty>())
+                        .and_then(|end| buf.get(addr..end))
+                        .with_context(|| TrapCode::MemoryOutOfBounds)?;
+                    Ok(<$ty>::from_le_bytes(bytes.try_into()?))
                 }
 
                 fn write(buf: &mut [u8], addr: usize, value: Self) -> Result<()> {
-                    let bytes = value.to_le_bytes();
-                    if addr + size_of::<$ty>() > buf.len() {
-                        bail!("out of bounds memory access");
-                    }
-                    buf[addr..addr + size_of::<$ty>()].copy_from_slice(&bytes);
+                    let bytes = addr
+                        .checked_add(size_of::<$ty>())
+                        .and_then(|end| buf.get_mut(addr..end))
+                        .with_context(|| TrapCode::MemoryOutOfBounds)?;
+                    bytes.copy_from_slice(&value.to_le_bytes());
                     Ok(())
                 }
             }
@@ -1,4 +1,6 @@
 mod binary;
 pub mod execution;
 pub mod wasi;
+pub use binary::instruction::Instruction;
+pub use binary::validate::{ValidationError, ValidationErrorKind};
 pub use execution::*;
@@ -1,5 +1,8 @@
 use anyhow::Result;
-use chibiwasm::{execution::Runtime, wasi::WasiSnapshotPreview1};
+use chibiwasm::{
+    execution::{Runtime, Trap},
+    wasi::WasiSnapshotPreview1,
+};
 use clap::Parser;
 
 #[derive(Debug, Parser)]
@@ -28,7 +31,16 @@ fn main() -> Result<()> {
 
     let mut runtime =
         Runtime::from_file(&file, Some(vec![Box::<WasiSnapshotPreview1>::default()]))?;
-    let result = runtime.call(func, args).expect("failed to call function");
+    let result = match runtime.call(func, args) {
+        Ok(result) => result,
+        Err(e) => match e.downcast_ref::<Trap>() {
+            Some(trap) => {
+                eprintln!("{trap:#}");
+                std::process::exit(1);
+            }
+            None => return Err(e),
+        },
+    };
 
     if let Some(output) = result {
         println!("{}", output);
@@ -0,0 +1,86 @@
+use super::types::Errno;
+use anyhow::{bail, Result};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// https://github.com/WebAssembly/WASI/blob/main/legacy/preview1/docs.md#clockid
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ClockId {
+    Realtime = 0,
+    Monotonic = 1,
+    ProcessCputime = 2,
+    ThreadCputime = 3,
+}
+
+impl TryFrom<i32> for ClockId {
+    type Error = anyhow::Error;
+
+    fn try_from(id: i32) -> Result<Self> {
+        let id = match id {
+            0 => Self::Realtime,
+            1 => Self::Monotonic,
+            2 => Self::ProcessCputime,
+            3 => Self::ThreadCputime,
+            _ => bail!(Errno::Inval),
+        };
+        Ok(id)
+    }
+}
+
+// source of the time for the guest, the values are nanoseconds.
+// the realtime is since the unix epoch, and the others are since an arbitrary point.
+pub trait WasiClock: Send + Sync {
+    fn time(&self, id: ClockId) -> Result<u64>;
+    fn resolution(&self, id: ClockId) -> Result<u64>;
+}
+
+// clock of the host.
+// the thread cpu time is of the host thread which runs the guest
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl WasiClock for SystemClock {
+    fn time(&self, id: ClockId) -> Result<u64> {
+        let time = match id {
+            ClockId::Realtime => SystemTime::now().duration_since(UNIX_EPOCH)?,
+            ClockId::Monotonic => self.start.elapsed(),
+            ClockId::ProcessCputime | ClockId::ThreadCputime => cpu_clock(libc::clock_gettime, id)?,
+        };
+        Ok(time.as_nanos() as u64)
+    }
+
+    fn resolution(&self, id: ClockId) -> Result<u64> {
+        let resolution = match id {
+            ClockId::Realtime | ClockId::Monotonic => Duration::from_nanos(1),
+            ClockId::ProcessCputime | ClockId::ThreadCputime => cpu_clock(libc::clock_getres, id)?,
+        };
+        Ok(resolution.as_nanos() as u64)
+    }
+}
+
+// the cpu-time clocks are not available in std, so they are read by clock_gettime or clock_getres
+fn cpu_clock(
+    read: unsafe extern "C" fn(libc::clockid_t, *mut libc::timespec) -> libc::c_int,
+    id: ClockId,
+) -> Result<Duration> {
+    let clock = match id {
+        ClockId::ProcessCputime => libc::CLOCK_PROCESS_CPUTIME_ID,
+        ClockId::ThreadCputime => libc::CLOCK_THREAD_CPUTIME_ID,
+        _ => bail!(Errno::Inval),
+    };
+    // SAFETY: timespec is a plain C struct, and all zeros is a valid value
+    let mut time: libc::timespec = unsafe { std::mem::zeroed() };
+    // SAFETY: the pointer to the timespec is valid during the call
+    if unsafe { read(clock, &mut time) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+}
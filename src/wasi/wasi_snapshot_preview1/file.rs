@@ -1,5 +1,12 @@
-use anyhow::Result;
-use std::io::{Read, Seek, Write};
+use super::{types::Errno, wasi_dir::Dirent};
+use anyhow::{bail, Result};
+use std::{
+    fs::Metadata,
+    io::{Read, Seek, SeekFrom, Write},
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
 pub trait ReadWrite: Read + Write + Seek + Send + Sync + 'static {}
 
@@ -7,6 +14,7 @@ impl<IO: Read + Write + Seek + Send + Sync + 'static> ReadWrite for IO {}
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum FdFlags {
+    Empty = 0, // no flags, e.g. for the directory
     Append = 0b1,
     Dsync = 0b10,
     Nonblock = 0b1000,
@@ -14,23 +22,44 @@ pub enum FdFlags {
     Sync = 0b100000,
 }
 
-#[derive(Debug, Clone)]
+// the value is the bit of the rights in the wasi
+// https://github.com/WebAssembly/WASI/blob/main/legacy/preview1/docs.md#rights
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum FileCaps {
-    DataSync = 0b1,
-    Read = 0b10,
-    Seek = 0b100,
-    FdstatSetFlags = 0b1000,
-    Sync = 0b10000,
-    Tell = 0b100000,
-    Write = 0b1000000,
-    Advise = 0b10000000,
-    Allocate = 0b100000000,
-    FilestatGet = 0b1000000000,
-    FilestatSetSize = 0b10000000000,
-    FilestatSetTimes = 0b100000000000,
-    PollReadwrite = 0b1000000000000,
+    DataSync = 1 << 0,
+    Read = 1 << 1,
+    Seek = 1 << 2,
+    FdstatSetFlags = 1 << 3,
+    Sync = 1 << 4,
+    Tell = 1 << 5,
+    Write = 1 << 6,
+    Advise = 1 << 7,
+    Allocate = 1 << 8,
+    CreateDirectory = 1 << 9,
+    CreateFile = 1 << 10,
+    LinkSource = 1 << 11,
+    LinkTarget = 1 << 12,
+    Open = 1 << 13,
+    Readdir = 1 << 14,
+    Readlink = 1 << 15,
+    RenameSource = 1 << 16,
+    RenameTarget = 1 << 17,
+    PathFilestatGet = 1 << 18,
+    PathFilestatSetSize = 1 << 19,
+    PathFilestatSetTimes = 1 << 20,
+    FilestatGet = 1 << 21,
+    FilestatSetSize = 1 << 22,
+    FilestatSetTimes = 1 << 23,
+    Symlink = 1 << 24,
+    RemoveDirectory = 1 << 25,
+    UnlinkFile = 1 << 26,
+    PollReadwrite = 1 << 27,
 }
 
+// set of the FileCaps
+pub type Rights = u64;
+pub const ALL_RIGHTS: Rights = (1 << 28) - 1;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum FileType {
     Unknown = 0,
@@ -44,43 +73,176 @@ pub enum FileType {
     Pipe = 8,
 }
 
+impl From<std::fs::FileType> for FileType {
+    fn from(ty: std::fs::FileType) -> Self {
+        if ty.is_file() {
+            Self::RegularFile
+        } else if ty.is_dir() {
+            Self::Directory
+        } else if ty.is_symlink() {
+            Self::SymbolicLink
+        } else if ty.is_block_device() {
+            Self::BlockDevice
+        } else if ty.is_char_device() {
+            Self::CharacterDevice
+        } else if ty.is_fifo() {
+            Self::Pipe
+        } else if ty.is_socket() {
+            Self::SocketStream
+        } else {
+            Self::Unknown
+        }
+    }
+}
+
+// the timestamps are nanoseconds since the unix epoch
+// https://github.com/WebAssembly/WASI/blob/main/legacy/preview1/docs.md#filestat
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filestat {
+    pub dev: u64,
+    pub ino: u64,
+    pub filetype: FileType,
+    pub nlink: u64,
+    pub size: u64,
+    pub atim: u64,
+    pub mtim: u64,
+    pub ctim: u64,
+}
+
+impl From<&Metadata> for Filestat {
+    fn from(m: &Metadata) -> Self {
+        let nanos = |sec: i64, nsec: i64| (sec as u64 * 1_000_000_000).wrapping_add(nsec as u64);
+        Self {
+            dev: m.dev(),
+            ino: m.ino(),
+            filetype: m.file_type().into(),
+            nlink: m.nlink(),
+            size: m.size(),
+            atim: nanos(m.atime(), m.atime_nsec()),
+            mtim: nanos(m.mtime(), m.mtime_nsec()),
+            ctim: nanos(m.ctime(), m.ctime_nsec()),
+        }
+    }
+}
+
+impl Filestat {
+    pub const SIZE: usize = 64;
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut buf = [0; Self::SIZE];
+        buf[0..8].copy_from_slice(&self.dev.to_le_bytes());
+        buf[8..16].copy_from_slice(&self.ino.to_le_bytes());
+        buf[16] = self.filetype as u8;
+        buf[24..32].copy_from_slice(&self.nlink.to_le_bytes());
+        buf[32..40].copy_from_slice(&self.size.to_le_bytes());
+        buf[40..48].copy_from_slice(&self.atim.to_le_bytes());
+        buf[48..56].copy_from_slice(&self.mtim.to_le_bytes());
+        buf[56..64].copy_from_slice(&self.ctim.to_le_bytes());
+        buf
+    }
+}
+
 pub trait File: Send + Sync {
     fn write(&mut self, data: &[u8]) -> Result<usize>;
     fn read(&mut self, data: &mut [u8]) -> Result<usize>;
     fn seek(&mut self, pos: u64) -> Result<u64>;
+    // seek relative to the current position or the end, returns the new position
+    fn seek_from(&mut self, pos: SeekFrom) -> Result<u64>;
     fn filetype(&self) -> Result<FileType>;
     fn fdflags(&self) -> Result<FdFlags>;
     fn read_string(&mut self) -> Result<String>;
+    fn filestat(&mut self) -> Result<Filestat>;
+    fn set_size(&mut self, size: u64) -> Result<()>;
+    // the time is not changed if it's None
+    fn set_times(&mut self, atim: Option<SystemTime>, mtim: Option<SystemTime>) -> Result<()>;
+    // all the entries of the directory including "." and ".."
+    fn readdir(&self) -> Result<Vec<Dirent>>;
 }
 
 #[derive(Debug, Clone)]
 pub struct FdStat {
     pub filetype: FileType,
-    pub caps: FileCaps,
     pub flags: FdFlags,
+    pub rights: Rights,
+    pub inheriting: Rights,
 }
 
 pub struct FileEntry {
-    caps: FileCaps,
     file: Box<dyn File>,
+    rights: Rights,          // checked by the operations on the fd
+    inheriting: Rights,      // rights of the fd opened by path_open in the directory
+    path: Option<PathBuf>,   // host path of the directory
+    preopen: Option<String>, // guest path of the preopened directory
 }
 
 impl FileEntry {
-    pub fn new(file: Box<dyn File>, caps: FileCaps) -> Self {
-        Self { caps, file }
+    // the entry for the stdio and the virtual files, which has all the rights
+    pub fn new(file: Box<dyn File>) -> Self {
+        Self {
+            file,
+            rights: ALL_RIGHTS,
+            inheriting: ALL_RIGHTS,
+            path: None,
+            preopen: None,
+        }
+    }
+
+    // the entry for the file or the directory opened on the host
+    pub fn open(
+        file: Box<dyn File>,
+        path: Option<PathBuf>,
+        rights: Rights,
+        inheriting: Rights,
+    ) -> Self {
+        Self {
+            file,
+            rights,
+            inheriting,
+            path,
+            preopen: None,
+        }
+    }
+
+    pub fn preopened(mut self, guest_path: impl Into<String>) -> Self {
+        self.preopen = Some(guest_path.into());
+        self
     }
 
     pub fn get_fdstat(&self) -> Result<FdStat> {
         Ok(FdStat {
             filetype: self.file.filetype()?,
-            caps: self.caps.clone(),
             flags: self.file.fdflags()?,
+            rights: self.rights,
+            inheriting: self.inheriting,
         })
     }
 
-    pub fn capbable(&mut self, _cap: FileCaps) -> Result<&mut Box<dyn File>> {
-        // TODO: check capabilites
+    pub fn preopen(&self) -> Option<&str> {
+        self.preopen.as_deref()
+    }
+
+    pub fn inheriting(&self) -> Rights {
+        self.inheriting
+    }
+
+    pub fn has(&self, cap: FileCaps) -> bool {
+        self.rights & cap as Rights != 0
+    }
+
+    pub fn capbable(&mut self, cap: FileCaps) -> Result<&mut Box<dyn File>> {
+        if !self.has(cap) {
+            bail!(Errno::Notcapable);
+        }
         let file = &mut self.file;
         Ok(file)
     }
+
+    // host path of the directory for the path operations
+    pub fn dir(&self, cap: FileCaps) -> Result<&Path> {
+        let path = self.path.as_deref().ok_or(Errno::Notdir)?;
+        if !self.has(cap) {
+            bail!(Errno::Notcapable);
+        }
+        Ok(path)
+    }
 }
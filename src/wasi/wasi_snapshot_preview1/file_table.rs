@@ -1,43 +1,60 @@
-use super::{
-    file::{FileCaps, FileEntry},
-    wasi_file::WasiFile,
-};
+use super::{file::FileEntry, wasi_file::WasiFile};
 use std::sync::{Arc, Mutex};
 
-pub struct FileTable(Vec<Arc<Mutex<FileEntry>>>);
+// the fd is the index of the table, and the closed fd is reused by the next file
+pub struct FileTable(Vec<Option<Arc<Mutex<FileEntry>>>>);
 
 impl Default for FileTable {
     fn default() -> Self {
-        Self(vec![
+        Self::with_io(vec![
             // stdin
-            Arc::new(Mutex::new(FileEntry::new(
-                Box::new(WasiFile::from_raw_fd(0)),
-                FileCaps::Sync,
-            ))),
+            Arc::new(Mutex::new(FileEntry::new(Box::new(WasiFile::from_raw_fd(
+                0,
+            ))))),
             // stdout
-            Arc::new(Mutex::new(FileEntry::new(
-                Box::new(WasiFile::from_raw_fd(1)),
-                FileCaps::Sync,
-            ))),
+            Arc::new(Mutex::new(FileEntry::new(Box::new(WasiFile::from_raw_fd(
+                1,
+            ))))),
             // stderr
-            Arc::new(Mutex::new(FileEntry::new(
-                Box::new(WasiFile::from_raw_fd(2)),
-                FileCaps::Sync,
-            ))),
+            Arc::new(Mutex::new(FileEntry::new(Box::new(WasiFile::from_raw_fd(
+                2,
+            ))))),
         ])
     }
 }
 
 impl FileTable {
     pub fn with_io(files: Vec<Arc<Mutex<FileEntry>>>) -> Self {
-        FileTable(files)
+        FileTable(files.into_iter().map(Some).collect())
     }
 
     pub fn get(&self, idx: usize) -> Option<&Arc<Mutex<FileEntry>>> {
-        self.0.get(idx)
+        self.0.get(idx)?.as_ref()
     }
 
-    pub fn add(&mut self, file: Arc<Mutex<FileEntry>>) {
-        self.0.push(file);
+    // returns the fd of the added file, the lowest closed fd is reused
+    pub fn add(&mut self, file: Arc<Mutex<FileEntry>>) -> usize {
+        match self.0.iter().position(Option::is_none) {
+            Some(fd) => {
+                self.0[fd] = Some(file);
+                fd
+            }
+            None => {
+                self.0.push(Some(file));
+                self.0.len() - 1
+            }
+        }
+    }
+
+    pub fn remove(&mut self, idx: usize) -> Option<Arc<Mutex<FileEntry>>> {
+        self.0.get_mut(idx)?.take()
+    }
+
+    // move the file to the fd `to`, the file at `to` is closed
+    pub fn renumber(&mut self, from: usize, to: usize) -> Option<()> {
+        self.get(to)?;
+        let file = self.0.get_mut(from)?.take()?;
+        self.0[to] = Some(file);
+        Some(())
     }
 }
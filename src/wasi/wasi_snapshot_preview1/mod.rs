@@ -1,8 +1,11 @@
+pub mod clock;
 pub mod file;
 pub mod file_table;
+pub(crate) mod path;
 pub mod preview1;
 pub mod types;
 pub mod virtual_file;
+pub mod wasi_dir;
 pub mod wasi_file;
 
 pub use preview1::*;
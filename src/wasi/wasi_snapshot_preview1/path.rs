@@ -0,0 +1,153 @@
+use super::types::Errno;
+use anyhow::{bail, Result};
+use std::{
+    ffi::OsString,
+    path::{Component, Path, PathBuf},
+};
+
+// same as the limit of the symlinks in linux
+const MAX_SYMLINKS: usize = 40;
+
+// resolve the guest path relative to the directory on the host.
+// every component is checked on the host, so the path can't escape the directory
+// by `..` or the symlinks, and the absolute path is rejected.
+// the last component isn't resolved if it's a symlink and `follow` is false.
+// NOTE: the result is checked only at this point, see `WasiSnapshotPreview1::preopen_dir`
+pub fn resolve(dir: &Path, path: &str, follow: bool) -> Result<PathBuf> {
+    if path.is_empty() {
+        bail!(Errno::Noent);
+    }
+    if path.starts_with('/') {
+        bail!(Errno::Notcapable);
+    }
+
+    // the components to resolve are popped from the end
+    let mut pending: Vec<OsString> = path.split('/').rev().map(OsString::from).collect();
+    let mut resolved = PathBuf::new();
+    let mut symlinks = 0;
+    while let Some(name) = pending.pop() {
+        if name.is_empty() || name == "." {
+            continue;
+        }
+        if name == ".." {
+            if !resolved.pop() {
+                bail!(Errno::Notcapable);
+            }
+            continue;
+        }
+
+        resolved.push(&name);
+        if pending.is_empty() && !follow {
+            break;
+        }
+        let host_path = dir.join(&resolved);
+        // the file which doesn't exist yet is created by the caller
+        let Ok(metadata) = std::fs::symlink_metadata(&host_path) else {
+            continue;
+        };
+        if !metadata.is_symlink() {
+            continue;
+        }
+
+        symlinks += 1;
+        if symlinks > MAX_SYMLINKS {
+            bail!(Errno::Loop);
+        }
+        resolved.pop();
+        let target = std::fs::read_link(&host_path)?;
+        for component in target.components().rev() {
+            match component {
+                Component::Normal(name) => pending.push(name.to_os_string()),
+                Component::ParentDir => pending.push("..".into()),
+                Component::CurDir => {}
+                Component::RootDir | Component::Prefix(_) => bail!(Errno::Notcapable),
+            }
+        }
+    }
+    Ok(dir.join(resolved))
+}
+
+// the symlink created in the directory must not point outside of it,
+// `link` is the path of the symlink relative to the directory
+pub fn check_symlink_target(link: &Path, target: &str) -> Result<()> {
+    if target.starts_with('/') {
+        bail!(Errno::Notcapable);
+    }
+    let mut depth = link.components().count().saturating_sub(1);
+    for name in target.split('/') {
+        match name {
+            "" | "." => {}
+            ".." => depth = depth.checked_sub(1).ok_or(Errno::Notcapable)?,
+            _ => depth += 1,
+        }
+    }
+    Ok(())
+}
+
+// the moved or linked symlink keeps the relative target, so it must not point outside
+// from the new path `dest`, which is relative to the directory.
+// the symlinks in the moved directory are also checked.
+pub fn check_moved_symlinks(source: &Path, dest: &Path) -> Result<()> {
+    let metadata = std::fs::symlink_metadata(source)?;
+    if metadata.is_symlink() {
+        let target = std::fs::read_link(source)?;
+        let target = target.to_str().ok_or(Errno::Ilseq)?;
+        return check_symlink_target(dest, target);
+    }
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(source)? {
+            let entry = entry?;
+            check_moved_symlinks(&entry.path(), &dest.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_symlink_target, resolve};
+    use crate::wasi::types::Errno;
+    use anyhow::Result;
+
+    #[test]
+    fn sandbox() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("chibiwasm-resolve-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("a/b"))?;
+        std::os::unix::fs::symlink("b", dir.join("a/link"))?;
+        std::os::unix::fs::symlink("../..", dir.join("a/b/escape"))?;
+        std::os::unix::fs::symlink("/etc", dir.join("a/abs"))?;
+        std::os::unix::fs::symlink("loop", dir.join("loop"))?;
+
+        assert_eq!(resolve(&dir, "a/b/../b/file", true)?, dir.join("a/b/file"));
+        assert_eq!(resolve(&dir, "./a//link/file", true)?, dir.join("a/b/file"));
+        assert_eq!(resolve(&dir, "a/link", false)?, dir.join("a/link"));
+        assert_eq!(resolve(&dir, "a/b/escape/a", true)?, dir.join("a"));
+
+        let errno = |path, follow| {
+            resolve(&dir, path, follow)
+                .unwrap_err()
+                .downcast::<Errno>()
+                .unwrap()
+        };
+        assert_eq!(errno("../file", true), Errno::Notcapable);
+        assert_eq!(errno("a/../../file", true), Errno::Notcapable);
+        assert_eq!(errno("/etc/passwd", true), Errno::Notcapable);
+        assert_eq!(errno("a/b/escape/..", true), Errno::Notcapable);
+        assert_eq!(errno("a/abs/passwd", true), Errno::Notcapable);
+        assert_eq!(errno("a/abs", true), Errno::Notcapable);
+        assert_eq!(errno("loop", true), Errno::Loop);
+        assert_eq!(errno("", true), Errno::Noent);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn symlink_target() {
+        assert!(check_symlink_target("link".as_ref(), "a/../b").is_ok());
+        assert!(check_symlink_target("a/b/link".as_ref(), "../../c").is_ok());
+        assert!(check_symlink_target("a/link".as_ref(), "../../c").is_err());
+        assert!(check_symlink_target("link".as_ref(), "/etc").is_err());
+    }
+}
@@ -1,19 +1,67 @@
-use super::{file::FileEntry, file_table::FileTable};
+use super::{
+    clock::{ClockId, SystemClock, WasiClock},
+    file::{File, FileEntry, Filestat, Rights, ALL_RIGHTS},
+    file_table::FileTable,
+    path::{check_moved_symlinks, check_symlink_target, resolve},
+    types::Errno,
+    wasi_dir::WasiDir,
+    wasi_file::{file_times, WasiFile},
+};
 use crate::{
-    binary::instruction::MemoryArg, memory_load, memory_write, module::ExternalFuncInst,
-    wasi::file::FileCaps, Importer, Store, Value,
+    binary::{
+        instruction::MemoryArg,
+        types::{FuncType, ValueType},
+    },
+    error::Error,
+    memory_load, memory_write,
+    module::{ExternalFuncInst, InternalMemoryInst, MemoryInst},
+    wasi::file::FileCaps,
+    Caller, Importer, Store, Value,
 };
-use anyhow::{Context as _, Result};
+use anyhow::{bail, Result};
 use rand::prelude::*;
 use std::{
-    cell::RefCell,
-    rc::Rc,
+    io::SeekFrom,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-#[derive(Default)]
+// https://github.com/WebAssembly/WASI/blob/main/legacy/preview1/docs.md#lookupflags
+const LOOKUP_SYMLINK_FOLLOW: i32 = 0b1;
+
+// https://github.com/WebAssembly/WASI/blob/main/legacy/preview1/docs.md#oflags
+const OFLAGS_CREAT: i32 = 0b1;
+const OFLAGS_DIRECTORY: i32 = 0b10;
+const OFLAGS_EXCL: i32 = 0b100;
+const OFLAGS_TRUNC: i32 = 0b1000;
+
+// https://github.com/WebAssembly/WASI/blob/main/legacy/preview1/docs.md#fdflags
+const FDFLAGS_APPEND: i32 = 0b1;
+
+// https://github.com/WebAssembly/WASI/blob/main/legacy/preview1/docs.md#whence
+const WHENCE_SET: i32 = 0;
+const WHENCE_CUR: i32 = 1;
+const WHENCE_END: i32 = 2;
+
+// https://github.com/WebAssembly/WASI/blob/main/legacy/preview1/docs.md#fstflags
+const FSTFLAGS_ATIM: i32 = 0b1;
+const FSTFLAGS_ATIM_NOW: i32 = 0b10;
+const FSTFLAGS_MTIM: i32 = 0b100;
+const FSTFLAGS_MTIM_NOW: i32 = 0b1000;
+
 pub struct WasiSnapshotPreview1 {
-    file_table: FileTable,
+    file_table: Mutex<FileTable>,
+    clock: Box<dyn WasiClock>,
+}
+
+impl Default for WasiSnapshotPreview1 {
+    fn default() -> Self {
+        Self {
+            file_table: Mutex::default(),
+            clock: Box::<SystemClock>::default(),
+        }
+    }
 }
 
 impl Importer for WasiSnapshotPreview1 {
@@ -23,32 +71,166 @@ impl Importer for WasiSnapshotPreview1 {
 
     fn invoke(
         &self,
-        store: Rc<RefCell<Store>>,
+        store: Arc<Mutex<Store>>,
+        func: ExternalFuncInst,
+        args: Vec<Value>,
+    ) -> Result<Vec<Value>> {
+        self.invoke_with_caller(Caller::new(store), func, args)
+    }
+
+    // the memory is taken from the calling instance
+    fn invoke_with_caller(
+        &self,
+        caller: Caller,
         func: ExternalFuncInst,
         args: Vec<Value>,
-    ) -> Result<Option<Value>> {
+    ) -> Result<Vec<Value>> {
+        let Some(func_type) = signature(&func.field) else {
+            // the function which isn't implemented returns the errno if the guest expects it
+            if func.func_type.results != [ValueType::I32] {
+                bail!(Error::NotFoundImportFunc(self.name().into(), func.field));
+            }
+            return Ok(vec![(Errno::Nosys as i32).into()]);
+        };
+        // the handlers rely on the arity and the types of the arguments
+        if !args
+            .iter()
+            .map(Value::value_type)
+            .eq(func_type.params.iter().cloned())
+        {
+            bail!(Error::UnexpectedStackValueType(format!("{args:?}")));
+        }
+
         let value = match func.field.as_str() {
-            "fd_read" => self.fd_read(store, args),
-            "fd_write" => self.fd_write(store, args),
+            "fd_read" => self.fd_read(&caller.memory()?, args),
+            "fd_write" => self.fd_write(&caller.memory()?, args),
             "proc_exit" => {
                 self.proc_exit(args);
             }
-            "environ_get" => self.environ_get(store, args),
-            "environ_sizes_get" => self.environ_sizes_get(store, args),
-            "args_get" => self.args_get(store, args),
-            "args_sizes_get" => self.args_sizes_get(store, args),
-            "random_get" => self.random_get(store, args),
-            "fd_fdstat_get" => self.fd_fdstat_get(store, args),
-            _ => todo!(),
-        }?;
-        Ok(Some(value))
+            "environ_get" => self.environ_get(&caller.memory()?, args),
+            "environ_sizes_get" => self.environ_sizes_get(&caller.memory()?, args),
+            "args_get" => self.args_get(&caller.memory()?, args),
+            "args_sizes_get" => self.args_sizes_get(&caller.memory()?, args),
+            "random_get" => self.random_get(&caller.memory()?, args),
+            "fd_fdstat_get" => self.fd_fdstat_get(&caller.memory()?, args),
+            "fd_prestat_get" => self.fd_prestat_get(&caller.memory()?, args),
+            "fd_prestat_dir_name" => self.fd_prestat_dir_name(&caller.memory()?, args),
+            "path_open" => self.path_open(&caller.memory()?, args),
+            "fd_close" => self.fd_close(args),
+            "fd_seek" => self.fd_seek(&caller.memory()?, args),
+            "fd_tell" => self.fd_tell(&caller.memory()?, args),
+            "fd_renumber" => self.fd_renumber(args),
+            "fd_filestat_get" => self.fd_filestat_get(&caller.memory()?, args),
+            "path_filestat_get" => self.path_filestat_get(&caller.memory()?, args),
+            "fd_filestat_set_size" => self.fd_filestat_set_size(args),
+            "fd_filestat_set_times" => self.fd_filestat_set_times(args),
+            "path_filestat_set_times" => self.path_filestat_set_times(&caller.memory()?, args),
+            "fd_readdir" => self.fd_readdir(&caller.memory()?, args),
+            "path_create_directory" => self.path_create_directory(&caller.memory()?, args),
+            "path_remove_directory" => self.path_remove_directory(&caller.memory()?, args),
+            "path_unlink_file" => self.path_unlink_file(&caller.memory()?, args),
+            "path_rename" => self.path_rename(&caller.memory()?, args),
+            "path_symlink" => self.path_symlink(&caller.memory()?, args),
+            "path_readlink" => self.path_readlink(&caller.memory()?, args),
+            "path_link" => self.path_link(&caller.memory()?, args),
+            "clock_time_get" => self.clock_time_get(&caller.memory()?, args),
+            "clock_res_get" => self.clock_res_get(&caller.memory()?, args),
+            _ => bail!(Error::NotFoundImportFunc(self.name().into(), func.field)),
+        };
+        let value = match value {
+            Ok(value) => value,
+            Err(e) => (Errno::from_error(e)? as i32).into(),
+        };
+        Ok(vec![value])
+    }
+
+    // the import with the other signature is rejected when the module is linked
+    fn resolve_func(&self, _module: &str, field: &str) -> Result<Option<FuncType>> {
+        Ok(signature(field))
     }
 }
 
 impl WasiSnapshotPreview1 {
     pub fn with_io(files: Vec<Arc<Mutex<FileEntry>>>) -> Self {
-        let file_table = FileTable::with_io(files);
-        Self { file_table }
+        let file_table = Mutex::new(FileTable::with_io(files));
+        Self {
+            file_table,
+            clock: Box::<SystemClock>::default(),
+        }
+    }
+
+    // replace the clock of the host, e.g. with the fake clock for the test
+    pub fn with_clock(mut self, clock: impl WasiClock + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    // give the guest access to the directory on the host by the guest path,
+    // the guest can't access the files outside of the directory.
+    // NOTE: the path is resolved on the host before it's opened by the path,
+    // so the directory must not be modified concurrently by others, e.g. the other instances
+    // sharing this context or the host processes. a symlink or a rename between them
+    // can make the path point outside of the directory.
+    pub fn preopen_dir(self, host_path: impl AsRef<Path>, guest_path: &str) -> Result<Self> {
+        self.preopen_dir_with_caps(host_path, guest_path, &[])
+    }
+
+    // the operations in the directory are limited to the caps,
+    // and the files opened in it can't have the other caps. all caps are given if empty.
+    pub fn preopen_dir_with_caps(
+        self,
+        host_path: impl AsRef<Path>,
+        guest_path: &str,
+        caps: &[FileCaps],
+    ) -> Result<Self> {
+        let rights = if caps.is_empty() {
+            ALL_RIGHTS
+        } else {
+            caps.iter().fold(0, |rights, cap| rights | *cap as Rights)
+        };
+        let host_path = host_path.as_ref().canonicalize()?;
+        if !host_path.is_dir() {
+            bail!("not a directory: {}", host_path.display());
+        }
+        let dir = std::fs::File::open(&host_path)?;
+        let entry = FileEntry::open(
+            Box::new(WasiDir::new(dir, host_path.clone())),
+            Some(host_path),
+            rights,
+            rights,
+        )
+        .preopened(guest_path);
+        self.file_table
+            .lock()
+            .expect("cannot lock file table")
+            .add(Arc::new(Mutex::new(entry)));
+        Ok(self)
+    }
+
+    fn file(&self, fd: usize) -> Result<Arc<Mutex<FileEntry>>> {
+        let file_table = self.file_table.lock().expect("cannot lock file table");
+        let file = file_table.get(fd).ok_or(Errno::Badf)?;
+        Ok(Arc::clone(file))
+    }
+
+    // resolve the guest path in the directory of the fd, which needs the right `cap`
+    fn resolve_path(&self, fd: i32, cap: FileCaps, path: &str, follow: bool) -> Result<PathBuf> {
+        let dir = self.file(fd as u32 as usize)?;
+        let dir = dir.lock().expect("cannot lock file");
+        resolve(dir.dir(cap)?, path, follow)
+    }
+
+    // resolve the path of the entry to create, remove or rename in the directory.
+    // the last symlink is not followed, and the directory itself can't be the entry.
+    fn resolve_entry(&self, fd: i32, cap: FileCaps, path: &str) -> Result<(PathBuf, PathBuf)> {
+        let dir = self.file(fd as u32 as usize)?;
+        let dir = dir.lock().expect("cannot lock file");
+        let base = dir.dir(cap)?;
+        let host_path = resolve(base, path, false)?;
+        if host_path == base {
+            bail!(Errno::Acces);
+        }
+        Ok((base.to_path_buf(), host_path))
     }
 
     fn proc_exit(&self, args: Vec<Value>) -> ! {
@@ -60,13 +242,11 @@ impl WasiSnapshotPreview1 {
         std::process::exit(exit_code);
     }
 
-    fn environ_get(&self, store: Rc<RefCell<Store>>, args: Vec<Value>) -> Result<Value> {
+    fn environ_get(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
         let args: Vec<i32> = args.into_iter().map(Into::into).collect();
-        let (mut offset, mut buf_offset) = (args[0] as usize, args[1] as usize);
+        let (mut offset, mut buf_offset) = (args[0] as u32 as usize, args[1] as u32 as usize);
 
-        let store = store.borrow();
-        let memory = store.memory.first().with_context(|| "not found memory")?;
-        let mut memory = memory.borrow_mut();
+        let mut memory = memory.lock().expect("cannot lock memory");
 
         let env = std::env::vars();
         for (key, val) in env {
@@ -84,13 +264,11 @@ impl WasiSnapshotPreview1 {
         Ok(0.into())
     }
 
-    fn environ_sizes_get(&self, store: Rc<RefCell<Store>>, args: Vec<Value>) -> Result<Value> {
+    fn environ_sizes_get(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
         let args: Vec<i32> = args.into_iter().map(Into::into).collect();
-        let (offset, buf_offset) = (args[0] as usize, args[1] as usize);
+        let (offset, buf_offset) = (args[0] as u32 as usize, args[1] as u32 as usize);
 
-        let store = store.borrow();
-        let memory = store.memory.first().with_context(|| "not found memory")?;
-        let mut memory = memory.borrow_mut();
+        let mut memory = memory.lock().expect("cannot lock memory");
 
         let env = std::env::vars();
 
@@ -108,25 +286,18 @@ impl WasiSnapshotPreview1 {
         Ok(0.into())
     }
 
-    fn fd_read(&self, store: Rc<RefCell<Store>>, args: Vec<Value>) -> Result<Value> {
+    fn fd_read(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
         let args: Vec<i32> = args.into_iter().map(Into::into).collect();
         let (fd, mut iovs, iovs_len, nread_offset) = (
-            args[0] as usize,
-            args[1] as usize,
-            args[2] as usize,
-            args[3] as usize,
+            args[0] as u32 as usize,
+            args[1] as u32 as usize,
+            args[2] as u32 as usize,
+            args[3] as u32 as usize,
         );
 
-        let store = store.borrow();
-        let memory = store.memory.first().with_context(|| "not found memory")?;
-        let mut memory = memory.borrow_mut();
-
-        let file = self
-            .file_table
-            .get(fd)
-            .with_context(|| format!("cannot get file with fd: {}", fd))?;
+        let mut memory = memory.lock().expect("cannot lock memory");
 
-        let file = Arc::clone(file);
+        let file = self.file(fd)?;
         let mut file = file.lock().expect("cannot lock file");
         let file = file.capbable(FileCaps::Read)?;
 
@@ -138,10 +309,8 @@ impl WasiSnapshotPreview1 {
             let len: i32 = memory_load!(memory, 0, 4, iovs);
             iovs += 4;
 
-            let offset = offset as usize;
-            let end = offset + len as usize;
-
-            nread += file.read(&mut memory.data[offset..end])?;
+            let buf = memory.bytes_mut(offset as u32 as usize, len as u32 as usize)?;
+            nread += file.read(buf)?;
         }
 
         memory_write!(memory, 0, 4, nread_offset, nread);
@@ -149,25 +318,18 @@ impl WasiSnapshotPreview1 {
         Ok(0.into())
     }
 
-    fn fd_write(&self, store: Rc<RefCell<Store>>, args: Vec<Value>) -> Result<Value> {
+    fn fd_write(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
         let args: Vec<i32> = args.into_iter().map(Into::into).collect();
         let (fd, mut iovs, iovs_len, rp) = (
-            args[0] as usize,
-            args[1] as usize,
-            args[2] as usize,
-            args[3] as usize,
+            args[0] as u32 as usize,
+            args[1] as u32 as usize,
+            args[2] as u32 as usize,
+            args[3] as u32 as usize,
         );
 
-        let store = store.borrow();
-        let memory = store.memory.first().with_context(|| "not found memory")?;
-        let mut memory = memory.borrow_mut();
-
-        let file = self
-            .file_table
-            .get(fd)
-            .with_context(|| format!("cannot get file with fd: {}", fd))?;
-        let file = Arc::clone(file);
+        let mut memory = memory.lock().expect("cannot lock memory");
 
+        let file = self.file(fd)?;
         let mut file = file.lock().expect("cannot lock file");
         let file = file.capbable(FileCaps::Write)?;
 
@@ -180,9 +342,7 @@ impl WasiSnapshotPreview1 {
             let len: i32 = memory_load!(memory, 0, 4, iovs);
             iovs += 4;
 
-            let offset = offset as usize;
-            let end = offset + len as usize;
-            let buf = &memory.data[offset..end];
+            let buf = memory.read_bytes(offset as u32 as usize, len as u32 as usize)?;
 
             written += file.write(buf)?;
         }
@@ -192,13 +352,11 @@ impl WasiSnapshotPreview1 {
         Ok(0.into())
     }
 
-    fn args_get(&self, store: Rc<RefCell<Store>>, args: Vec<Value>) -> Result<Value> {
+    fn args_get(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
         let args: Vec<i32> = args.into_iter().map(Into::into).collect();
-        let (mut offset, mut buf_offset) = (args[0] as usize, args[1] as usize);
+        let (mut offset, mut buf_offset) = (args[0] as u32 as usize, args[1] as u32 as usize);
 
-        let store = store.borrow();
-        let memory = store.memory.first().with_context(|| "not found memory")?;
-        let mut memory = memory.borrow_mut();
+        let mut memory = memory.lock().expect("cannot lock memory");
 
         let args = std::env::args();
         for arg in args {
@@ -216,13 +374,11 @@ impl WasiSnapshotPreview1 {
         Ok(0.into())
     }
 
-    fn args_sizes_get(&self, store: Rc<RefCell<Store>>, args: Vec<Value>) -> Result<Value> {
+    fn args_sizes_get(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
         let args: Vec<i32> = args.into_iter().map(Into::into).collect();
-        let (offset, buf_offset) = (args[0] as usize, args[1] as usize);
+        let (offset, buf_offset) = (args[0] as u32 as usize, args[1] as u32 as usize);
 
-        let store = store.borrow();
-        let memory = store.memory.first().with_context(|| "not found memory")?;
-        let mut memory = memory.borrow_mut();
+        let mut memory = memory.lock().expect("cannot lock memory");
 
         let args = std::env::args();
 
@@ -240,13 +396,11 @@ impl WasiSnapshotPreview1 {
         Ok(0.into())
     }
 
-    fn random_get(&self, store: Rc<RefCell<Store>>, args: Vec<Value>) -> Result<Value> {
+    fn random_get(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
         let args: Vec<i32> = args.into_iter().map(Into::into).collect();
-        let (mut offset, buf_len) = (args[0] as usize, args[1] as usize);
+        let (mut offset, buf_len) = (args[0] as u32 as usize, args[1] as u32 as usize);
 
-        let store = store.borrow();
-        let memory = store.memory.first().with_context(|| "not found memory")?;
-        let mut memory = memory.borrow_mut();
+        let mut memory = memory.lock().expect("cannot lock memory");
 
         let mut rng = thread_rng();
 
@@ -262,27 +416,530 @@ impl WasiSnapshotPreview1 {
         Ok(0.into())
     }
 
-    fn fd_fdstat_get(&self, store: Rc<RefCell<Store>>, args: Vec<Value>) -> Result<Value> {
+    fn fd_fdstat_get(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
         let args: Vec<i32> = args.into_iter().map(Into::into).collect();
-        let (fd, offset) = (args[0] as usize, args[1] as usize);
+        let (fd, offset) = (args[0] as u32 as usize, args[1] as u32 as usize);
 
-        let store = store.borrow();
-        let memory = store.memory.first().with_context(|| "not found memory")?;
-        let mut memory = memory.borrow_mut();
+        let mut memory = memory.lock().expect("cannot lock memory");
 
-        let file = self
-            .file_table
-            .get(fd)
-            .with_context(|| format!("cannot get file with fd: {}", fd))?;
+        let file = self.file(fd)?;
         let file = file.lock().expect("cannot lock file");
         let stat = file.get_fdstat()?;
 
         // ref: https://deno.land/std@0.206.0/wasi/snapshot_preview1.ts?source=#L673
         memory.write_bytes(offset, get_memory(&stat.filetype))?;
-        memory.write_bytes(offset + 2, get_memory(&stat.flags))?;
+        memory.write_bytes(offset.saturating_add(2), get_memory(&stat.flags))?;
+        memory.write_bytes(offset.saturating_add(8), &stat.rights.to_le_bytes())?;
+        memory.write_bytes(offset.saturating_add(16), &stat.inheriting.to_le_bytes())?;
+
+        Ok(0.into())
+    }
+
+    fn fd_prestat_get(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
+        let args: Vec<i32> = args.into_iter().map(Into::into).collect();
+        let (fd, offset) = (args[0] as u32 as usize, args[1] as u32 as usize);
+
+        let mut memory = memory.lock().expect("cannot lock memory");
+
+        let file = self.file(fd)?;
+        let file = file.lock().expect("cannot lock file");
+        let name = file.preopen().ok_or(Errno::Badf)?;
+
+        // prestat is the union of the directory, the tag 0 and the length of the name
+        memory_write!(memory, 0, 1, offset, 0);
+        memory_write!(memory, 0, 4, offset.saturating_add(4), name.len());
+
+        Ok(0.into())
+    }
+
+    fn fd_prestat_dir_name(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
+        let args: Vec<i32> = args.into_iter().map(Into::into).collect();
+        let (fd, offset, len) = (
+            args[0] as u32 as usize,
+            args[1] as u32 as usize,
+            args[2] as u32 as usize,
+        );
+
+        let mut memory = memory.lock().expect("cannot lock memory");
+
+        let file = self.file(fd)?;
+        let file = file.lock().expect("cannot lock file");
+        let name = file.preopen().ok_or(Errno::Badf)?;
+        if len < name.len() {
+            bail!(Errno::Nametoolong);
+        }
+        memory.write_bytes(offset, name.as_bytes())?;
+
+        Ok(0.into())
+    }
+
+    fn path_open(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
+        let [fd, dirflags, path, path_len, oflags, rights, inheriting, fdflags, opened_fd] =
+            params(args)?;
+        let (fd, dirflags, oflags, fdflags, opened_fd): (i32, i32, i32, i32, i32) = (
+            fd.into(),
+            dirflags.into(),
+            oflags.into(),
+            fdflags.into(),
+            opened_fd.into(),
+        );
+        let (rights, inheriting): (i64, i64) = (rights.into(), inheriting.into());
+        let (rights, inheriting) = (rights as u64, inheriting as u64);
+
+        let mut memory = memory.lock().expect("cannot lock memory");
+        let path = read_path(&memory, path, path_len)?;
+
+        let dir = self.file(fd as u32 as usize)?;
+        let (host_path, dir_inheriting) = {
+            let dir = dir.lock().expect("cannot lock file");
+            let base = dir.dir(FileCaps::Open)?;
+            if oflags & OFLAGS_CREAT != 0 && !dir.has(FileCaps::CreateFile) {
+                bail!(Errno::Notcapable);
+            }
+            let follow = dirflags & LOOKUP_SYMLINK_FOLLOW != 0;
+            (resolve(base, &path, follow)?, dir.inheriting())
+        };
+        // the symlink is not opened without following it, like O_NOFOLLOW
+        if dirflags & LOOKUP_SYMLINK_FOLLOW == 0 && host_path.is_symlink() {
+            bail!(Errno::Loop);
+        }
+
+        let file = if oflags & OFLAGS_DIRECTORY != 0 {
+            if !host_path.is_dir() {
+                bail!(Errno::Notdir);
+            }
+            std::fs::File::open(&host_path)?
+        } else {
+            // the rights to change the file need to open it for writing
+            let write_caps = [
+                FileCaps::Write,
+                FileCaps::DataSync,
+                FileCaps::Allocate,
+                FileCaps::FilestatSetSize,
+            ];
+            let write = write_caps.iter().any(|cap| rights & *cap as u64 != 0);
+            let create = oflags & OFLAGS_CREAT != 0;
+            let truncate = oflags & OFLAGS_TRUNC != 0;
+            std::fs::OpenOptions::new()
+                .read(rights & (FileCaps::Read as u64) != 0 || !write)
+                .write(write || create || truncate)
+                .append(fdflags & FDFLAGS_APPEND != 0)
+                .create(create)
+                .create_new(create && oflags & OFLAGS_EXCL != 0)
+                .truncate(truncate)
+                .open(&host_path)?
+        };
+
+        let (file, dir_path): (Box<dyn File>, _) = if host_path.is_dir() {
+            let dir = WasiDir::new(file, host_path.clone());
+            (Box::new(dir), Some(host_path))
+        } else {
+            (Box::new(WasiFile::new(file)), None)
+        };
+        let entry = FileEntry::open(
+            file,
+            dir_path,
+            rights & dir_inheriting,
+            inheriting & dir_inheriting,
+        );
+        let fd = self
+            .file_table
+            .lock()
+            .expect("cannot lock file table")
+            .add(Arc::new(Mutex::new(entry)));
+        memory_write!(memory, 0, 4, opened_fd as u32 as usize, fd);
+
+        Ok(0.into())
+    }
+
+    fn fd_close(&self, args: Vec<Value>) -> Result<Value> {
+        let fd: i32 = args[0].clone().into();
+        self.file_table
+            .lock()
+            .expect("cannot lock file table")
+            .remove(fd as u32 as usize)
+            .ok_or(Errno::Badf)?;
+        Ok(0.into())
+    }
+
+    fn fd_seek(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
+        let [fd, offset, whence, new_offset] = params(args)?;
+        let (fd, whence, new_offset): (i32, i32, i32) =
+            (fd.into(), whence.into(), new_offset.into());
+        let offset: i64 = offset.into();
+
+        let mut memory = memory.lock().expect("cannot lock memory");
+
+        let pos = match whence {
+            WHENCE_SET => SeekFrom::Start(u64::try_from(offset).map_err(|_| Errno::Inval)?),
+            WHENCE_CUR => SeekFrom::Current(offset),
+            WHENCE_END => SeekFrom::End(offset),
+            _ => bail!(Errno::Inval),
+        };
+        // getting the current position only needs the right of fd_tell
+        let cap = if pos == SeekFrom::Current(0) {
+            FileCaps::Tell
+        } else {
+            FileCaps::Seek
+        };
+
+        let file = self.file(fd as u32 as usize)?;
+        let mut file = file.lock().expect("cannot lock file");
+        let pos = file.capbable(cap)?.seek_from(pos)?;
+        memory.write_bytes(new_offset as u32 as usize, &pos.to_le_bytes())?;
+
+        Ok(0.into())
+    }
+
+    fn fd_tell(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
+        let args: Vec<i32> = args.into_iter().map(Into::into).collect();
+        let (fd, offset) = (args[0] as u32 as usize, args[1] as u32 as usize);
+
+        let mut memory = memory.lock().expect("cannot lock memory");
+
+        let file = self.file(fd)?;
+        let mut file = file.lock().expect("cannot lock file");
+        let pos = file
+            .capbable(FileCaps::Tell)?
+            .seek_from(SeekFrom::Current(0))?;
+        memory.write_bytes(offset, &pos.to_le_bytes())?;
+
+        Ok(0.into())
+    }
+
+    fn fd_filestat_get(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
+        let args: Vec<i32> = args.into_iter().map(Into::into).collect();
+        let (fd, offset) = (args[0] as u32 as usize, args[1] as u32 as usize);
+
+        let mut memory = memory.lock().expect("cannot lock memory");
+
+        let file = self.file(fd)?;
+        let mut file = file.lock().expect("cannot lock file");
+        let stat = file.capbable(FileCaps::FilestatGet)?.filestat()?;
+        memory.write_bytes(offset, &stat.to_bytes())?;
+
+        Ok(0.into())
+    }
+
+    fn path_filestat_get(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
+        let [fd, flags, path, path_len, offset] = params(args)?;
+        let (fd, flags, offset): (i32, i32, i32) = (fd.into(), flags.into(), offset.into());
+
+        let mut memory = memory.lock().expect("cannot lock memory");
+        let path = read_path(&memory, path, path_len)?;
+
+        let follow = flags & LOOKUP_SYMLINK_FOLLOW != 0;
+        let host_path = self.resolve_path(fd, FileCaps::PathFilestatGet, &path, follow)?;
+        let metadata = if follow {
+            std::fs::metadata(&host_path)?
+        } else {
+            std::fs::symlink_metadata(&host_path)?
+        };
+        let stat = Filestat::from(&metadata);
+        memory.write_bytes(offset as u32 as usize, &stat.to_bytes())?;
+
+        Ok(0.into())
+    }
+
+    fn fd_filestat_set_size(&self, args: Vec<Value>) -> Result<Value> {
+        let [fd, size] = params(args)?;
+        let (fd, size): (i32, i64) = (fd.into(), size.into());
+
+        let file = self.file(fd as u32 as usize)?;
+        let mut file = file.lock().expect("cannot lock file");
+        file.capbable(FileCaps::FilestatSetSize)?
+            .set_size(size as u64)?;
+
+        Ok(0.into())
+    }
+
+    fn fd_filestat_set_times(&self, args: Vec<Value>) -> Result<Value> {
+        let [fd, atim, mtim, fst_flags] = params(args)?;
+        let (fd, fst_flags): (i32, i32) = (fd.into(), fst_flags.into());
+        let (atim, mtim) = timestamps(atim.into(), mtim.into(), fst_flags, self.clock.as_ref())?;
+
+        let file = self.file(fd as u32 as usize)?;
+        let mut file = file.lock().expect("cannot lock file");
+        file.capbable(FileCaps::FilestatSetTimes)?
+            .set_times(atim, mtim)?;
+
+        Ok(0.into())
+    }
+
+    fn path_filestat_set_times(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
+        let [fd, flags, path, path_len, atim, mtim, fst_flags] = params(args)?;
+        let (fd, flags, fst_flags): (i32, i32, i32) = (fd.into(), flags.into(), fst_flags.into());
+        let (atim, mtim) = timestamps(atim.into(), mtim.into(), fst_flags, self.clock.as_ref())?;
+
+        let memory = memory.lock().expect("cannot lock memory");
+        let path = read_path(&memory, path, path_len)?;
+
+        let follow = flags & LOOKUP_SYMLINK_FOLLOW != 0;
+        let host_path = self.resolve_path(fd, FileCaps::PathFilestatSetTimes, &path, follow)?;
+        // the times of the symlink itself can't be changed by std
+        if !follow && host_path.is_symlink() {
+            bail!(Errno::Notsup);
+        }
+        std::fs::File::open(&host_path)?.set_times(file_times(atim, mtim))?;
+
+        Ok(0.into())
+    }
+
+    // write the entries from the cookie until the buffer is full,
+    // the last entry is truncated if it doesn't fit in the buffer
+    fn fd_readdir(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
+        let [fd, buf, buf_len, cookie, bufused] = params(args)?;
+        let (fd, buf, buf_len, bufused): (i32, i32, i32, i32) =
+            (fd.into(), buf.into(), buf_len.into(), bufused.into());
+        let cookie: i64 = cookie.into();
+        let buf_len = buf_len as u32 as usize;
+
+        let mut memory = memory.lock().expect("cannot lock memory");
+
+        let file = self.file(fd as u32 as usize)?;
+        let dirents = file
+            .lock()
+            .expect("cannot lock file")
+            .capbable(FileCaps::Readdir)?
+            .readdir()?;
+
+        let mut bytes = vec![];
+        for dirent in dirents.iter().skip(cookie as u64 as usize) {
+            if bytes.len() >= buf_len {
+                break;
+            }
+            bytes.extend(dirent.to_bytes());
+        }
+        bytes.truncate(buf_len);
+        memory.write_bytes(buf as u32 as usize, &bytes)?;
+        memory_write!(memory, 0, 4, bufused as u32 as usize, bytes.len());
+
+        Ok(0.into())
+    }
+
+    fn path_create_directory(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
+        let [fd, path, path_len] = params(args)?;
+
+        let memory = memory.lock().expect("cannot lock memory");
+        let path = read_path(&memory, path, path_len)?;
+
+        let (_, host_path) = self.resolve_entry(fd.into(), FileCaps::CreateDirectory, &path)?;
+        std::fs::create_dir(host_path)?;
+
+        Ok(0.into())
+    }
+
+    fn path_remove_directory(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
+        let [fd, path, path_len] = params(args)?;
+
+        let memory = memory.lock().expect("cannot lock memory");
+        let path = read_path(&memory, path, path_len)?;
+
+        let (_, host_path) = self.resolve_entry(fd.into(), FileCaps::RemoveDirectory, &path)?;
+        std::fs::remove_dir(host_path)?;
+
+        Ok(0.into())
+    }
+
+    fn path_unlink_file(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
+        let [fd, path, path_len] = params(args)?;
+
+        let memory = memory.lock().expect("cannot lock memory");
+        let path = read_path(&memory, path, path_len)?;
+
+        let (_, host_path) = self.resolve_entry(fd.into(), FileCaps::UnlinkFile, &path)?;
+        if std::fs::symlink_metadata(&host_path)?.is_dir() {
+            bail!(Errno::Isdir);
+        }
+        std::fs::remove_file(host_path)?;
+
+        Ok(0.into())
+    }
+
+    fn path_rename(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
+        let [fd, old_path, old_len, new_fd, new_path, new_len] = params(args)?;
+
+        let memory = memory.lock().expect("cannot lock memory");
+        let old_path = read_path(&memory, old_path, old_len)?;
+        let new_path = read_path(&memory, new_path, new_len)?;
+
+        let (_, old_path) = self.resolve_entry(fd.into(), FileCaps::RenameSource, &old_path)?;
+        let (base, new_path) =
+            self.resolve_entry(new_fd.into(), FileCaps::RenameTarget, &new_path)?;
+        let dest = new_path
+            .strip_prefix(&base)
+            .map_err(|_| Errno::Notcapable)?;
+        check_moved_symlinks(&old_path, dest)?;
+        std::fs::rename(old_path, new_path)?;
+
+        Ok(0.into())
+    }
+
+    fn path_symlink(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
+        let [target, target_len, fd, path, path_len] = params(args)?;
+
+        let memory = memory.lock().expect("cannot lock memory");
+        let target = read_path(&memory, target, target_len)?;
+        let path = read_path(&memory, path, path_len)?;
+
+        let (base, host_path) = self.resolve_entry(fd.into(), FileCaps::Symlink, &path)?;
+        let link = host_path
+            .strip_prefix(&base)
+            .map_err(|_| Errno::Notcapable)?;
+        check_symlink_target(link, &target)?;
+        std::os::unix::fs::symlink(target, host_path)?;
+
+        Ok(0.into())
+    }
+
+    // the target is truncated if it doesn't fit in the buffer
+    fn path_readlink(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
+        let [fd, path, path_len, buf, buf_len, bufused] = params(args)?;
+        let (buf, buf_len, bufused): (i32, i32, i32) = (buf.into(), buf_len.into(), bufused.into());
+
+        let mut memory = memory.lock().expect("cannot lock memory");
+        let path = read_path(&memory, path, path_len)?;
+
+        let host_path = self.resolve_path(fd.into(), FileCaps::Readlink, &path, false)?;
+        let target = std::fs::read_link(host_path)?;
+        let target = target.to_str().ok_or(Errno::Ilseq)?.as_bytes();
+        let len = target.len().min(buf_len as u32 as usize);
+        memory.write_bytes(buf as u32 as usize, &target[..len])?;
+        memory_write!(memory, 0, 4, bufused as u32 as usize, len);
+
+        Ok(0.into())
+    }
+
+    fn path_link(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
+        let [old_fd, old_flags, old_path, old_len, new_fd, new_path, new_len] = params(args)?;
+        let (old_fd, old_flags): (i32, i32) = (old_fd.into(), old_flags.into());
+
+        let memory = memory.lock().expect("cannot lock memory");
+        let old_path = read_path(&memory, old_path, old_len)?;
+        let new_path = read_path(&memory, new_path, new_len)?;
+
+        let follow = old_flags & LOOKUP_SYMLINK_FOLLOW != 0;
+        let old_path = self.resolve_path(old_fd, FileCaps::LinkSource, &old_path, follow)?;
+        let (base, new_path) =
+            self.resolve_entry(new_fd.into(), FileCaps::LinkTarget, &new_path)?;
+        let dest = new_path
+            .strip_prefix(&base)
+            .map_err(|_| Errno::Notcapable)?;
+        check_moved_symlinks(&old_path, dest)?;
+        std::fs::hard_link(old_path, new_path)?;
+
+        Ok(0.into())
+    }
+
+    // the precision is only a hint, so it's ignored
+    fn clock_time_get(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
+        let [id, _precision, offset] = params(args)?;
+        let (id, offset): (i32, i32) = (id.into(), offset.into());
+
+        let mut memory = memory.lock().expect("cannot lock memory");
+
+        let time = self.clock.time(ClockId::try_from(id)?)?;
+        memory.write_bytes(offset as u32 as usize, &time.to_le_bytes())?;
 
         Ok(0.into())
     }
+
+    fn clock_res_get(&self, memory: &MemoryInst, args: Vec<Value>) -> Result<Value> {
+        let args: Vec<i32> = args.into_iter().map(Into::into).collect();
+        let (id, offset) = (args[0], args[1] as u32 as usize);
+
+        let mut memory = memory.lock().expect("cannot lock memory");
+
+        let resolution = self.clock.resolution(ClockId::try_from(id)?)?;
+        memory.write_bytes(offset, &resolution.to_le_bytes())?;
+
+        Ok(0.into())
+    }
+
+    fn fd_renumber(&self, args: Vec<Value>) -> Result<Value> {
+        let args: Vec<i32> = args.into_iter().map(Into::into).collect();
+        let (fd, to) = (args[0] as u32 as usize, args[1] as u32 as usize);
+        self.file_table
+            .lock()
+            .expect("cannot lock file table")
+            .renumber(fd, to)
+            .ok_or(Errno::Badf)?;
+        Ok(0.into())
+    }
+}
+
+// signatures of the implemented functions
+// https://github.com/WebAssembly/WASI/blob/main/legacy/preview1/witx/wasi_snapshot_preview1.witx
+fn signature(name: &str) -> Option<FuncType> {
+    use ValueType::{I32, I64};
+    let params = match name {
+        "proc_exit" | "fd_close" => vec![I32],
+        "environ_get" | "environ_sizes_get" | "args_get" | "args_sizes_get" | "random_get"
+        | "fd_fdstat_get" | "fd_prestat_get" | "fd_tell" | "fd_renumber" | "fd_filestat_get"
+        | "clock_res_get" => vec![I32, I32],
+        "fd_prestat_dir_name"
+        | "path_create_directory"
+        | "path_remove_directory"
+        | "path_unlink_file" => vec![I32, I32, I32],
+        "fd_read" | "fd_write" => vec![I32, I32, I32, I32],
+        "path_open" => vec![I32, I32, I32, I32, I32, I64, I64, I32, I32],
+        "fd_seek" => vec![I32, I64, I32, I32],
+        "path_filestat_get" => vec![I32, I32, I32, I32, I32],
+        "fd_filestat_set_size" => vec![I32, I64],
+        "fd_filestat_set_times" => vec![I32, I64, I64, I32],
+        "path_filestat_set_times" => vec![I32, I32, I32, I32, I64, I64, I32],
+        "fd_readdir" => vec![I32, I32, I32, I64, I32],
+        "path_rename" | "path_readlink" => vec![I32, I32, I32, I32, I32, I32],
+        "path_symlink" => vec![I32, I32, I32, I32, I32],
+        "path_link" => vec![I32, I32, I32, I32, I32, I32, I32],
+        "clock_time_get" => vec![I32, I64, I32],
+        _ => return None,
+    };
+    let results = if name == "proc_exit" {
+        vec![]
+    } else {
+        vec![I32]
+    };
+    Some(FuncType { params, results })
+}
+
+// the arguments are checked against the signature before the handler is called,
+// so this only fails if the signature doesn't match the handler
+fn params<const N: usize>(args: Vec<Value>) -> Result<[Value; N]> {
+    args.try_into()
+        .map_err(|args| Error::UnexpectedStackValueType(format!("{args:?}")).into())
+}
+
+// the times to set by the fst_flags, None is not changed
+fn timestamps(
+    atim: i64,
+    mtim: i64,
+    fst_flags: i32,
+    clock: &dyn WasiClock,
+) -> Result<(Option<SystemTime>, Option<SystemTime>)> {
+    let time = |t: i64, set: i32, now: i32| -> Result<Option<SystemTime>> {
+        match (fst_flags & set != 0, fst_flags & now != 0) {
+            (true, true) => bail!(Errno::Inval),
+            (true, false) => Ok(Some(UNIX_EPOCH + Duration::from_nanos(t as u64))),
+            (false, true) => {
+                let now = clock.time(ClockId::Realtime)?;
+                Ok(Some(UNIX_EPOCH + Duration::from_nanos(now)))
+            }
+            (false, false) => Ok(None),
+        }
+    };
+    Ok((
+        time(atim, FSTFLAGS_ATIM, FSTFLAGS_ATIM_NOW)?,
+        time(mtim, FSTFLAGS_MTIM, FSTFLAGS_MTIM_NOW)?,
+    ))
+}
+
+// read the guest path from the memory
+fn read_path(memory: &InternalMemoryInst, ptr: Value, len: Value) -> Result<String> {
+    let (ptr, len): (i32, i32) = (ptr.into(), len.into());
+    let bytes = memory.read_bytes(ptr as u32 as usize, len as u32 as usize)?;
+    let path = std::str::from_utf8(bytes).map_err(|_| Errno::Ilseq)?;
+    Ok(path.to_string())
 }
 
 fn get_memory<T>(input: &T) -> &[u8] {
@@ -295,7 +952,11 @@ mod tests {
 
     use super::*;
     use crate::{
-        wasi::{file::FileEntry, wasi_snapshot_preview1::virtual_file::VirtualFile},
+        wasi::{
+            file::{FdFlags, FileEntry, FileType},
+            wasi_dir::Dirent,
+            wasi_snapshot_preview1::virtual_file::VirtualFile,
+        },
         Runtime,
     };
     use pretty_assertions::assert_eq;
@@ -330,14 +991,8 @@ mod tests {
             "#;
         let wasm = wat::parse_str(code)?;
 
-        let stdin = Arc::new(Mutex::new(FileEntry::new(
-            Box::<VirtualFile>::default(),
-            FileCaps::Sync,
-        )));
-        let stdout = Arc::new(Mutex::new(FileEntry::new(
-            Box::<VirtualFile>::default(),
-            FileCaps::Sync,
-        )));
+        let stdin = Arc::new(Mutex::new(FileEntry::new(Box::<VirtualFile>::default())));
+        let stdout = Arc::new(Mutex::new(FileEntry::new(Box::<VirtualFile>::default())));
 
         let wasi = WasiSnapshotPreview1::with_io(vec![stdin, stdout.clone()]);
         let mut runtime = Runtime::from_bytes(wasm.as_slice(), Some(vec![Box::new(wasi)]))?;
@@ -359,14 +1014,8 @@ mod tests {
     fn test_args_get() -> Result<()> {
         let wasm = wat::parse_file("examples/args_get.wasm")?;
 
-        let stdin = Arc::new(Mutex::new(FileEntry::new(
-            Box::<VirtualFile>::default(),
-            FileCaps::Sync,
-        )));
-        let stdout = Arc::new(Mutex::new(FileEntry::new(
-            Box::<VirtualFile>::default(),
-            FileCaps::Sync,
-        )));
+        let stdin = Arc::new(Mutex::new(FileEntry::new(Box::<VirtualFile>::default())));
+        let stdout = Arc::new(Mutex::new(FileEntry::new(Box::<VirtualFile>::default())));
 
         let wasi = WasiSnapshotPreview1::with_io(vec![stdin, stdout.clone()]);
         let mut runtime = Runtime::from_bytes(wasm.as_slice(), Some(vec![Box::new(wasi)]))?;
@@ -386,15 +1035,11 @@ mod tests {
     fn test_fd_read() -> Result<()> {
         let wasm = wat::parse_file("examples/fd_read.wasm")?;
 
-        let stdin = Arc::new(Mutex::new(FileEntry::new(
-            Box::new(VirtualFile::new(b"hello world")),
-            FileCaps::Sync,
-        )));
+        let stdin = Arc::new(Mutex::new(FileEntry::new(Box::new(VirtualFile::new(
+            b"hello world",
+        )))));
 
-        let stdout = Arc::new(Mutex::new(FileEntry::new(
-            Box::<VirtualFile>::default(),
-            FileCaps::Sync,
-        )));
+        let stdout = Arc::new(Mutex::new(FileEntry::new(Box::<VirtualFile>::default())));
 
         let wasi = WasiSnapshotPreview1::with_io(vec![stdin.clone(), stdout.clone()]);
         let mut runtime = Runtime::from_bytes(wasm.as_slice(), Some(vec![Box::new(wasi)]))?;
@@ -407,4 +1052,629 @@ mod tests {
         assert_eq!(stdout.read_string()?, "input: got: hello world\n");
         Ok(())
     }
+
+    // guest which exports the wasi functions to call them from the test
+    fn wasi_runtime(wasi: WasiSnapshotPreview1, funcs: &[(&str, &str)]) -> Result<Runtime> {
+        let mut code = String::from("(module\n");
+        for (name, ty) in funcs {
+            code += &format!(
+                "(import \"wasi_snapshot_preview1\" \"{name}\" (func ${name} {ty}))\n\
+                 (export \"{name}\" (func ${name}))\n"
+            );
+        }
+        code += "(memory (export \"memory\") 1))";
+        let wasm = wat::parse_str(code)?;
+        Runtime::from_bytes(wasm, Some(vec![Box::new(wasi)]))
+    }
+
+    fn call(runtime: &mut Runtime, name: &str, args: Vec<Value>) -> Result<i32> {
+        let errno = runtime.call(name.into(), args)?.expect("not found result");
+        Ok(errno.into())
+    }
+
+    fn memory(runtime: &mut Runtime) -> Result<MemoryInst> {
+        match runtime.exports("memory".into())? {
+            crate::Exports::Memory(memory) => Ok(memory),
+            _ => panic!("memory is not exported"),
+        }
+    }
+
+    fn load_i32(memory: &MemoryInst, addr: usize) -> Result<i32> {
+        let memory = memory.lock().unwrap();
+        Ok(i32::from_le_bytes(memory.read_bytes(addr, 4)?.try_into()?))
+    }
+
+    // open the path in the preopened directory at fd 3, the opened fd is written at 200
+    fn path_open(
+        runtime: &mut Runtime,
+        memory: &MemoryInst,
+        path: &str,
+        oflags: i32,
+        rights: u64,
+    ) -> Result<i32> {
+        memory.lock().unwrap().write_bytes(100, path.as_bytes())?;
+        let args = vec![
+            3.into(),
+            1.into(),
+            100.into(),
+            (path.len() as i32).into(),
+            oflags.into(),
+            (rights as i64).into(),
+            0i64.into(),
+            0.into(),
+            200.into(),
+        ];
+        call(runtime, "path_open", args)
+    }
+
+    fn load_i64(memory: &MemoryInst, addr: usize) -> Result<i64> {
+        let memory = memory.lock().unwrap();
+        Ok(i64::from_le_bytes(memory.read_bytes(addr, 8)?.try_into()?))
+    }
+
+    fn temp_dir(name: &str) -> Result<std::path::PathBuf> {
+        let dir = std::env::temp_dir().join(format!("chibiwasm-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    fn stdio() -> Vec<Arc<Mutex<FileEntry>>> {
+        (0..3)
+            .map(|_| Arc::new(Mutex::new(FileEntry::new(Box::<VirtualFile>::default()))))
+            .collect()
+    }
+
+    #[test]
+    fn test_path_open() -> Result<()> {
+        let dir = temp_dir("path-open")?;
+        std::fs::write(dir.join("hello.txt"), "hello sandbox")?;
+        std::os::unix::fs::symlink("..", dir.join("escape"))?;
+
+        let wasi = WasiSnapshotPreview1::with_io(stdio()).preopen_dir(&dir, "/sandbox")?;
+        let mut runtime = wasi_runtime(
+            wasi,
+            &[
+                ("fd_prestat_get", "(param i32 i32) (result i32)"),
+                ("fd_prestat_dir_name", "(param i32 i32 i32) (result i32)"),
+                (
+                    "path_open",
+                    "(param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)",
+                ),
+                ("fd_read", "(param i32 i32 i32 i32) (result i32)"),
+                ("sched_yield", "(result i32)"),
+            ],
+        )?;
+        let memory = memory(&mut runtime)?;
+
+        // the preopened directory is the next of the stdio
+        let errno = call(&mut runtime, "fd_prestat_get", vec![3.into(), 0.into()])?;
+        assert_eq!(errno, 0);
+        let len = load_i32(&memory, 4)?;
+        assert_eq!(len, 8);
+        let args = vec![3.into(), 16.into(), 8.into()];
+        assert_eq!(call(&mut runtime, "fd_prestat_dir_name", args)?, 0);
+        assert_eq!(memory.lock().unwrap().read_bytes(16, 8)?, b"/sandbox");
+        let args = vec![3.into(), 16.into(), 4.into()];
+        let errno = call(&mut runtime, "fd_prestat_dir_name", args)?;
+        assert_eq!(errno, Errno::Nametoolong as i32);
+        for fd in [0, 4] {
+            let errno = call(&mut runtime, "fd_prestat_get", vec![fd.into(), 0.into()])?;
+            assert_eq!(errno, Errno::Badf as i32);
+        }
+
+        let rights = FileCaps::Read as u64 | FileCaps::Seek as u64;
+        let mut open = |path| path_open(&mut runtime, &memory, path, 0, rights);
+        assert_eq!(open("../hello.txt")?, Errno::Notcapable as i32);
+        assert_eq!(open("escape/hello.txt")?, Errno::Notcapable as i32);
+        assert_eq!(open("missing.txt")?, Errno::Noent as i32);
+        assert_eq!(open("./hello.txt")?, 0);
+        let fd = load_i32(&memory, 200)?;
+        assert_eq!(fd, 4);
+
+        // read the opened file by the iovec at 300
+        {
+            let mut memory = memory.lock().unwrap();
+            memory_write!(memory, 0, 4, 300, 400);
+            memory_write!(memory, 0, 4, 304, 32);
+        }
+        let args = vec![fd.into(), 300.into(), 1.into(), 308.into()];
+        assert_eq!(call(&mut runtime, "fd_read", args)?, 0);
+        let nread = load_i32(&memory, 308)?;
+        assert_eq!(nread, 13);
+        assert_eq!(
+            memory.lock().unwrap().read_bytes(400, 13)?,
+            b"hello sandbox"
+        );
+
+        // the function which is not implemented returns the errno
+        let errno = call(&mut runtime, "sched_yield", vec![])?;
+        assert_eq!(errno, Errno::Nosys as i32);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_signature() -> Result<()> {
+        // the import with the wrong signature is rejected instead of panicking the host
+        for ty in [
+            "(param i32) (result i32)",
+            "(param i32 i32 i32 i32 i32 i64 i64 i32 i32)",
+        ] {
+            let err = wasi_runtime(WasiSnapshotPreview1::with_io(stdio()), &[("path_open", ty)])
+                .err()
+                .map(|e| e.to_string());
+            let expected = "incompatible import type: wasi_snapshot_preview1::path_open";
+            assert_eq!(err, Some(expected.into()), "{ty}");
+        }
+
+        // the function which is not implemented can't return the errno
+        let mut runtime = wasi_runtime(
+            WasiSnapshotPreview1::with_io(stdio()),
+            &[("sched_yield", "(result i64)")],
+        )?;
+        assert!(runtime.call("sched_yield".into(), vec![]).is_err());
+
+        // the host can invoke the function with any arguments directly
+        let wasi = WasiSnapshotPreview1::with_io(stdio());
+        let func = ExternalFuncInst {
+            module: "wasi_snapshot_preview1".into(),
+            field: "fd_close".into(),
+            func_type: signature("fd_close").unwrap(),
+            importer: Arc::new(WasiSnapshotPreview1::with_io(stdio())),
+        };
+        let store = Arc::new(Mutex::new(Store::default()));
+        assert!(wasi.invoke(store, func, vec![1i64.into()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fd_seek() -> Result<()> {
+        let dir = temp_dir("fd-seek")?;
+        std::fs::write(dir.join("hello.txt"), "hello sandbox")?;
+
+        let wasi = WasiSnapshotPreview1::with_io(stdio()).preopen_dir(&dir, "/sandbox")?;
+        let mut runtime = wasi_runtime(
+            wasi,
+            &[
+                (
+                    "path_open",
+                    "(param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)",
+                ),
+                ("fd_read", "(param i32 i32 i32 i32) (result i32)"),
+                ("fd_seek", "(param i32 i64 i32 i32) (result i32)"),
+                ("fd_tell", "(param i32 i32) (result i32)"),
+                ("fd_close", "(param i32) (result i32)"),
+                ("fd_renumber", "(param i32 i32) (result i32)"),
+            ],
+        )?;
+        let memory = memory(&mut runtime)?;
+
+        let rights = FileCaps::Read as u64 | FileCaps::Seek as u64 | FileCaps::Tell as u64;
+        assert_eq!(path_open(&mut runtime, &memory, "hello.txt", 0, rights)?, 0);
+        assert_eq!(load_i32(&memory, 200)?, 4);
+
+        let mut seek = |fd: i32, offset: i64, whence: i32| -> Result<i32> {
+            let args = vec![fd.into(), offset.into(), whence.into(), 300.into()];
+            call(&mut runtime, "fd_seek", args)
+        };
+        assert_eq!(seek(4, -3, WHENCE_END)?, 0);
+        assert_eq!(load_i64(&memory, 300)?, 10);
+        assert_eq!(seek(4, -4, WHENCE_CUR)?, 0);
+        assert_eq!(load_i64(&memory, 300)?, 6);
+        assert_eq!(seek(4, -1, WHENCE_SET)?, Errno::Inval as i32);
+        assert_eq!(seek(4, 0, 3)?, Errno::Inval as i32);
+        assert_eq!(seek(4, 2, WHENCE_SET)?, 0);
+
+        {
+            let mut memory = memory.lock().unwrap();
+            memory_write!(memory, 0, 4, 400, 500);
+            memory_write!(memory, 0, 4, 404, 3);
+        }
+        let args = vec![4.into(), 400.into(), 1.into(), 408.into()];
+        assert_eq!(call(&mut runtime, "fd_read", args)?, 0);
+        assert_eq!(memory.lock().unwrap().read_bytes(500, 3)?, b"llo");
+        assert_eq!(
+            call(&mut runtime, "fd_tell", vec![4.into(), 300.into()])?,
+            0
+        );
+        assert_eq!(load_i64(&memory, 300)?, 5);
+
+        // the file opened without the rights can't seek
+        let rights = FileCaps::Read as u64;
+        assert_eq!(path_open(&mut runtime, &memory, "hello.txt", 0, rights)?, 0);
+        assert_eq!(load_i32(&memory, 200)?, 5);
+        let args = vec![5.into(), 0i64.into(), WHENCE_SET.into(), 300.into()];
+        let errno = call(&mut runtime, "fd_seek", args)?;
+        assert_eq!(errno, Errno::Notcapable as i32);
+        let errno = call(&mut runtime, "fd_tell", vec![5.into(), 300.into()])?;
+        assert_eq!(errno, Errno::Notcapable as i32);
+
+        // fd 4 is moved to 5, and the closed fd is reused
+        assert_eq!(
+            call(&mut runtime, "fd_renumber", vec![4.into(), 5.into()])?,
+            0
+        );
+        assert_eq!(
+            call(&mut runtime, "fd_tell", vec![5.into(), 300.into()])?,
+            0
+        );
+        assert_eq!(load_i64(&memory, 300)?, 5);
+        let errno = call(&mut runtime, "fd_renumber", vec![4.into(), 5.into()])?;
+        assert_eq!(errno, Errno::Badf as i32);
+        let errno = call(&mut runtime, "fd_renumber", vec![5.into(), 6.into()])?;
+        assert_eq!(errno, Errno::Badf as i32);
+        assert_eq!(call(&mut runtime, "fd_close", vec![5.into()])?, 0);
+        assert_eq!(
+            call(&mut runtime, "fd_close", vec![5.into()])?,
+            Errno::Badf as i32
+        );
+        assert_eq!(path_open(&mut runtime, &memory, "hello.txt", 0, rights)?, 0);
+        assert_eq!(load_i32(&memory, 200)?, 4);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_filestat_and_readdir() -> Result<()> {
+        let dir = temp_dir("filestat")?;
+        std::fs::write(dir.join("a.txt"), "hello")?;
+        std::fs::create_dir(dir.join("b"))?;
+        std::os::unix::fs::symlink("a.txt", dir.join("c"))?;
+
+        let wasi = WasiSnapshotPreview1::with_io(stdio()).preopen_dir(&dir, "/sandbox")?;
+        let mut runtime = wasi_runtime(
+            wasi,
+            &[
+                (
+                    "path_open",
+                    "(param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)",
+                ),
+                ("fd_filestat_get", "(param i32 i32) (result i32)"),
+                ("fd_fdstat_get", "(param i32 i32) (result i32)"),
+                (
+                    "path_filestat_get",
+                    "(param i32 i32 i32 i32 i32) (result i32)",
+                ),
+                ("fd_filestat_set_size", "(param i32 i64) (result i32)"),
+                (
+                    "fd_filestat_set_times",
+                    "(param i32 i64 i64 i32) (result i32)",
+                ),
+                (
+                    "path_filestat_set_times",
+                    "(param i32 i32 i32 i32 i64 i64 i32) (result i32)",
+                ),
+                ("fd_readdir", "(param i32 i32 i32 i64 i32) (result i32)"),
+            ],
+        )?;
+        let memory = memory(&mut runtime)?;
+        let filetype = |memory: &MemoryInst| memory.lock().unwrap().read_bytes(316, 1).unwrap()[0];
+
+        assert_eq!(
+            call(&mut runtime, "fd_filestat_get", vec![3.into(), 300.into()])?,
+            0
+        );
+        assert_eq!(filetype(&memory), FileType::Directory as u8);
+
+        // the preopened directory has no fdflags
+        memory
+            .lock()
+            .unwrap()
+            .write_bytes(202, &[FdFlags::Append as u8])?;
+        assert_eq!(
+            call(&mut runtime, "fd_fdstat_get", vec![3.into(), 200.into()])?,
+            0
+        );
+        let stat = memory.lock().unwrap().read_bytes(200, 4)?.to_vec();
+        assert_eq!(stat[0], FileType::Directory as u8);
+        assert_eq!(stat[2..4], [FdFlags::Empty as u8, 0]);
+
+        let mut path_filestat_get = |path: &str, flags: i32| -> Result<i32> {
+            memory.lock().unwrap().write_bytes(100, path.as_bytes())?;
+            let args = vec![
+                3.into(),
+                flags.into(),
+                100.into(),
+                (path.len() as i32).into(),
+                300.into(),
+            ];
+            call(&mut runtime, "path_filestat_get", args)
+        };
+        assert_eq!(path_filestat_get("c", 0)?, 0);
+        assert_eq!(filetype(&memory), FileType::SymbolicLink as u8);
+        assert_eq!(path_filestat_get("c", LOOKUP_SYMLINK_FOLLOW)?, 0);
+        assert_eq!(filetype(&memory), FileType::RegularFile as u8);
+        assert_eq!(load_i64(&memory, 332)?, 5);
+        assert_eq!(path_filestat_get("../a.txt", 0)?, Errno::Notcapable as i32);
+        assert_eq!(path_filestat_get("d", 0)?, Errno::Noent as i32);
+
+        let rights = FileCaps::Read as u64
+            | FileCaps::FilestatGet as u64
+            | FileCaps::FilestatSetSize as u64
+            | FileCaps::FilestatSetTimes as u64;
+        assert_eq!(path_open(&mut runtime, &memory, "a.txt", 0, rights)?, 0);
+        let fd = load_i32(&memory, 200)?;
+        let args = vec![fd.into(), 2i64.into()];
+        assert_eq!(call(&mut runtime, "fd_filestat_set_size", args)?, 0);
+        assert_eq!(std::fs::read(dir.join("a.txt"))?, b"he");
+        let args = vec![fd.into(), 300.into()];
+        assert_eq!(call(&mut runtime, "fd_filestat_get", args)?, 0);
+        assert_eq!(load_i64(&memory, 332)?, 2);
+
+        let mtim = UNIX_EPOCH + Duration::from_secs(1000);
+        let args = vec![
+            fd.into(),
+            0i64.into(),
+            1_000_000_000_000i64.into(),
+            FSTFLAGS_MTIM.into(),
+        ];
+        assert_eq!(call(&mut runtime, "fd_filestat_set_times", args)?, 0);
+        assert_eq!(std::fs::metadata(dir.join("a.txt"))?.modified()?, mtim);
+        let args = vec![
+            fd.into(),
+            0i64.into(),
+            0i64.into(),
+            (FSTFLAGS_ATIM | FSTFLAGS_ATIM_NOW).into(),
+        ];
+        let errno = call(&mut runtime, "fd_filestat_set_times", args)?;
+        assert_eq!(errno, Errno::Inval as i32);
+
+        memory.lock().unwrap().write_bytes(100, b"b")?;
+        let args = vec![
+            3.into(),
+            0.into(),
+            100.into(),
+            1.into(),
+            0i64.into(),
+            1_000_000_000_000i64.into(),
+            FSTFLAGS_MTIM.into(),
+        ];
+        assert_eq!(call(&mut runtime, "path_filestat_set_times", args)?, 0);
+        assert_eq!(std::fs::metadata(dir.join("b"))?.modified()?, mtim);
+
+        // the entries are resumed from the cookie
+        let mut readdir = |fd: i32, cookie: i64, len: i32| -> Result<(i32, Vec<String>)> {
+            let args = vec![
+                fd.into(),
+                1000.into(),
+                len.into(),
+                cookie.into(),
+                900.into(),
+            ];
+            let errno = call(&mut runtime, "fd_readdir", args)?;
+            let used = load_i32(&memory, 900)? as usize;
+            let memory = memory.lock().unwrap();
+            let buf = memory.read_bytes(1000, used)?;
+            let mut names = vec![];
+            let mut i = 0;
+            while i + Dirent::HEADER_SIZE <= used {
+                let len = u32::from_le_bytes(buf[i + 16..i + 20].try_into()?) as usize;
+                let end = (i + Dirent::HEADER_SIZE + len).min(used);
+                names.push(String::from_utf8(
+                    buf[i + Dirent::HEADER_SIZE..end].to_vec(),
+                )?);
+                i += Dirent::HEADER_SIZE + len;
+            }
+            Ok((errno, names))
+        };
+        let (errno, names) = readdir(3, 0, 4096)?;
+        assert_eq!(errno, 0);
+        assert_eq!(names, vec![".", "..", "a.txt", "b", "c"]);
+        assert_eq!(readdir(3, 3, 4096)?.1, vec!["b", "c"]);
+        assert_eq!(readdir(3, 5, 4096)?.1, Vec::<String>::new());
+        // 25 and 26 bytes for "." and "..", and the header and 1 byte of "a.txt"
+        assert_eq!(readdir(3, 0, 76)?.1, vec![".", "..", "a"]);
+        assert_eq!(load_i32(&memory, 900)?, 76);
+        assert_eq!(readdir(fd, 0, 4096)?.0, Errno::Notcapable as i32);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_path_operations() -> Result<()> {
+        let dir = temp_dir("path-ops")?;
+        let readonly = temp_dir("path-ops-readonly")?;
+        std::fs::write(dir.join("file"), "data")?;
+
+        let wasi = WasiSnapshotPreview1::with_io(stdio())
+            .preopen_dir(&dir, "/sandbox")?
+            .preopen_dir_with_caps(&readonly, "/readonly", &[FileCaps::Open])?;
+        let mut runtime = wasi_runtime(
+            wasi,
+            &[
+                ("path_create_directory", "(param i32 i32 i32) (result i32)"),
+                ("path_remove_directory", "(param i32 i32 i32) (result i32)"),
+                ("path_unlink_file", "(param i32 i32 i32) (result i32)"),
+                (
+                    "path_rename",
+                    "(param i32 i32 i32 i32 i32 i32) (result i32)",
+                ),
+                ("path_symlink", "(param i32 i32 i32 i32 i32) (result i32)"),
+                (
+                    "path_readlink",
+                    "(param i32 i32 i32 i32 i32 i32) (result i32)",
+                ),
+                (
+                    "path_link",
+                    "(param i32 i32 i32 i32 i32 i32 i32) (result i32)",
+                ),
+            ],
+        )?;
+        let memory = memory(&mut runtime)?;
+
+        // write the path at the address, and returns the pointer and the length
+        let path = |addr: i32, path: &str| -> Vec<Value> {
+            let mut memory = memory.lock().unwrap();
+            memory.write_bytes(addr as usize, path.as_bytes()).unwrap();
+            vec![addr.into(), (path.len() as i32).into()]
+        };
+        let at = |fd: i32, p: &str| [vec![fd.into()], path(100, p)].concat();
+
+        let mut run = |name: &str, args: Vec<Value>| call(&mut runtime, name, args).unwrap();
+        assert_eq!(run("path_create_directory", at(3, "sub")), 0);
+        assert!(dir.join("sub").is_dir());
+        let errno = run("path_create_directory", at(3, "sub"));
+        assert_eq!(errno, Errno::Exist as i32);
+        let errno = run("path_create_directory", at(3, "../sub"));
+        assert_eq!(errno, Errno::Notcapable as i32);
+
+        let args = [at(3, "file"), vec![3.into()], path(150, "sub/moved")].concat();
+        assert_eq!(run("path_rename", args), 0);
+        assert_eq!(std::fs::read(dir.join("sub/moved"))?, b"data");
+
+        let args = [
+            vec![3.into(), 0.into()],
+            path(100, "sub/moved"),
+            vec![3.into()],
+            path(150, "hard"),
+        ]
+        .concat();
+        assert_eq!(run("path_link", args), 0);
+        assert_eq!(std::fs::read(dir.join("hard"))?, b"data");
+
+        let args = [path(100, "sub/moved"), vec![3.into()], path(150, "link")].concat();
+        assert_eq!(run("path_symlink", args), 0);
+        for (len, target) in [(100, "sub/moved"), (3, "sub")] {
+            let args = [at(3, "link"), vec![200.into(), len.into(), 300.into()]].concat();
+            assert_eq!(run("path_readlink", args), 0);
+            assert_eq!(load_i32(&memory, 300)? as usize, target.len());
+            let bytes = memory
+                .lock()
+                .unwrap()
+                .read_bytes(200, target.len())?
+                .to_vec();
+            assert_eq!(bytes, target.as_bytes());
+        }
+        for target in ["../outside", "/etc"] {
+            let args = [path(100, target), vec![3.into()], path(150, "bad")].concat();
+            assert_eq!(run("path_symlink", args), Errno::Notcapable as i32);
+        }
+
+        // the symlink itself is removed
+        assert_eq!(run("path_unlink_file", at(3, "link")), 0);
+        assert!(dir.join("sub/moved").exists());
+        assert_eq!(run("path_unlink_file", at(3, "sub")), Errno::Isdir as i32);
+        let errno = run("path_remove_directory", at(3, "sub"));
+        assert_eq!(errno, Errno::Notempty as i32);
+        assert_eq!(run("path_unlink_file", at(3, "sub/moved")), 0);
+        assert_eq!(run("path_remove_directory", at(3, "sub")), 0);
+        assert!(!dir.join("sub").exists());
+        let errno = run("path_remove_directory", at(3, "hard/.."));
+        assert_eq!(errno, Errno::Acces as i32);
+
+        // the symlink can't be moved to where it points outside
+        assert_eq!(run("path_create_directory", at(3, "a")), 0);
+        assert_eq!(run("path_create_directory", at(3, "a/b")), 0);
+        let args = [path(100, "../.."), vec![3.into()], path(150, "a/b/up")].concat();
+        assert_eq!(run("path_symlink", args), 0);
+        for (from, to) in [("a/b/up", "up"), ("a/b", "b")] {
+            let args = [at(3, from), vec![3.into()], path(150, to)].concat();
+            assert_eq!(run("path_rename", args), Errno::Notcapable as i32, "{from}");
+        }
+        let args = [
+            vec![3.into(), 0.into()],
+            path(100, "a/b/up"),
+            vec![3.into()],
+            path(150, "up"),
+        ]
+        .concat();
+        assert_eq!(run("path_link", args), Errno::Notcapable as i32);
+        assert!(!dir.join("up").exists() && !dir.join("b").exists());
+        let args = [at(3, "a/b/up"), vec![3.into()], path(150, "a/b/up2")].concat();
+        assert_eq!(run("path_rename", args), 0);
+
+        // the directory without the caps can't be changed
+        let errno = run("path_create_directory", at(4, "sub"));
+        assert_eq!(errno, Errno::Notcapable as i32);
+        let args = [at(3, "hard"), vec![4.into()], path(150, "hard")].concat();
+        assert_eq!(run("path_rename", args), Errno::Notcapable as i32);
+        assert!(dir.join("hard").exists());
+
+        std::fs::remove_dir_all(&dir)?;
+        std::fs::remove_dir_all(&readonly)?;
+        Ok(())
+    }
+
+    // clock which returns the fixed time for each clock id
+    struct FakeClock;
+
+    impl WasiClock for FakeClock {
+        fn time(&self, id: ClockId) -> Result<u64> {
+            Ok(1_000_000_000_000 + id as u64)
+        }
+
+        fn resolution(&self, id: ClockId) -> Result<u64> {
+            Ok(1000 * (id as u64 + 1))
+        }
+    }
+
+    #[test]
+    fn test_clock() -> Result<()> {
+        let dir = temp_dir("clock")?;
+        std::fs::write(dir.join("file"), "")?;
+
+        let wasi = WasiSnapshotPreview1::with_io(stdio())
+            .preopen_dir(&dir, "/sandbox")?
+            .with_clock(FakeClock);
+        let mut runtime = wasi_runtime(
+            wasi,
+            &[
+                ("clock_time_get", "(param i32 i64 i32) (result i32)"),
+                ("clock_res_get", "(param i32 i32) (result i32)"),
+                (
+                    "path_filestat_set_times",
+                    "(param i32 i32 i32 i32 i64 i64 i32) (result i32)",
+                ),
+            ],
+        )?;
+        let memory = memory(&mut runtime)?;
+
+        for id in 0..4 {
+            let args = vec![id.into(), 1i64.into(), 8.into()];
+            assert_eq!(call(&mut runtime, "clock_time_get", args)?, 0);
+            assert_eq!(load_i64(&memory, 8)?, 1_000_000_000_000 + id as i64);
+            let args = vec![id.into(), 8.into()];
+            assert_eq!(call(&mut runtime, "clock_res_get", args)?, 0);
+            assert_eq!(load_i64(&memory, 8)?, 1000 * (id as i64 + 1));
+        }
+        let args = vec![4.into(), 1i64.into(), 8.into()];
+        let errno = call(&mut runtime, "clock_time_get", args)?;
+        assert_eq!(errno, Errno::Inval as i32);
+        let errno = call(&mut runtime, "clock_res_get", vec![4.into(), 8.into()])?;
+        assert_eq!(errno, Errno::Inval as i32);
+
+        // the current time to set is also taken from the clock
+        memory.lock().unwrap().write_bytes(100, b"file")?;
+        let args = vec![
+            3.into(),
+            0.into(),
+            100.into(),
+            4.into(),
+            0i64.into(),
+            0i64.into(),
+            FSTFLAGS_MTIM_NOW.into(),
+        ];
+        assert_eq!(call(&mut runtime, "path_filestat_set_times", args)?, 0);
+        let mtim = std::fs::metadata(dir.join("file"))?.modified()?;
+        assert_eq!(mtim, UNIX_EPOCH + Duration::from_secs(1000));
+
+        // the realtime of the host clock is since the unix epoch
+        let now = SystemClock::default().time(ClockId::Realtime)?;
+        let expected = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+        assert!(expected.abs_diff(now) < 60_000_000_000);
+        // the cpu time of the host advances while the guest is running
+        let clock = SystemClock::default();
+        for id in [ClockId::ProcessCputime, ClockId::ThreadCputime] {
+            let start = clock.time(id)?;
+            while clock.time(id)? == start {}
+            assert!(clock.resolution(id)? > 0);
+        }
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
 }
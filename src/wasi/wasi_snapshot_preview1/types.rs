@@ -1 +1,70 @@
 pub type ExitCode = u32;
+
+// error code returned to the guest instead of trapping
+// https://github.com/WebAssembly/WASI/blob/main/legacy/preview1/docs.md#errno
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u16)]
+pub enum Errno {
+    Success = 0,
+    Acces = 2,
+    Badf = 8,
+    Exist = 20,
+    Fault = 21,
+    Ilseq = 25,
+    Inval = 28,
+    Io = 29,
+    Isdir = 31,
+    Loop = 32,
+    Nametoolong = 37,
+    Noent = 44,
+    Nosys = 52,
+    Notdir = 54,
+    Notempty = 55,
+    Notsup = 58,
+    Perm = 63,
+    Spipe = 70,
+    Xdev = 75,
+    Notcapable = 76,
+}
+
+impl std::fmt::Display for Errno {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "wasi errno: {:?}", self)
+    }
+}
+
+impl std::error::Error for Errno {}
+
+impl From<&std::io::Error> for Errno {
+    fn from(e: &std::io::Error) -> Self {
+        use std::io::ErrorKind;
+        match e.kind() {
+            ErrorKind::NotFound => Self::Noent,
+            ErrorKind::PermissionDenied => Self::Acces,
+            ErrorKind::AlreadyExists => Self::Exist,
+            ErrorKind::InvalidInput => Self::Inval,
+            ErrorKind::NotADirectory => Self::Notdir,
+            ErrorKind::IsADirectory => Self::Isdir,
+            ErrorKind::DirectoryNotEmpty => Self::Notempty,
+            ErrorKind::CrossesDevices => Self::Xdev,
+            ErrorKind::InvalidFilename => Self::Nametoolong,
+            ErrorKind::Unsupported => Self::Notsup,
+            ErrorKind::NotSeekable => Self::Spipe,
+            _ => Self::Io,
+        }
+    }
+}
+
+impl Errno {
+    // the errors of the file system become the errno,
+    // and the others like the memory access out of bounds are the trap
+    pub fn from_error(e: anyhow::Error) -> anyhow::Result<Self> {
+        if let Some(errno) = e.downcast_ref::<Errno>() {
+            return Ok(*errno);
+        }
+        if let Some(e) = e.downcast_ref::<std::io::Error>() {
+            return Ok(e.into());
+        }
+        Err(e)
+    }
+}
@@ -1,6 +1,13 @@
-use super::file::{FdFlags, File, FileType, ReadWrite};
-use anyhow::Result;
-use std::io::Cursor;
+use super::{
+    file::{FdFlags, File, FileType, Filestat, ReadWrite},
+    types::Errno,
+    wasi_dir::Dirent,
+};
+use anyhow::{bail, Result};
+use std::{
+    io::{Cursor, SeekFrom},
+    time::SystemTime,
+};
 
 pub struct VirtualFile(Box<dyn ReadWrite>);
 
@@ -15,7 +22,11 @@ impl File for VirtualFile {
     }
 
     fn seek(&mut self, pos: u64) -> Result<u64> {
-        Ok(self.0.seek(std::io::SeekFrom::Start(pos))?)
+        self.seek_from(SeekFrom::Start(pos))
+    }
+
+    fn seek_from(&mut self, pos: SeekFrom) -> Result<u64> {
+        Ok(self.0.seek(pos)?)
     }
 
     fn read_string(&mut self) -> Result<String> {
@@ -31,6 +42,35 @@ impl File for VirtualFile {
     fn fdflags(&self) -> Result<super::file::FdFlags> {
         Ok(FdFlags::Append)
     }
+
+    // only the size is known for the file in memory
+    fn filestat(&mut self) -> Result<Filestat> {
+        let pos = self.0.stream_position()?;
+        let size = self.0.seek(SeekFrom::End(0))?;
+        self.0.seek(SeekFrom::Start(pos))?;
+        Ok(Filestat {
+            dev: 0,
+            ino: 0,
+            filetype: FileType::RegularFile,
+            nlink: 1,
+            size,
+            atim: 0,
+            mtim: 0,
+            ctim: 0,
+        })
+    }
+
+    fn set_size(&mut self, _size: u64) -> Result<()> {
+        bail!(Errno::Notsup)
+    }
+
+    fn set_times(&mut self, _atim: Option<SystemTime>, _mtim: Option<SystemTime>) -> Result<()> {
+        bail!(Errno::Notsup)
+    }
+
+    fn readdir(&self) -> Result<Vec<Dirent>> {
+        bail!(Errno::Notdir)
+    }
 }
 
 impl Default for VirtualFile {
@@ -0,0 +1,124 @@
+use super::{
+    file::{FdFlags, File, FileType, Filestat},
+    types::Errno,
+    wasi_file::file_times,
+};
+use anyhow::{bail, Result};
+use std::{
+    io::SeekFrom,
+    os::unix::fs::{DirEntryExt, MetadataExt},
+    path::PathBuf,
+    time::SystemTime,
+};
+
+// entry of the directory read by fd_readdir
+// https://github.com/WebAssembly/WASI/blob/main/legacy/preview1/docs.md#dirent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dirent {
+    pub next: u64, // cookie of the next entry
+    pub ino: u64,
+    pub name: String,
+    pub filetype: FileType,
+}
+
+impl Dirent {
+    pub const HEADER_SIZE: usize = 24;
+
+    // the header followed by the name without the null terminator
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![0; Self::HEADER_SIZE];
+        buf[0..8].copy_from_slice(&self.next.to_le_bytes());
+        buf[8..16].copy_from_slice(&self.ino.to_le_bytes());
+        buf[16..20].copy_from_slice(&(self.name.len() as u32).to_le_bytes());
+        buf[20] = self.filetype as u8;
+        buf.extend_from_slice(self.name.as_bytes());
+        buf
+    }
+}
+
+// directory on the host, the path is used to list the entries
+pub struct WasiDir {
+    file: std::fs::File,
+    path: PathBuf,
+}
+
+impl WasiDir {
+    pub fn new(file: std::fs::File, path: PathBuf) -> Self {
+        Self { file, path }
+    }
+}
+
+impl File for WasiDir {
+    fn write(&mut self, _data: &[u8]) -> Result<usize> {
+        bail!(Errno::Isdir)
+    }
+
+    fn read(&mut self, _data: &mut [u8]) -> Result<usize> {
+        bail!(Errno::Isdir)
+    }
+
+    fn seek(&mut self, _pos: u64) -> Result<u64> {
+        bail!(Errno::Isdir)
+    }
+
+    fn seek_from(&mut self, _pos: SeekFrom) -> Result<u64> {
+        bail!(Errno::Isdir)
+    }
+
+    fn read_string(&mut self) -> Result<String> {
+        bail!(Errno::Isdir)
+    }
+
+    fn filetype(&self) -> Result<FileType> {
+        Ok(FileType::Directory)
+    }
+
+    fn fdflags(&self) -> Result<FdFlags> {
+        Ok(FdFlags::Empty)
+    }
+
+    fn filestat(&mut self) -> Result<Filestat> {
+        Ok((&self.file.metadata()?).into())
+    }
+
+    fn set_size(&mut self, _size: u64) -> Result<()> {
+        bail!(Errno::Isdir)
+    }
+
+    fn set_times(&mut self, atim: Option<SystemTime>, mtim: Option<SystemTime>) -> Result<()> {
+        self.file.set_times(file_times(atim, mtim))?;
+        Ok(())
+    }
+
+    // the entries are sorted by the name, so the cookie is the index of the entry
+    fn readdir(&self) -> Result<Vec<Dirent>> {
+        let mut entries = vec![
+            (
+                ".".to_string(),
+                self.file.metadata()?.ino(),
+                FileType::Directory,
+            ),
+            ("..".to_string(), 0, FileType::Directory),
+        ];
+        let mut children = vec![];
+        for entry in std::fs::read_dir(&self.path)? {
+            let entry = entry?;
+            let name = entry.file_name().into_string().map_err(|_| Errno::Ilseq)?;
+            children.push((name, entry.ino(), entry.file_type()?.into()));
+        }
+        children.sort_by(|a, b| a.0.cmp(&b.0));
+        entries.extend(children);
+
+        let dirents = entries
+            .into_iter()
+            .enumerate()
+            .map(|(i, (name, ino, filetype))| Dirent {
+                next: i as u64 + 1,
+                ino,
+                name,
+                filetype,
+            })
+            .collect();
+        Ok(dirents)
+    }
+}
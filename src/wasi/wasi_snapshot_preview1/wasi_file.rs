@@ -1,6 +1,15 @@
-use super::file::{FdFlags, File, FileType};
-use anyhow::Result;
-use std::{io::prelude::*, os::fd::FromRawFd};
+use super::{
+    file::{FdFlags, File, FileType, Filestat},
+    types::Errno,
+    wasi_dir::Dirent,
+};
+use anyhow::{bail, Result};
+use std::{
+    fs::FileTimes,
+    io::{prelude::*, SeekFrom},
+    os::fd::FromRawFd,
+    time::SystemTime,
+};
 
 pub struct WasiFile(std::fs::File);
 
@@ -15,7 +24,11 @@ impl File for WasiFile {
     }
 
     fn seek(&mut self, pos: u64) -> Result<u64> {
-        Ok(self.0.seek(std::io::SeekFrom::Start(pos))?)
+        self.seek_from(SeekFrom::Start(pos))
+    }
+
+    fn seek_from(&mut self, pos: SeekFrom) -> Result<u64> {
+        Ok(self.0.seek(pos)?)
     }
 
     fn read_string(&mut self) -> Result<String> {
@@ -25,27 +38,49 @@ impl File for WasiFile {
     }
 
     fn filetype(&self) -> Result<FileType> {
-        // FIXME: this is not correct
-        let m = self.0.metadata()?;
-        let filetype = if m.is_file() {
-            FileType::RegularFile
-        } else if m.is_dir() {
-            FileType::Directory
-        } else if m.is_symlink() {
-            FileType::SymbolicLink
-        } else {
-            FileType::Unknown
-        };
-        Ok(filetype)
+        Ok(self.0.metadata()?.file_type().into())
     }
 
     fn fdflags(&self) -> Result<FdFlags> {
         // TODO: implement fdflags
         Ok(FdFlags::Append)
     }
+
+    fn filestat(&mut self) -> Result<Filestat> {
+        Ok((&self.0.metadata()?).into())
+    }
+
+    fn set_size(&mut self, size: u64) -> Result<()> {
+        self.0.set_len(size)?;
+        Ok(())
+    }
+
+    fn set_times(&mut self, atim: Option<SystemTime>, mtim: Option<SystemTime>) -> Result<()> {
+        self.0.set_times(file_times(atim, mtim))?;
+        Ok(())
+    }
+
+    fn readdir(&self) -> Result<Vec<Dirent>> {
+        bail!(Errno::Notdir)
+    }
+}
+
+pub(crate) fn file_times(atim: Option<SystemTime>, mtim: Option<SystemTime>) -> FileTimes {
+    let mut times = FileTimes::new();
+    if let Some(atim) = atim {
+        times = times.set_accessed(atim);
+    }
+    if let Some(mtim) = mtim {
+        times = times.set_modified(mtim);
+    }
+    times
 }
 
 impl WasiFile {
+    pub fn new(file: std::fs::File) -> Self {
+        Self(file)
+    }
+
     pub fn from_raw_fd(fd: u32) -> Self {
         let file = unsafe { std::fs::File::from_raw_fd(fd as i32) };
         Self(file)
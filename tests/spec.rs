@@ -1,190 +1,25 @@
-#[cfg(test)]
-mod importer {
-    use anyhow::{bail, Context as _, Result};
-    use chibiwasm::{
-        module::{ExternalFuncInst, FuncInst, GlobalInst, InternalMemoryInst, InternalTableInst},
-        ExternalVal, Importer, Runtime, Store, Value,
-    };
-    use std::{cell::RefCell, rc::Rc};
-
-    #[derive(Clone)]
-    pub struct Import((String, Rc<RefCell<Store>>));
-
-    impl Import {
-        pub fn new(name: String, store: Rc<RefCell<Store>>) -> Self {
-            Self((name, store))
-        }
-    }
-
-    impl Importer for Import {
-        fn name(&self) -> &str {
-            let (name, _) = &self.0;
-            name.as_str()
-        }
-
-        fn get(&self, name: &str) -> Result<Option<Rc<RefCell<Store>>>> {
-            if self.name() != name {
-                return Ok(None);
-            }
-            let (_, store) = &self.0;
-            Ok(Some(Rc::clone(store)))
-        }
-
-        fn invoke(
-            &self,
-            store: Rc<RefCell<Store>>,
-            func: ExternalFuncInst,
-            args: Vec<Value>,
-        ) -> Result<Option<Value>> {
-            let mut runtime = Runtime::instantiate(Rc::clone(&store))?;
-            runtime.call(func.field, args)
-        }
-
-        fn resolve_table(
-            &self,
-            name: &str,
-            field: &str,
-        ) -> Result<Option<Rc<RefCell<InternalTableInst>>>> {
-            let store = self.get(name)?;
-            match store {
-                Some(store) => {
-                    let store = store.borrow();
-
-                    let export_inst = store
-                        .module
-                        .exports
-                        .get(field)
-                        .context(format!("not found exported table '{field}' from {name}"))?;
-
-                    let external_val = &export_inst.desc;
-                    let ExternalVal::Table(idx) = external_val else {
-                        bail!("invalid export desc: {:?}", external_val);
-                    };
-
-                    let table = store
-                        .tables
-                        .get(*idx as usize)
-                        .with_context(|| format!("not found table {idx} in module: {name}"))?;
-
-                    Ok(Some(Rc::clone(table)))
-                }
-                None => {
-                    bail!("cannot resolve table. not found module: {name} in imports",);
-                }
-            }
-        }
-
-        fn resolve_global(&self, name: &str, field: &str) -> Result<Option<GlobalInst>> {
-            let store = self.get(name)?;
-            match store {
-                Some(store) => {
-                    let store = store.borrow();
-                    let export_inst = store
-                        .module
-                        .exports
-                        .get(field)
-                        .context(format!("not found exported global '{field}' from {name}"))?;
-                    let external_val = &export_inst.desc;
-
-                    let ExternalVal::Global(idx) = external_val else {
-                        bail!("invalid export desc: {:?}", external_val);
-                    };
-                    let global = store
-                        .globals
-                        .get(*idx as usize)
-                        .with_context(|| format!("not found global index '{idx}' from {name}"))?;
-
-                    Ok(Some(Rc::clone(global)))
-                }
-                None => {
-                    bail!("cannot resolve global. not found module: {name} in imports",);
-                }
-            }
-        }
-
-        fn resolve_func(&self, name: &str, field: &str) -> Result<Option<FuncInst>> {
-            let store = self.get(name)?;
-            match store {
-                Some(store) => {
-                    let store = store.borrow();
-
-                    let export_inst =
-                        store.module.exports.get(field).context(format!(
-                            "not found exported function '{field}' from {name}"
-                        ))?;
-                    let external_val = &export_inst.desc;
-
-                    let ExternalVal::Func(idx) = external_val else {
-                        bail!("invalid export desc: {:?}", external_val);
-                    };
-                    let func = store
-                        .funcs
-                        .get(*idx as usize)
-                        .with_context(|| format!("not found function by {name}"))?;
-
-                    Ok(Some(func.clone()))
-                }
-                None => {
-                    bail!("cannot resolve function. not found module: {name} in imports",);
-                }
-            }
-        }
-
-        fn resolve_memory(
-            &self,
-            name: &str,
-            field: &str,
-        ) -> Result<Option<Rc<RefCell<InternalMemoryInst>>>> {
-            let store = self.get(name)?;
-            match store {
-                Some(store) => {
-                    let store = store.borrow();
-
-                    let export_inst = store
-                        .module
-                        .exports
-                        .get(field)
-                        .context(format!("not found exported memory '{field}' from {name}"))?;
-                    let external_val = &export_inst.desc;
-
-                    let ExternalVal::Memory(idx) = external_val else {
-                        bail!("invalid export desc: {:?}", external_val);
-                    };
-                    let memory = store
-                        .memory
-                        .get(*idx as usize)
-                        .with_context(|| format!("not found memory from {name}"))?;
-
-                    Ok(Some(Rc::clone(memory)))
-                }
-                None => {
-                    bail!("cannot resolve memory. not found module: {name} in imports",);
-                }
-            }
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
-    use super::importer::Import;
     use anyhow::Result;
-    use chibiwasm::execution::{Exports, Importer, Runtime, Store, Value};
+    use chibiwasm::execution::{Exports, Linker, Module, Runtime, Store, Value};
     use log::debug;
     use paste::paste;
     use std::cell::RefCell;
     use std::collections::HashMap;
-    use std::io::{Cursor, Read};
+    use std::io::Read;
     use std::rc::Rc;
-    use std::sync::Once;
+    use std::sync::{Arc, Mutex, Once};
     use std::{fs, path::Path};
     use wabt::{script::*, Features};
     static INIT: Once = Once::new();
 
+    // all the modules are instantiated in the same store,
+    // and the registered instances are linked by the name
     #[derive(Default)]
     struct Spec {
+        store: Arc<Mutex<Store>>,
         modules: HashMap<Option<String>, Rc<RefCell<Runtime>>>,
-        imports: HashMap<String, Import>,
+        linker: Linker,
     }
 
     fn into_wasm_value(values: Vec<wabt::script::Value>) -> Vec<Value> {
@@ -200,7 +35,7 @@ mod tests {
             .collect()
     }
 
-    fn run_test(spec_file: &str) -> Result<()> {
+    fn run_test(dir: &str, spec_file: &str) -> Result<()> {
         INIT.call_once(|| {
             // enable logger
             let _ = pretty_env_logger::env_logger::builder()
@@ -208,8 +43,10 @@ mod tests {
                 .try_init();
         });
 
+        let spec = &mut Spec::default();
+
         // add module for testing module importing
-        let testspec = {
+        {
             let code = r#"
 (module
   (table (export "table") 10 funcref)
@@ -239,21 +76,12 @@ mod tests {
   )
 )
                 "#;
-            let wasm = wat::parse_str(code).unwrap();
-            let store = Store::from_bytes(wasm, None).unwrap();
-            Rc::new(RefCell::new(store))
-        };
-
-        let mut imports = HashMap::new();
-        let module_name = "spectest".to_string();
-        imports.insert(module_name.clone(), Import::new(module_name, testspec));
-
-        let spec = &mut Spec {
-            modules: HashMap::new(),
-            imports,
-        };
+            let module = Module::from_bytes(wat::parse_str(code)?)?;
+            let runtime = spec.linker.instantiate(&spec.store, &module)?;
+            spec.linker.instance("spectest", &runtime);
+        }
 
-        let mut file = fs::File::open(Path::new("./tests/testsuite").join(spec_file))?;
+        let mut file = fs::File::open(Path::new(dir).join(spec_file))?;
         let mut wast = String::new();
         file.read_to_string(&mut wast)?;
 
@@ -288,6 +116,10 @@ mod tests {
                             wabt::script::Value::F64(v)
                         }
                     }
+                    // NOTE: wabt script doesn't support reference values
+                    Value::FuncRef(_) | Value::ExternRef(_) => {
+                        panic!("unsupported result value: {result}")
+                    }
                 })
                 .collect();
 
@@ -320,11 +152,8 @@ mod tests {
             expected: Vec<wabt::script::Value>,
         ) -> Result<()> {
             let args = into_wasm_value(args);
-            let result = runtime.call(field, args)?;
-            match result {
-                Some(result) => assert_values(vec![result], expected),
-                None => Ok(()),
-            }
+            let results = runtime.call_multi(field, args)?;
+            assert_values(results, expected)
         }
 
         while let Some(command) = parser.next()? {
@@ -350,7 +179,7 @@ mod tests {
                         let exports = runtime.exports(field.clone())?;
 
                         let results = match exports {
-                            Exports::Global(global) => vec![global.borrow().value.clone()],
+                            Exports::Global(global) => vec![global.lock().unwrap().value.clone()],
                             _ => {
                                 todo!();
                             }
@@ -381,7 +210,8 @@ mod tests {
                 CommandKind::AssertReturnArithmeticNan { .. } => {
                     // TODO
                 }
-                CommandKind::AssertTrap { action, message } => match action {
+                CommandKind::AssertTrap { action, message }
+                | CommandKind::AssertExhaustion { action, message } => match action {
                     Action::Invoke {
                         field,
                         args,
@@ -413,36 +243,28 @@ mod tests {
                     }
                     Action::Get { .. } => todo!(),
                 },
-                CommandKind::AssertInvalid { .. } => {
-                    // TODO
-                }
-                CommandKind::AssertMalformed { .. } => {
-                    // TODO
+                CommandKind::AssertInvalid { module, message }
+                | CommandKind::AssertMalformed { module, message } => {
+                    let result = Module::from_bytes(module.into_vec());
+                    assert!(
+                        result.is_err(),
+                        "module must be rejected: {message}, line: {}",
+                        command.line
+                    );
                 }
                 CommandKind::AssertUninstantiable { .. } => {
                     // TODO
                 }
-                CommandKind::AssertExhaustion { .. } => {
-                    // TODO
-                }
                 CommandKind::AssertUnlinkable { .. } => {
                     // TODO
                 }
                 CommandKind::Register { name, as_name } => {
                     let runtime = spec.modules.get(&name).expect("not found mdoule").clone();
-                    let store = &runtime.borrow().store;
-                    spec.imports
-                        .insert(as_name.clone(), Import::new(as_name, Rc::clone(store)));
+                    spec.linker.instance(as_name, &runtime.borrow());
                 }
                 CommandKind::Module { module, name } => {
-                    let mut reader = Cursor::new(module.into_vec());
-
-                    let mut imports: Vec<Box<dyn Importer>> = vec![];
-                    for (_, import) in spec.imports.iter() {
-                        imports.push(Box::new(import.clone()));
-                    }
-
-                    let runtime = Runtime::from_reader(&mut reader, Some(imports))?;
+                    let module = Module::from_bytes(module.into_vec())?;
+                    let runtime = spec.linker.instantiate(&spec.store, &module)?;
                     let runtime = Rc::new(RefCell::new(runtime));
                     spec.modules.insert(name, runtime.clone());
                     spec.modules.insert(None, runtime);
@@ -454,11 +276,14 @@ mod tests {
 
     macro_rules! test {
         ($ty: ident) => {
+            test!($ty, "./tests/testsuite");
+        };
+        ($ty: ident, $dir: literal) => {
             paste! {
                 #[test]
                 fn [<test_ $ty>]() -> Result<()> {
                     let file = format!("{}.wast", stringify!($ty));
-                    run_test(&file)?;
+                    run_test($dir, &file)?;
                     Ok(())
                 }
             }
@@ -532,10 +357,13 @@ mod tests {
     test!(conversions);
     test!(traps);
     test!(linking);
+    test!(unreached_invalid);
+
+    // proposals which are not covered by the testsuite
+    test!(conversions_sat, "./tests/wast");
 
     // Skip invalid tests
     //test!(token);
-    //test!(unreached_invalid);
     //test!(utf8_custom_section_id);
     //test!(utf8_import_field);
     //test!(utf8_import_module);